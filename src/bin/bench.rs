@@ -0,0 +1,91 @@
+use clap::{value_t, App, Arg};
+use example_tskit_rust_simulations::diploid::*;
+use example_tskit_rust_simulations::logging::exit_with_error;
+use tskit::TableAccess;
+
+// A fixed workload for profiling, e.g. with perf or
+// cargo flamegraph.  Nothing is written to disk.
+fn main() {
+    let matches = App::new("bench")
+        .arg(
+            Arg::with_name("popsize")
+                .short("N")
+                .long("popsize")
+                .help("Diploid population size. Default = 1,000.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nsteps")
+                .short("n")
+                .long("nsteps")
+                .help("Number of birth steps to simulate. Default = 1,000.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("xovers")
+                .short("x")
+                .long("xovers")
+                .help("Mean number of crossovers per meiosis. Default = 1.0.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("genome_length")
+                .short("L")
+                .long("genome_length")
+                .help("Genome length (continuous units). Default = 1e6.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("simplification_interval")
+                .short("s")
+                .long("simplify")
+                .help("Number of birth steps between simplifications. Default = 100.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("psurvival")
+                .short("P")
+                .long("psurvival")
+                .help("Survival probability. Default = 0.0.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("buffer_edges")
+                .short("b")
+                .long("buffer_edges")
+                .help("Buffer new edges between simplifications.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .short("S")
+                .long("seed")
+                .help("Random number seed. Default = 0.")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let mut params = SimParams {
+        xovers: 1.0,
+        ..SimParams::default()
+    };
+    params.popsize = value_t!(matches.value_of("popsize"), u32).unwrap_or(params.popsize);
+    params.nsteps = value_t!(matches.value_of("nsteps"), u64).unwrap_or(params.nsteps);
+    params.xovers = value_t!(matches.value_of("xovers"), f64).unwrap_or(params.xovers);
+    params.genome_length =
+        value_t!(matches.value_of("genome_length"), f64).unwrap_or(params.genome_length);
+    params.simplification_interval = value_t!(matches.value_of("simplification_interval"), u32)
+        .unwrap_or(params.simplification_interval);
+    params.psurvival = value_t!(matches.value_of("psurvival"), f64).unwrap_or(params.psurvival);
+    params.buffer_edges = matches.is_present("buffer_edges");
+    let seed = value_t!(matches.value_of("seed"), u64).unwrap_or(0);
+
+    let start = std::time::Instant::now();
+    let mut sim = DiploidSimulation::new(params, seed).unwrap_or_else(|e| exit_with_error(e));
+    let tables = sim.run().unwrap_or_else(|e| exit_with_error(e));
+    let elapsed = start.elapsed();
+
+    println!("seconds: {:.3}", elapsed.as_secs_f64());
+    println!("nodes: {}", tables.nodes().num_rows());
+    println!("edges: {}", tables.edges().num_rows());
+}
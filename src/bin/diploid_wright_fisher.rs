@@ -0,0 +1,440 @@
+use clap::{value_t, App, Arg};
+use example_tskit_rust_simulations::config::Config;
+use example_tskit_rust_simulations::diploid::*;
+use example_tskit_rust_simulations::error::SimulationError;
+use example_tskit_rust_simulations::estimate::estimate_memory;
+use example_tskit_rust_simulations::logging::{exit_with_error, init_logging};
+use example_tskit_rust_simulations::metadata::{set_simulation_metadata, SimulationMetadata};
+use example_tskit_rust_simulations::output::{gzip_file, write_seed, write_spectrum, write_tables};
+use example_tskit_rust_simulations::provenance::add_provenance;
+use example_tskit_rust_simulations::rng::entropy_seed;
+use example_tskit_rust_simulations::stats::{
+    allele_frequency_spectrum, expected_tmrca, mean_tmrca,
+};
+use example_tskit_rust_simulations::time::{rescale_times, TimeUnits};
+use tskit::TableAccess;
+
+#[derive(serde::Serialize)]
+struct ProgramOptions {
+    params: SimParams,
+    treefile: String,
+    seed: Option<u64>,
+    time_units: TimeUnits,
+    check: bool,
+    compress: bool,
+    strict: bool,
+    rng_substreams: bool,
+    keep_unary: bool,
+    canonical_sort: bool,
+    filter_populations: bool,
+    filter_individuals: bool,
+    seed_log: bool,
+    dry_run: bool,
+    validate_coalescent: bool,
+    sfs: Option<String>,
+    fold_sfs: bool,
+    #[serde(skip)]
+    print_config: bool,
+}
+
+impl Default for ProgramOptions {
+    fn default() -> Self {
+        Self {
+            params: SimParams::default(),
+            treefile: String::from("treefile.trees"),
+            seed: None,
+            time_units: TimeUnits::Steps,
+            check: false,
+            compress: false,
+            strict: false,
+            rng_substreams: false,
+            keep_unary: false,
+            canonical_sort: false,
+            filter_populations: true,
+            filter_individuals: true,
+            seed_log: true,
+            dry_run: false,
+            validate_coalescent: false,
+            sfs: None,
+            fold_sfs: false,
+            print_config: false,
+        }
+    }
+}
+
+impl ProgramOptions {
+    fn new() -> Self {
+        let mut options = Self::default();
+
+        let matches = App::new("diploid_wright_fisher")
+            .arg(
+                Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose")
+                    .help("Log each simplification to stderr. Give twice to also log each generation.")
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")
+                    .help("Do not log anything except a fatal error.")
+                    .conflicts_with("verbose"),
+            )
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .help("Read the simulation parameters, seed, and tree file name from this TOML file. Other options override its values, except that flags without a value can only turn a setting on.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("popsize")
+                    .short("N")
+                    .long("popsize")
+                    .help("Diploid population size. Default = 1,000.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ngenerations")
+                    .short("n")
+                    .long("ngenerations")
+                    .help("Number of generations to simulate. Default = 1,000.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("xovers")
+                    .short("x")
+                    .long("xovers")
+                    .help("Mean number of crossovers per meiosis. The number of crossovers is Poisson-distributed with this value. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("genome_length")
+                    .short("L")
+                    .long("genome_length")
+                    .help("Genome length (continuous units).  Default = 1e6.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("discrete_sites")
+                    .long("discrete-sites")
+                    .help("Use a genome of this many sites at integer positions instead of --genome_length. Breakpoints are rounded down to integers, and each branch gets at most one neutral mutation per site.")
+                    .takes_value(true)
+                    .conflicts_with("genome_length"),
+            )
+            .arg(
+                Arg::with_name("simplification_interval")
+                    .short("s")
+                    .long("simplify")
+                    .help("Number of generations between simplifications. Default = 100.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("mutrate")
+                    .short("m")
+                    .long("mutrate")
+                    .help("Neutral mutation rate per unit genome length per generation. Mutations are overlaid onto the final tables. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("treefile")
+                    .short("t")
+                    .long("treefile")
+                    .help("Name of output file. The format is a tskit \"trees\" file. Default = \"treefile.trees\".")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("demes")
+                    .long("demes")
+                    .help("Number of demes. Diploids are assigned to demes round-robin, and each deme is a population in the output. Default = 1.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ploidy")
+                    .long("ploidy")
+                    .help("Number of nodes per individual, at least 2. Offspring get alternate gametes from their two parents, each recombining two random homologs. Selection needs ploidy 2. Default = 2.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("migration")
+                    .long("migration")
+                    .help("Probability that one parent of an offspring comes from another deme. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("selfing")
+                    .long("selfing")
+                    .help("Probability that an offspring is produced by selfing, with both gametes from one parent. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("reproduction")
+                    .long("reproduction")
+                    .help("How parents are chosen. \"standard\" picks them uniformly. \"sweepstakes\" lets one lucky parent per deme produce a Beta-distributed fraction of the births in each step. Default = standard.")
+                    .takes_value(true)
+                    .possible_values(&["standard", "sweepstakes"]),
+            )
+            .arg(
+                Arg::with_name("sweepstakes_alpha")
+                    .long("sweepstakes-alpha")
+                    .help("Sweepstakes concentration parameter, 0.0 < alpha < 2.0. The lucky parent's share is Beta(2 - alpha, alpha) distributed, so smaller values give larger families. Default = 1.5.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("node_metadata")
+                    .long("node-metadata")
+                    .help("Give each node JSON metadata recording its birth step, {\"birth_step\": s}, and register a schema so that tskit-python can decode it.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("check")
+                    .long("check")
+                    .help("Check the integrity of the tables before writing them, and exit with an error naming the failed check.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("compress")
+                    .long("compress")
+                    .help("Compress the tree file with gzip after writing it, to the tree file name plus \".gz\", and remove the uncompressed file. tskit cannot load the result until it is decompressed, for example with gunzip.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("strict")
+                    .long("strict")
+                    .help("Check after every step that each diploid has two distinct nodes that exist, and that no node is shared, exiting with an error if not. These checks always run before each simplification.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("rng_substreams")
+                    .long("rng-substreams")
+                    .help("Choose parents and do births, including crossovers, with separate random number streams derived from the seed, so that changing one does not shift the other. Output differs from the default for the same seed.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("keep_unary")
+                    .long("keep-unary")
+                    .help("Keep unary nodes when simplifying: ancestors of the final generation that are not coalescences, with their edges, so that each lineage records every ancestor it passes through. The output can be much larger.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("canonical_sort")
+                    .long("canonical-sort")
+                    .help("Sort the tables into tskit's canonical order before each simplification, which also orders mutations and individuals. Runs whose tables differ only in the order rows were added then write the same tables, which helps when comparing output.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("no_filter_populations")
+                    .long("no-filter-populations")
+                    .help("Keep populations that no node in the output refers to. By default, the last simplification removes them and renumbers the rest.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("no_filter_individuals")
+                    .long("no-filter-individuals")
+                    .help("Keep individuals that no node in the output refers to, such as the full pedigree recorded with record_individuals in a --config file. By default, the last simplification removes them and renumbers the rest.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("no_seed_log")
+                    .long("no-seed-log")
+                    .help("Do not write the seed to a .seed file next to the tree file before simulating.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("print_config")
+                    .long("print-config")
+                    .help("Print the options in effect, after applying defaults, any --config file, and the other options, to stderr as JSON before simulating.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("dry_run")
+                    .long("dry-run")
+                    .help("Print an estimate of the peak size of the node and edge tables, and their memory use, then exit without simulating.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("validate_coalescent")
+                    .long("validate-coalescent")
+                    .help("Print the mean time, along the genome, of the most recent common ancestor of the final generation, and its Wright-Fisher expectation, to stderr. The expectation assumes one deme, standard reproduction, and no selfing.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("sfs")
+                    .long("sfs")
+                    .help("Write the site frequency spectrum of the final generation to this file. Each line is a derived allele count and the number of sites with that count, separated by a tab. Monomorphic sites are counted in bin 0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("fold_sfs")
+                    .long("fold-sfs")
+                    .help("Fold the spectrum written by --sfs, counting each site by its minor allele.")
+                    .takes_value(false)
+                    .requires("sfs"),
+            )
+            .arg(
+                Arg::with_name("time_units")
+                    .long("time-units")
+                    .help("Units of node and mutation times in the output. \"steps\" are birth steps before the end of the run. \"generations\" scale steps by 1 - psurvival. \"coalescent\" further divides by 2N. Default = steps.")
+                    .takes_value(true)
+                    .possible_values(&["steps", "generations", "coalescent"]),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .short("S")
+                    .long("seed")
+                    .help("Random number seed. Default = a seed from the operating system, written to the .seed file and the provenance, and logged with -v.")
+                    .takes_value(true),
+            )
+            .get_matches();
+
+        init_logging(
+            matches.occurrences_of("verbose"),
+            matches.is_present("quiet"),
+        );
+
+        // Values from a config file are the defaults
+        // for the options below.
+        if let Some(path) = matches.value_of("config") {
+            let config = Config::read(path).unwrap_or_else(|e| exit_with_error(e));
+            options.params = config.params;
+            options.seed = config.seed.or(options.seed);
+            options.treefile = config.treefile.unwrap_or(options.treefile);
+        }
+
+        options.params.popsize =
+            value_t!(matches.value_of("popsize"), u32).unwrap_or(options.params.popsize);
+        options.params.nsteps =
+            value_t!(matches.value_of("ngenerations"), u64).unwrap_or(options.params.nsteps);
+        options.params.xovers =
+            value_t!(matches.value_of("xovers"), f64).unwrap_or(options.params.xovers);
+        options.params.genome_length = value_t!(matches.value_of("genome_length"), f64)
+            .unwrap_or(options.params.genome_length);
+        if let Ok(nsites) = value_t!(matches.value_of("discrete_sites"), u32) {
+            options.params.genome_length = nsites as f64;
+            options.params.discrete_genome = true;
+        }
+        options.params.simplification_interval =
+            value_t!(matches.value_of("simplification_interval"), u32)
+                .unwrap_or(options.params.simplification_interval);
+        options.params.mutrate =
+            value_t!(matches.value_of("mutrate"), f64).unwrap_or(options.params.mutrate);
+        options.params.demes =
+            value_t!(matches.value_of("demes"), u32).unwrap_or(options.params.demes);
+        options.params.ploidy =
+            value_t!(matches.value_of("ploidy"), u32).unwrap_or(options.params.ploidy);
+        options.params.migration =
+            value_t!(matches.value_of("migration"), f64).unwrap_or(options.params.migration);
+        options.params.selfing_rate =
+            value_t!(matches.value_of("selfing"), f64).unwrap_or(options.params.selfing_rate);
+        match matches.value_of("reproduction") {
+            Some("sweepstakes") => {
+                let alpha = value_t!(matches.value_of("sweepstakes_alpha"), f64).unwrap_or(1.5);
+                options.params.reproduction = Reproduction::Sweepstakes { alpha };
+            }
+            Some(_) => options.params.reproduction = Reproduction::Standard,
+            None => (),
+        }
+        options.params.node_metadata |= matches.is_present("node_metadata");
+        options.check = matches.is_present("check");
+        options.compress = matches.is_present("compress");
+        options.strict = matches.is_present("strict");
+        options.rng_substreams = matches.is_present("rng_substreams");
+        options.keep_unary = matches.is_present("keep_unary");
+        options.canonical_sort = matches.is_present("canonical_sort");
+        options.filter_populations = !matches.is_present("no_filter_populations");
+        options.filter_individuals = !matches.is_present("no_filter_individuals");
+        options.seed_log = !matches.is_present("no_seed_log");
+        options.dry_run = matches.is_present("dry_run");
+        options.validate_coalescent = matches.is_present("validate_coalescent");
+        options.sfs = matches.value_of("sfs").map(String::from);
+        options.fold_sfs = matches.is_present("fold_sfs");
+        options.print_config = matches.is_present("print_config");
+        options.seed = value_t!(matches.value_of("seed"), u64)
+            .ok()
+            .or(options.seed);
+        options.time_units =
+            value_t!(matches.value_of("time_units"), TimeUnits).unwrap_or(options.time_units);
+        options.treefile =
+            value_t!(matches.value_of("treefile"), String).unwrap_or(options.treefile);
+
+        // Non-overlapping generations: every parent dies.
+        options.params.psurvival = 0.0;
+
+        if let Err(e) = options.params.validate() {
+            exit_with_error(e);
+        }
+        options
+    }
+}
+
+fn wright_fisher(
+    options: &ProgramOptions,
+    seed: u64,
+) -> Result<tskit::TableCollection, SimulationError> {
+    let mut sim = DiploidSimulation::new(options.params, seed)?;
+    sim.set_strict(options.strict);
+    sim.set_substreams(options.rng_substreams);
+    sim.set_keep_unary(options.keep_unary);
+    sim.set_canonical_sort(options.canonical_sort);
+    sim.set_filter_populations(options.filter_populations);
+    sim.set_filter_individuals(options.filter_individuals);
+    sim.run()
+}
+
+fn run(options: &ProgramOptions) -> Result<(), SimulationError> {
+    if options.print_config {
+        eprintln!("{}", serde_json::to_string_pretty(options).unwrap());
+    }
+
+    if options.dry_run {
+        println!("{}", estimate_memory(&options.params, None));
+        return Ok(());
+    }
+
+    let seed = options.seed.unwrap_or_else(entropy_seed);
+    if options.seed_log {
+        write_seed(&options.treefile, seed)?;
+    }
+
+    let mut tables = wright_fisher(options, seed)?;
+    add_provenance(&mut tables, "diploid_wright_fisher", seed, options)?;
+    set_simulation_metadata(
+        &mut tables,
+        &SimulationMetadata::new(
+            "diploid_wright_fisher",
+            options.params.popsize,
+            options.params.nsteps,
+            seed,
+        ),
+    )?;
+
+    let scale = options.time_units.scale(&options.params);
+    rescale_times(&mut tables, scale)?;
+
+    if options.validate_coalescent {
+        eprintln!(
+            "mean TMRCA: {}, expected: {}",
+            mean_tmrca(&tables)?,
+            expected_tmrca(&options.params, options.params.num_alive_nodes()) * scale
+        );
+    }
+
+    if let Some(sfs) = &options.sfs {
+        let samples = tables.nodes().samples_as_vector();
+        let spectrum = allele_frequency_spectrum(&tables, &samples, !options.fold_sfs)?;
+        write_spectrum(sfs, &spectrum)?;
+    }
+
+    write_tables(&tables, &options.treefile, options.check)?;
+    if options.compress {
+        gzip_file(&options.treefile)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = ProgramOptions::new();
+    if let Err(e) = run(&options) {
+        exit_with_error(e);
+    }
+}
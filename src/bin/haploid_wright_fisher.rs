@@ -0,0 +1,259 @@
+use clap::{value_t, App, Arg};
+use example_tskit_rust_simulations::diploid::{overlay_mutations, MAX_NSTEPS};
+use example_tskit_rust_simulations::error::SimulationError;
+use example_tskit_rust_simulations::haploid::{founders, simplify_haploid, wright_fisher_step};
+use example_tskit_rust_simulations::logging::{exit_with_error, init_logging};
+use example_tskit_rust_simulations::metadata::{set_simulation_metadata, SimulationMetadata};
+use example_tskit_rust_simulations::output::{gzip_file, write_seed, write_tables};
+use example_tskit_rust_simulations::provenance::add_provenance;
+use example_tskit_rust_simulations::rng::{entropy_seed, SimRng};
+use rand::SeedableRng;
+use tskit::TableAccess;
+
+#[derive(serde::Serialize)]
+struct ProgramOptions {
+    popsize: u32,
+    ngenerations: u64,
+    genome_length: f64,
+    simplification_interval: u32,
+    mutrate: f64,
+    treefile: String,
+    seed: Option<u64>,
+    check: bool,
+    compress: bool,
+    keep_unary: bool,
+    seed_log: bool,
+}
+
+impl Default for ProgramOptions {
+    fn default() -> Self {
+        Self {
+            popsize: 1000,
+            ngenerations: 1000,
+            genome_length: 1e6,
+            simplification_interval: 100,
+            mutrate: 0.0,
+            treefile: String::from("treefile.trees"),
+            seed: None,
+            check: false,
+            compress: false,
+            keep_unary: false,
+            seed_log: true,
+        }
+    }
+}
+
+impl ProgramOptions {
+    fn new() -> Self {
+        let mut options = Self::default();
+
+        let matches = App::new("haploid_wright_fisher")
+            .arg(
+                Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose")
+                    .help("Log each simplification to stderr. Give twice to also log each generation.")
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")
+                    .help("Do not log anything except a fatal error.")
+                    .conflicts_with("verbose"),
+            )
+            .arg(
+                Arg::with_name("popsize")
+                    .short("N")
+                    .long("popsize")
+                    .help("Haploid population size. Default = 1,000.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ngenerations")
+                    .short("n")
+                    .long("ngenerations")
+                    .help("Number of generations to simulate. Default = 1,000.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("genome_length")
+                    .short("L")
+                    .long("genome_length")
+                    .help("Genome length (continuous units).  Default = 1e6.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("simplification_interval")
+                    .short("s")
+                    .long("simplify")
+                    .help("Number of generations between simplifications. Default = 100.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("mutrate")
+                    .short("m")
+                    .long("mutrate")
+                    .help("Neutral mutation rate per unit genome length per generation. Mutations are overlaid onto the final tables. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("treefile")
+                    .short("t")
+                    .long("treefile")
+                    .help("Name of output file. The format is a tskit \"trees\" file. Default = \"treefile.trees\".")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("check")
+                    .long("check")
+                    .help("Check the integrity of the tables before writing them, and exit with an error naming the failed check.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("compress")
+                    .long("compress")
+                    .help("Compress the tree file with gzip after writing it, to the tree file name plus \".gz\", and remove the uncompressed file. tskit cannot load the result until it is decompressed, for example with gunzip.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("keep_unary")
+                    .long("keep-unary")
+                    .help("Keep unary nodes when simplifying: ancestors of the final generation that are not coalescences, with their edges. The output can be much larger.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("no_seed_log")
+                    .long("no-seed-log")
+                    .help("Do not write the seed to a .seed file next to the tree file before simulating.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .short("S")
+                    .long("seed")
+                    .help("Random number seed. Default = a seed from the operating system, written to the .seed file and the provenance, and logged with -v.")
+                    .takes_value(true),
+            )
+            .get_matches();
+
+        init_logging(
+            matches.occurrences_of("verbose"),
+            matches.is_present("quiet"),
+        );
+
+        options.popsize = value_t!(matches.value_of("popsize"), u32).unwrap_or(options.popsize);
+        options.ngenerations =
+            value_t!(matches.value_of("ngenerations"), u64).unwrap_or(options.ngenerations);
+        options.genome_length =
+            value_t!(matches.value_of("genome_length"), f64).unwrap_or(options.genome_length);
+        options.simplification_interval =
+            value_t!(matches.value_of("simplification_interval"), u32)
+                .unwrap_or(options.simplification_interval);
+        options.mutrate = value_t!(matches.value_of("mutrate"), f64).unwrap_or(options.mutrate);
+        options.treefile =
+            value_t!(matches.value_of("treefile"), String).unwrap_or(options.treefile);
+        options.seed = value_t!(matches.value_of("seed"), u64).ok();
+        options.check = matches.is_present("check");
+        options.compress = matches.is_present("compress");
+        options.keep_unary = matches.is_present("keep_unary");
+        options.seed_log = !matches.is_present("no_seed_log");
+
+        if let Err(e) = options.validate() {
+            exit_with_error(e);
+        }
+        options
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.popsize == 0 {
+            return Err(String::from("popsize must be > 0"));
+        }
+        if self.ngenerations > MAX_NSTEPS {
+            return Err(format!("ngenerations must be <= 2^52 = {}", MAX_NSTEPS));
+        }
+        if !self.genome_length.is_finite() || self.genome_length <= 0.0 {
+            return Err(String::from("genome_length must be finite and > 0.0"));
+        }
+        if self.simplification_interval == 0 {
+            return Err(String::from("simplification_interval must be > 0"));
+        }
+        if !self.mutrate.is_finite() || self.mutrate < 0.0 {
+            return Err(String::from("mutrate must be finite and >= 0.0"));
+        }
+        Ok(())
+    }
+}
+
+// Each generation, every one of the popsize haploids
+// is replaced by the offspring of a uniformly chosen
+// parent, inheriting its whole genome via one edge.
+fn wright_fisher(
+    options: &ProgramOptions,
+    seed: u64,
+) -> Result<tskit::TableCollection, SimulationError> {
+    let mut rng = SimRng::seed_from_u64(seed);
+    let mut tables = tskit::TableCollection::new(options.genome_length)?;
+
+    let mut alive = founders(options.popsize, options.ngenerations as f64, &mut tables)?;
+
+    for generation in (0..options.ngenerations).rev() {
+        wright_fisher_step(&mut alive, generation as f64, &mut tables, &mut rng)?;
+
+        if generation.is_multiple_of(options.simplification_interval as u64) {
+            simplify_haploid(&mut alive, &mut tables, options.keep_unary)?;
+            log::info!(
+                "generation {}: simplified to {} nodes and {} edges",
+                generation,
+                tables.nodes().num_rows(),
+                tables.edges().num_rows()
+            );
+        }
+        log::debug!(
+            "generation {}: {} nodes, {} edges",
+            generation,
+            tables.nodes().num_rows(),
+            tables.edges().num_rows()
+        );
+    }
+
+    tables.build_index()?;
+
+    if options.mutrate > 0.0 {
+        overlay_mutations(&mut tables, options.mutrate, &mut rng)?;
+    }
+
+    Ok(tables)
+}
+
+fn run(options: &ProgramOptions) -> Result<(), SimulationError> {
+    let seed = options.seed.unwrap_or_else(entropy_seed);
+    if options.seed_log {
+        write_seed(&options.treefile, seed)?;
+    }
+
+    let mut tables = wright_fisher(options, seed)?;
+    add_provenance(&mut tables, "haploid_wright_fisher", seed, options)?;
+    set_simulation_metadata(
+        &mut tables,
+        &SimulationMetadata::new(
+            "haploid_wright_fisher",
+            options.popsize,
+            options.ngenerations,
+            seed,
+        ),
+    )?;
+
+    write_tables(&tables, &options.treefile, options.check)?;
+    if options.compress {
+        gzip_file(&options.treefile)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = ProgramOptions::new();
+    if let Err(e) = run(&options) {
+        exit_with_error(e);
+    }
+}
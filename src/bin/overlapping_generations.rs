@@ -1,12 +1,78 @@
-use clap::{value_t, App, Arg};
+use clap::{value_t, value_t_or_exit, values_t, App, Arg};
+use example_tskit_rust_simulations::config::Config;
+use example_tskit_rust_simulations::demography::Demography;
 use example_tskit_rust_simulations::diploid::*;
-use rand::rngs::StdRng;
-use rand::SeedableRng;
+use example_tskit_rust_simulations::error::SimulationError;
+use example_tskit_rust_simulations::estimate::estimate_memory;
+use example_tskit_rust_simulations::export::{
+    dump_tables_csv, trees_to_newick, write_edge_diffs, write_vcf,
+};
+use example_tskit_rust_simulations::logging::{exit_with_error, init_logging};
+use example_tskit_rust_simulations::metadata::{set_simulation_metadata, SimulationMetadata};
+use example_tskit_rust_simulations::mutmap::MutationMap;
+use example_tskit_rust_simulations::output::{
+    append_statistics, file_prefix, gzip_file, write_seed, write_spectrum, write_tables,
+};
+use example_tskit_rust_simulations::provenance::add_provenance;
+use example_tskit_rust_simulations::recmap::RecombinationMap;
+use example_tskit_rust_simulations::rng::{entropy_seed, subsample_rng};
+use example_tskit_rust_simulations::stats::{
+    allele_frequency_spectrum, expected_tmrca, is_fully_coalesced, mean_pairwise_diversity,
+    mean_tmrca, tajimas_d, Statistic,
+};
+use example_tskit_rust_simulations::survival::SurvivalSchedule;
+use example_tskit_rust_simulations::time::{rescale_times, TimeUnits};
+use tskit::TableAccess;
 
+#[derive(serde::Serialize)]
 struct ProgramOptions {
     params: SimParams,
     treefile: String,
-    seed: u64,
+    seed: Option<u64>,
+    time_units: TimeUnits,
+    check: bool,
+    compress: bool,
+    strict: bool,
+    rng_substreams: bool,
+    max_seconds: Option<f64>,
+    simplify_when_edges: Option<usize>,
+    table_size_log: Option<String>,
+    trajectory: Option<String>,
+    seed_log: bool,
+    dry_run: bool,
+    #[serde(skip)]
+    print_config: bool,
+    /// The tables of `start_from`, loaded once.
+    #[serde(skip)]
+    start_tables: Option<tskit::TableCollection>,
+    recmap: Option<String>,
+    mutmap: Option<String>,
+    demography: Option<String>,
+    survival_schedule: Option<String>,
+    growth_rate: Option<f64>,
+    checkpoint_interval: u32,
+    resume: Option<String>,
+    start_from: Option<String>,
+    progress: bool,
+    final_simplify: bool,
+    keep_unary: bool,
+    canonical_sort: bool,
+    no_index: bool,
+    filter_populations: bool,
+    filter_individuals: bool,
+    sample_size: Option<usize>,
+    vcf: Option<String>,
+    newick: Option<String>,
+    edge_diff_log: Option<String>,
+    csv: bool,
+    print_diversity: bool,
+    print_tajimas_d: bool,
+    validate_coalescent: bool,
+    require_coalescence: bool,
+    sfs: Option<String>,
+    fold_sfs: bool,
+    stats_only: Option<String>,
+    stats: Vec<Statistic>,
 }
 
 impl Default for ProgramOptions {
@@ -14,7 +80,48 @@ impl Default for ProgramOptions {
         Self {
             params: SimParams::default(),
             treefile: String::from("treefile.trees"),
-            seed: 0,
+            seed: None,
+            time_units: TimeUnits::Steps,
+            check: false,
+            compress: false,
+            strict: false,
+            rng_substreams: false,
+            max_seconds: None,
+            simplify_when_edges: None,
+            table_size_log: None,
+            trajectory: None,
+            seed_log: true,
+            dry_run: false,
+            print_config: false,
+            start_tables: None,
+            recmap: None,
+            mutmap: None,
+            demography: None,
+            survival_schedule: None,
+            growth_rate: None,
+            checkpoint_interval: 0,
+            resume: None,
+            start_from: None,
+            progress: false,
+            final_simplify: true,
+            keep_unary: false,
+            canonical_sort: false,
+            no_index: false,
+            filter_populations: true,
+            filter_individuals: true,
+            sample_size: None,
+            vcf: None,
+            newick: None,
+            edge_diff_log: None,
+            csv: false,
+            print_diversity: false,
+            print_tajimas_d: false,
+            validate_coalescent: false,
+            require_coalescence: false,
+            sfs: None,
+            fold_sfs: false,
+            stats_only: None,
+            stats: Statistic::ALL.to_vec(),
         }
     }
 }
@@ -35,6 +142,26 @@ impl ProgramOptions {
         let mut options = Self::default();
 
         let matches = App::new("overlapping_generations")
+            .arg(
+                Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose")
+                    .help("Log each simplification to stderr. Give twice to also log each step.")
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")
+                    .help("Do not log anything except a fatal error.")
+                    .conflicts_with("verbose"),
+            )
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .help("Read the simulation parameters, seed, and tree file name from this TOML file. Other options override its values, except that flags without a value can only turn a setting on.")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("popsize")
                     .short("N")
@@ -63,6 +190,13 @@ impl ProgramOptions {
                     .help("Genome length (continuous units).  Default = 1e6.")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("discrete_sites")
+                    .long("discrete-sites")
+                    .help("Use a genome of this many sites at integer positions instead of --genome_length. Breakpoints are rounded down to integers, and each branch gets at most one neutral mutation per site.")
+                    .takes_value(true)
+                    .conflicts_with("genome_length"),
+            )
             .arg(
                 Arg::with_name("simplification_interval")
                     .short("s")
@@ -70,6 +204,19 @@ impl ProgramOptions {
                     .help("Number of birth steps between simplifications. Default = 100.")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("simplify_when_edges")
+                    .long("simplify-when-edges")
+                    .help("Simplify at the end of any step that leaves more than this many edges, instead of every --simplify steps. The edge table then never holds more than this plus one step's edges.")
+                    .takes_value(true)
+                    .conflicts_with("simplification_interval"),
+            )
+            .arg(
+                Arg::with_name("table_size_log")
+                    .long("table-size-log")
+                    .help("Write the number of nodes and edges at the end of every step, after any simplification, to this CSV file, with the columns step,num_nodes,num_edges.")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("treefile")
                     .short("t")
@@ -77,11 +224,86 @@ impl ProgramOptions {
                     .help("Name of output file. The format is a tskit \"trees\" file. Default = \"treefile.trees\".")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("continuous_time")
+                    .long("continuous-time")
+                    .help("Give births real-valued times. The births of each step are spread over one unit of time, separated by exponential waiting times, so that each birth has its own time.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("shuffle_deaths")
+                    .long("shuffle-deaths")
+                    .help("Decide deaths and choose parents for the alive diploids in a random order each step, rather than in index order, so that effects of the order average out. This changes the output for a given seed.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("demes")
+                    .long("demes")
+                    .help("Number of demes. Diploids are assigned to demes round-robin, and each deme is a population in the output. Default = 1.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ploidy")
+                    .long("ploidy")
+                    .help("Number of nodes per individual, at least 2. Offspring get alternate gametes from their two parents, each recombining two random homologs. Selection needs ploidy 2. Default = 2.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("migration")
+                    .long("migration")
+                    .help("Probability that one parent of an offspring comes from another deme. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("selfing")
+                    .long("selfing")
+                    .help("Probability that an offspring is produced by selfing, with both gametes from one parent. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("reproduction")
+                    .long("reproduction")
+                    .help("How parents are chosen. \"standard\" picks them uniformly. \"sweepstakes\" lets one lucky parent per deme produce a Beta-distributed fraction of the births in each step. Default = standard.")
+                    .takes_value(true)
+                    .possible_values(&["standard", "sweepstakes"]),
+            )
+            .arg(
+                Arg::with_name("sweepstakes_alpha")
+                    .long("sweepstakes-alpha")
+                    .help("Sweepstakes concentration parameter, 0.0 < alpha < 2.0. The lucky parent's share is Beta(2 - alpha, alpha) distributed, so smaller values give larger families. Default = 1.5.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("selected_mutrate")
+                    .long("selected-mutrate")
+                    .help("Rate of selected mutations per unit genome length per gamete. Unlike --mutrate, these arise during the simulation and affect fitness. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("selection_coefficient")
+                    .long("selection-coefficient")
+                    .help("Fitness effect of each selected mutation. Fitness is 1 + s times the number of selected mutations carried, and parents are chosen in proportion to fitness. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("trajectory")
+                    .long("trajectory")
+                    .help("Follow the first selected mutation to arise, and write its frequency among the alive nodes at each step to this CSV file, with the columns step,position,frequency.")
+                    .takes_value(true)
+                    .requires("selected_mutrate"),
+            )
+            .arg(
+                Arg::with_name("time_units")
+                    .long("time-units")
+                    .help("Units of node and mutation times in the output. \"steps\" are birth steps before the end of the run. \"generations\" scale steps by 1 - psurvival. \"coalescent\" further divides by 2N. Default = steps.")
+                    .takes_value(true)
+                    .possible_values(&["steps", "generations", "coalescent"]),
+            )
             .arg(
                 Arg::with_name("seed")
                     .short("S")
                     .long("seed")
-                    .help("Random number seed. Default = 0.")
+                    .help("Random number seed. Default = a seed from the operating system, written to the .seed file and the provenance, and logged with -v.")
                     .takes_value(true),
             )
             .arg(
@@ -91,101 +313,787 @@ impl ProgramOptions {
                     .help("Survival probability. A value of 0.0 is the Wright-Fisher model of non-overlapping generations.  Values must b 0.0 <= p < 1.0.  Default = 0.0.")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("survival_schedule")
+                    .long("survival-schedule")
+                    .help("Age-dependent survival file with two tab-separated columns: an age, starting at 0 and going up by one, and the probability that a diploid of that age survives a step. A diploid's age is the number of steps it has survived. Diploids older than the last age always die. Cannot be combined with --psurvival.")
+                    .takes_value(true)
+                    .conflicts_with("psurvival"),
+            )
+            .arg(
+                Arg::with_name("mutrate")
+                    .short("m")
+                    .long("mutrate")
+                    .help("Neutral mutation rate per unit genome length per birth step. Mutations are overlaid onto the final tables. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("buffer_edges")
+                    .short("b")
+                    .long("buffer_edges")
+                    .help("Buffer new edges between simplifications so that the tables need not be sorted.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("record_individuals")
+                    .short("I")
+                    .long("record_individuals")
+                    .help("Record each diploid and its parents in the individual table.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("node_metadata")
+                    .long("node-metadata")
+                    .help("Give each node JSON metadata recording its birth step, {\"birth_step\": s}, and register a schema so that tskit-python can decode it.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("sample_every")
+                    .long("sample-every")
+                    .help("Keep the nodes born at every birth time that is a multiple of this as ancient samples in the output. Must be less than --nsteps. Default = 0, for no ancient samples.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("check")
+                    .long("check")
+                    .help("Check the integrity of the tables before writing them, and exit with an error naming the failed check.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("compress")
+                    .long("compress")
+                    .help("Compress the tree file with gzip after writing it, to the tree file name plus \".gz\", and remove the uncompressed file. tskit cannot load the result until it is decompressed, for example with gunzip.")
+                    .takes_value(false)
+                    .conflicts_with("stats_only"),
+            )
+            .arg(
+                Arg::with_name("strict")
+                    .long("strict")
+                    .help("Check after every step that each diploid has two distinct nodes that exist, and that no node is shared, exiting with an error if not. These checks always run before each simplification.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("rng_substreams")
+                    .long("rng-substreams")
+                    .help("Choose parents and do births, including crossovers, with separate random number streams derived from the seed, so that changing one does not shift the other. Output differs from the default for the same seed.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("max_seconds")
+                    .long("max-seconds")
+                    .help("Stop at the first simplification after this many seconds and write the tables so far. Node times still count steps before the planned end, and the provenance records the step reached.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("no_seed_log")
+                    .long("no-seed-log")
+                    .help("Do not write the seed to a .seed file next to the tree file before simulating.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("print_config")
+                    .long("print-config")
+                    .help("Print the options in effect, after applying defaults, any --config file, and the other options, to stderr as JSON before simulating.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("dry_run")
+                    .long("dry-run")
+                    .help("Print an estimate of the peak size of the node and edge tables, and their memory use, then exit without simulating.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("gamma_shape")
+                    .short("g")
+                    .long("gamma_shape")
+                    .help("If given, distances between crossovers are gamma-distributed with this shape, modelling interference. Otherwise, crossover positions are a Poisson process.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("fixed_xovers")
+                    .long("fixed-xovers")
+                    .help("Exact number of crossovers per meiosis, at uniform positions. Cannot be combined with --xovers or --gamma_shape.")
+                    .takes_value(true)
+                    .conflicts_with_all(&["xovers", "gamma_shape"]),
+            )
+            .arg(
+                Arg::with_name("gc_rate")
+                    .long("gc_rate")
+                    .help("Mean number of gene conversion tracts per meiosis. The number of tracts is Poisson-distributed with this value. Default = 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("gc_mean_tract")
+                    .long("gc_mean_tract")
+                    .help("Mean gene conversion tract length. Tract lengths are geometric. Must be >= 1.0 if --gc_rate > 0.0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("recmap")
+                    .long("recmap")
+                    .help("Recombination map file with two tab-separated columns: the start position of each segment and its rate of crossovers per unit length per meiosis. Cannot be combined with --xovers.")
+                    .takes_value(true)
+                    .conflicts_with("xovers"),
+            )
+            .arg(
+                Arg::with_name("mutmap")
+                    .long("mutmap")
+                    .help("Mutation map file with two tab-separated columns: the start position of each segment and its neutral mutation rate per unit length per birth step. Cannot be combined with --mutrate or --discrete-sites.")
+                    .takes_value(true)
+                    .conflicts_with("mutrate"),
+            )
+            .arg(
+                Arg::with_name("demography")
+                    .long("demography")
+                    .help("Population size changes file with two tab-separated columns: the birth step of each change, counted back from the end of the run like node times, and the new popsize. The population grows with extra births from random parents in each deme, or shrinks by dropping random diploids after that step's births. --popsize is the initial size.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("growth_rate")
+                    .long("growth-rate")
+                    .help("Grow the population exponentially from --popsize, to popsize * exp(r * k), rounded, after k steps. Negative rates shrink it. Cannot be combined with --demography.")
+                    .takes_value(true)
+                    .allow_hyphen_values(true)
+                    .conflicts_with("demography"),
+            )
+            .arg(
+                Arg::with_name("checkpoint_interval")
+                    .long("checkpoint-interval")
+                    .help("Every this many steps, write the tables and simulation state to <treefile>.checkpoint.trees and <treefile>.checkpoint.json. Resumed runs match uninterrupted runs. Default = 0 (no checkpoints).")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("resume")
+                    .long("resume")
+                    .help("Resume from the checkpoint with this prefix, for example treefile.checkpoint. The other parameters must match the checkpointed run.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("start_from")
+                    .long("start-from")
+                    .help("Continue from the sample nodes of this tree sequence, for example a burn-in. Each run of --ploidy consecutive sample nodes forms an individual. If --popsize is not given, it is the number of sample nodes divided by the ploidy.")
+                    .takes_value(true)
+                    .conflicts_with("resume"),
+            )
+            .arg(
+                Arg::with_name("progress")
+                    .long("progress")
+                    .help("Print the current step, percent complete, and estimated time remaining to stderr, at most once per second.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("no_final_simplify")
+                    .long("no-final-simplify")
+                    .help("Skip the simplification at the last step, keeping every node and edge from the last simplification interval. The output can be many times larger, up to the peak reported by --dry-run. Conflicts with --check, which expects simplified tables.")
+                    .takes_value(false)
+                    .conflicts_with("check"),
+            )
+            .arg(
+                Arg::with_name("no_filter_populations")
+                    .long("no-filter-populations")
+                    .help("Keep populations that no node in the output refers to. By default, the last simplification removes them and renumbers the rest.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("no_filter_individuals")
+                    .long("no-filter-individuals")
+                    .help("Keep individuals that no node in the output refers to, such as the full pedigree recorded by --record_individuals. By default, the last simplification removes them and renumbers the rest.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("keep_unary")
+                    .long("keep-unary")
+                    .help("Keep unary nodes when simplifying: ancestors of the final generation that are not coalescences, with their edges, so that each lineage records every ancestor it passes through. The output can be much larger.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("canonical_sort")
+                    .long("canonical-sort")
+                    .help("Sort the tables into tskit's canonical order before each simplification, which also orders mutations and individuals. Runs whose tables differ only in the order rows were added then write the same tables, which helps when comparing output.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("no_index")
+                    .long("no-index")
+                    .help("Write the tables without building the edge index, for pipelines that sort or modify them afterwards. Readers must index them before making trees. Cannot be combined with outputs that need trees.")
+                    .takes_value(false)
+                    .conflicts_with_all(&[
+                        "vcf",
+                        "newick",
+                        "edge_diff_log",
+                        "print_diversity",
+                        "print_tajimas_d",
+                        "validate_coalescent",
+                        "require_coalescence",
+                        "sfs",
+                        "stats_only",
+                    ]),
+            )
+            .arg(
+                Arg::with_name("sample_size")
+                    .long("sample-size")
+                    .help("Keep only this many of the final generation's sample nodes, chosen at random with a seed derived from --seed, by simplifying once more after the run. Ancient samples are kept. Must be at most ploidy times the final population size.")
+                    .takes_value(true)
+                    .conflicts_with("no_final_simplify"),
+            )
+            .arg(
+                Arg::with_name("vcf")
+                    .long("vcf")
+                    .help("Write the genotypes of the final generation to this VCF file, one phased column per individual. With --sample-size, each column holds the chosen nodes of an individual. Requires a mutation rate > 0.0 to contain any sites.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("newick")
+                    .long("newick")
+                    .help("Write each of the final trees, in Newick format, to this file. Each line starts with the tree's genomic interval.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("edge_diff_log")
+                    .long("edge-diff-log")
+                    .help("Write the edges removed and inserted between each final tree and the next to this CSV file, one row per edge, with the interval of the new tree.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("csv")
+                    .long("csv")
+                    .help("Also write the node and edge tables, with tskit's column names, to CSV files named after the tree file: prefix.nodes.csv and prefix.edges.csv.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("print_diversity")
+                    .long("print-diversity")
+                    .help("Print the mean pairwise diversity, per unit genome length, of the final generation to stderr.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("print_tajimas_d")
+                    .long("print-tajimas-d")
+                    .help("Print Tajima's D of the final generation to stderr. Exits with an error if no site is segregating.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("validate_coalescent")
+                    .long("validate-coalescent")
+                    .help("Print the mean time, along the genome, of the most recent common ancestor of the final generation, and its expectation under the neutral model, to stderr. The expectation assumes one deme, standard reproduction, and no selection or selfing.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("require_coalescence")
+                    .long("require-coalescence")
+                    .help("Exit with an error, after writing the output, if the samples do not have a single common ancestor at every position of the genome, which means that --nsteps was too short.")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("sfs")
+                    .long("sfs")
+                    .help("Write the site frequency spectrum of the final generation to this file. Each line is a derived allele count and the number of sites with that count, separated by a tab. Monomorphic sites are counted in bin 0.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("fold_sfs")
+                    .long("fold-sfs")
+                    .help("Fold the spectrum written by --sfs, counting each site by its minor allele.")
+                    .takes_value(false)
+                    .requires("sfs"),
+            )
+            .arg(
+                Arg::with_name("stats_only")
+                    .long("stats-only")
+                    .help("Do not write the tree file. Instead, append a row to this CSV file with the seed, the simulation parameters, and the statistics chosen by --stats for the final generation. A new file gets a header, and an existing one must have the same columns.")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("stats")
+                    .long("stats")
+                    .help("Comma-separated statistics for --stats-only. Default = diversity,segregating_sites,mean_tmrca.")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .multiple(true)
+                    .possible_values(&["diversity", "segregating_sites", "mean_tmrca"])
+                    .requires("stats_only"),
+            )
             .get_matches();
 
+        init_logging(
+            matches.occurrences_of("verbose"),
+            matches.is_present("quiet"),
+        );
+
+        // Values from a config file are the defaults
+        // for the options below.
+        if let Some(path) = matches.value_of("config") {
+            let config = Config::read(path).unwrap_or_else(|e| exit_with_error(e));
+            options.params = config.params;
+            options.seed = config.seed.or(options.seed);
+            options.treefile = config.treefile.unwrap_or(options.treefile);
+        }
+
         options.params.popsize =
             value_t!(matches.value_of("popsize"), u32).unwrap_or(options.params.popsize);
         options.params.nsteps =
-            value_t!(matches.value_of("nsteps"), u32).unwrap_or(options.params.nsteps);
+            value_t!(matches.value_of("nsteps"), u64).unwrap_or(options.params.nsteps);
         options.params.xovers =
             value_t!(matches.value_of("xovers"), f64).unwrap_or(options.params.xovers);
         options.params.genome_length = value_t!(matches.value_of("genome_length"), f64)
             .unwrap_or(options.params.genome_length);
+        if let Ok(nsites) = value_t!(matches.value_of("discrete_sites"), u32) {
+            options.params.genome_length = nsites as f64;
+            options.params.discrete_genome = true;
+        }
         options.params.simplification_interval =
             value_t!(matches.value_of("simplification_interval"), u32)
                 .unwrap_or(options.params.simplification_interval);
         options.params.psurvival =
             value_t!(matches.value_of("psurvival"), f64).unwrap_or(options.params.psurvival);
-        options.seed = value_t!(matches.value_of("seed"), u64).unwrap_or(options.seed);
+        options.params.mutrate =
+            value_t!(matches.value_of("mutrate"), f64).unwrap_or(options.params.mutrate);
+        if let Ok(shape) = value_t!(matches.value_of("gamma_shape"), f64) {
+            options.params.crossover_model = CrossoverModel::Gamma { shape };
+        }
+        if let Ok(n_xovers) = value_t!(matches.value_of("fixed_xovers"), u32) {
+            options.params.crossover_model = CrossoverModel::Fixed { n_xovers };
+        }
+        options.params.gc_rate =
+            value_t!(matches.value_of("gc_rate"), f64).unwrap_or(options.params.gc_rate);
+        options.params.gc_mean_tract = value_t!(matches.value_of("gc_mean_tract"), f64)
+            .unwrap_or(options.params.gc_mean_tract);
+        options.params.buffer_edges |= matches.is_present("buffer_edges");
+        options.params.record_individuals |= matches.is_present("record_individuals");
+        options.params.node_metadata |= matches.is_present("node_metadata");
+        options.params.continuous_time |= matches.is_present("continuous_time");
+        options.params.shuffle_deaths |= matches.is_present("shuffle_deaths");
+        options.params.sample_every =
+            value_t!(matches.value_of("sample_every"), u32).unwrap_or(options.params.sample_every);
+        options.check = matches.is_present("check");
+        options.compress = matches.is_present("compress");
+        options.strict = matches.is_present("strict");
+        options.rng_substreams = matches.is_present("rng_substreams");
+        if matches.is_present("max_seconds") {
+            options.max_seconds = Some(value_t_or_exit!(matches, "max_seconds", f64));
+        }
+        if matches.is_present("simplify_when_edges") {
+            options.simplify_when_edges =
+                Some(value_t_or_exit!(matches, "simplify_when_edges", usize));
+        }
+        options.table_size_log = matches.value_of("table_size_log").map(String::from);
+        options.trajectory = matches.value_of("trajectory").map(String::from);
+        options.seed_log = !matches.is_present("no_seed_log");
+        options.dry_run = matches.is_present("dry_run");
+        options.print_config = matches.is_present("print_config");
+        options.params.demes =
+            value_t!(matches.value_of("demes"), u32).unwrap_or(options.params.demes);
+        options.params.ploidy =
+            value_t!(matches.value_of("ploidy"), u32).unwrap_or(options.params.ploidy);
+        options.params.migration =
+            value_t!(matches.value_of("migration"), f64).unwrap_or(options.params.migration);
+        options.params.selfing_rate =
+            value_t!(matches.value_of("selfing"), f64).unwrap_or(options.params.selfing_rate);
+        match matches.value_of("reproduction") {
+            Some("sweepstakes") => {
+                let alpha = value_t!(matches.value_of("sweepstakes_alpha"), f64).unwrap_or(1.5);
+                options.params.reproduction = Reproduction::Sweepstakes { alpha };
+            }
+            Some(_) => options.params.reproduction = Reproduction::Standard,
+            None => (),
+        }
+        options.params.selected_mutrate = value_t!(matches.value_of("selected_mutrate"), f64)
+            .unwrap_or(options.params.selected_mutrate);
+        options.params.selection_coefficient =
+            value_t!(matches.value_of("selection_coefficient"), f64)
+                .unwrap_or(options.params.selection_coefficient);
+        options.seed = value_t!(matches.value_of("seed"), u64)
+            .ok()
+            .or(options.seed);
+        options.time_units =
+            value_t!(matches.value_of("time_units"), TimeUnits).unwrap_or(options.time_units);
         options.treefile =
             value_t!(matches.value_of("treefile"), String).unwrap_or(options.treefile);
+        options.recmap = matches.value_of("recmap").map(String::from);
+        options.mutmap = matches.value_of("mutmap").map(String::from);
+        options.demography = matches.value_of("demography").map(String::from);
+        options.survival_schedule = matches.value_of("survival_schedule").map(String::from);
+        if matches.is_present("growth_rate") {
+            options.growth_rate = Some(value_t_or_exit!(matches, "growth_rate", f64));
+        }
+        options.checkpoint_interval = value_t!(matches.value_of("checkpoint_interval"), u32)
+            .unwrap_or(options.checkpoint_interval);
+        options.resume = matches.value_of("resume").map(String::from);
+        options.start_from = matches.value_of("start_from").map(String::from);
+        if let Some(path) = &options.start_from {
+            let tables = tskit::TableCollection::new_from_file(path)
+                .unwrap_or_else(|e| exit_with_error(SimulationError::from(e)));
+            if !matches.is_present("popsize") {
+                options.params.popsize = (tables.nodes().samples_as_vector().len() as u32)
+                    .checked_div(options.params.ploidy)
+                    .unwrap_or(0);
+            }
+            options.start_tables = Some(tables);
+        }
+        options.progress = matches.is_present("progress");
+        options.final_simplify = !matches.is_present("no_final_simplify");
+        options.keep_unary = matches.is_present("keep_unary");
+        options.canonical_sort = matches.is_present("canonical_sort");
+        options.no_index = matches.is_present("no_index");
+        options.filter_populations = !matches.is_present("no_filter_populations");
+        options.filter_individuals = !matches.is_present("no_filter_individuals");
+        if matches.is_present("sample_size") {
+            options.sample_size = Some(value_t_or_exit!(matches, "sample_size", usize));
+        }
+        options.vcf = matches.value_of("vcf").map(String::from);
+        options.newick = matches.value_of("newick").map(String::from);
+        options.edge_diff_log = matches.value_of("edge_diff_log").map(String::from);
+        options.csv = matches.is_present("csv");
+        options.print_diversity = matches.is_present("print_diversity");
+        options.print_tajimas_d = matches.is_present("print_tajimas_d");
+        options.validate_coalescent = matches.is_present("validate_coalescent");
+        options.require_coalescence = matches.is_present("require_coalescence");
+        options.sfs = matches.value_of("sfs").map(String::from);
+        options.fold_sfs = matches.is_present("fold_sfs");
+        options.stats_only = matches.value_of("stats_only").map(String::from);
+        options.stats = values_t!(matches.values_of("stats"), Statistic).unwrap_or(options.stats);
 
-        options.validate().unwrap();
+        if let Err(e) = options.validate() {
+            exit_with_error(e);
+        }
         options
     }
 
-    // NOTE: This function is incomplete.
     fn validate(&self) -> Result<(), BadParameter> {
-        match self.params.psurvival.partial_cmp(&0.0) {
-            Some(std::cmp::Ordering::Less) => {
+        self.params.validate().map_err(|msg| BadParameter { msg })?;
+
+        if let Some(max_seconds) = self.max_seconds {
+            if !max_seconds.is_finite() || max_seconds < 0.0 {
                 return Err(BadParameter {
-                    msg: String::from("psurvival must be 0 <= p < 1.0"),
+                    msg: String::from("max-seconds must be finite and >= 0.0"),
                 });
             }
-            Some(_) => (),
-            None => (),
         }
 
-        match self.params.psurvival.partial_cmp(&1.0) {
-            Some(std::cmp::Ordering::Less) => (),
-            Some(_) => {
+        if self.sample_size == Some(0) {
+            return Err(BadParameter {
+                msg: String::from("sample-size must be > 0"),
+            });
+        }
+
+        if let Some(rate) = self.growth_rate {
+            if !rate.is_finite() {
                 return Err(BadParameter {
-                    msg: String::from("psurvival must be 0 <= p < 1.0"),
+                    msg: String::from("growth-rate must be finite"),
                 });
             }
-            None => (),
         }
 
         Ok(())
     }
 }
 
-fn overlapping_generations(params: SimParams, seed: u64) -> tskit::TableCollection {
-    let mut tables = match tskit::TableCollection::new(params.genome_length) {
-        Ok(x) => x,
-        Err(e) => panic!("{}", e),
-    };
+// The options, plus the birth time of the last step
+// if --max-seconds stopped the run early.
+#[derive(serde::Serialize)]
+struct ProvenanceRecord<'a> {
+    #[serde(flatten)]
+    options: &'a ProgramOptions,
+    stopped_at_step: Option<u64>,
+}
 
-    let mut rng = StdRng::seed_from_u64(seed);
+fn overlapping_generations(
+    options: &ProgramOptions,
+    recombination_map: Option<RecombinationMap>,
+    mutation_map: Option<MutationMap>,
+    survival_schedule: Option<SurvivalSchedule>,
+    demography: Demography,
+    start_tables: Option<tskit::TableCollection>,
+) -> Result<(tskit::TableCollection, Vec<Vec<tskit::tsk_id_t>>, u64), SimulationError> {
+    let mut sim = match (&options.resume, start_tables) {
+        (Some(prefix), _) => DiploidSimulation::from_checkpoint_with_demography(
+            options.params,
+            demography.clone(),
+            prefix,
+        )?,
+        (None, Some(tables)) => DiploidSimulation::start_from(
+            options.params,
+            options.seed.unwrap_or_else(entropy_seed),
+            tables,
+        )?,
+        (None, None) => {
+            DiploidSimulation::new(options.params, options.seed.unwrap_or_else(entropy_seed))?
+        }
+    };
+    if options.resume.is_none() {
+        sim.set_demography(demography)?;
+    }
+    if let Some(map) = recombination_map {
+        sim.set_recombination_map(map)?;
+    }
+    if let Some(map) = mutation_map {
+        sim.set_mutation_map(map)?;
+    }
+    if let Some(schedule) = survival_schedule {
+        sim.set_survival_schedule(schedule);
+    }
+    sim.set_progress(options.progress);
+    sim.set_final_simplify(options.final_simplify);
+    sim.set_strict(options.strict);
+    sim.set_substreams(options.rng_substreams);
+    sim.set_keep_unary(options.keep_unary);
+    sim.set_canonical_sort(options.canonical_sort);
+    sim.set_build_index(!options.no_index);
+    sim.set_filter_populations(options.filter_populations);
+    sim.set_filter_individuals(options.filter_individuals);
+    sim.set_max_duration(options.max_seconds.map(std::time::Duration::from_secs_f64));
+    sim.set_simplify_when_edges(options.simplify_when_edges);
+    sim.set_table_size_log(options.table_size_log.as_deref())?;
+    sim.set_trajectory(options.trajectory.as_deref())?;
+    if options.checkpoint_interval > 0 {
+        let prefix = format!("{}.checkpoint", file_prefix(&options.treefile));
+        sim.set_checkpointing(options.checkpoint_interval, &prefix);
+    }
+    if options.seed_log {
+        write_seed(&options.treefile, sim.seed())?;
+    }
+    let mut tables = sim.run()?;
+    let stopped_at_step = match sim.remaining_steps() {
+        0 => None,
+        step => {
+            log::warn!(
+                "stopped early, after --max-seconds, at step {} of {}",
+                options.params.nsteps - step,
+                options.params.nsteps
+            );
+            Some(step)
+        }
+    };
+    let record = ProvenanceRecord {
+        options,
+        stopped_at_step,
+    };
+    add_provenance(&mut tables, "overlapping_generations", sim.seed(), &record)?;
+    set_simulation_metadata(
+        &mut tables,
+        &SimulationMetadata::new(
+            "overlapping_generations",
+            options.params.popsize,
+            options.params.nsteps,
+            sim.seed(),
+        ),
+    )?;
+    let mut individuals = sim
+        .alive()
+        .iter()
+        .map(|a| a.nodes.clone())
+        .collect::<Vec<_>>();
+    if let Some(sample_size) = options.sample_size {
+        let new_ids = subsample(
+            &mut tables,
+            &alive_sample_nodes(sim.alive()),
+            sample_size,
+            sim.final_simplification_options(),
+            &mut subsample_rng(sim.seed()),
+        )?;
+        // Keep the chosen nodes of each individual together.
+        individuals = new_ids
+            .chunks(options.params.ploidy as usize)
+            .map(|nodes| {
+                nodes
+                    .iter()
+                    .copied()
+                    .filter(|&u| u != tskit::TSK_NULL)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|nodes| !nodes.is_empty())
+            .collect();
+    }
+    Ok((tables, individuals, sim.seed()))
+}
 
-    let mut alive: Vec<Diploid> = vec![];
-    for _ in 0..params.popsize {
-        let node0 = match tables.add_node(0, params.nsteps as f64, tskit::TSK_NULL, tskit::TSK_NULL)
-        {
-            Ok(x) => x,
-            Err(e) => panic!("{}", e),
-        };
-        let node1 = match tables.add_node(0, params.nsteps as f64, tskit::TSK_NULL, tskit::TSK_NULL)
-        {
-            Ok(x) => x,
-            Err(e) => panic!("{}", e),
-        };
-        alive.push(Diploid { node0, node1 });
+fn run(
+    options: &ProgramOptions,
+    start_tables: Option<tskit::TableCollection>,
+) -> Result<(), SimulationError> {
+    if options.print_config {
+        eprintln!("{}", serde_json::to_string_pretty(options).unwrap());
     }
 
-    let mut parents: Vec<Parents> = vec![];
+    let recombination_map = match &options.recmap {
+        Some(path) => Some(RecombinationMap::from_tsv(
+            path,
+            options.params.genome_length,
+        )?),
+        None => None,
+    };
+    let mutation_map = match &options.mutmap {
+        Some(path) => Some(MutationMap::from_tsv(path, options.params.genome_length)?),
+        None => None,
+    };
+    let survival_schedule = match &options.survival_schedule {
+        Some(path) => Some(SurvivalSchedule::from_tsv(path)?),
+        None => None,
+    };
 
-    for step in (0..params.nsteps).rev() {
-        parents.clear();
-        death_and_parents(&alive, &params, &mut parents, &mut rng);
-        births(&parents, &params, step, &mut tables, &mut alive, &mut rng);
+    if options.dry_run {
+        println!(
+            "{}",
+            estimate_memory(&options.params, recombination_map.as_ref())
+        );
+        return Ok(());
+    }
 
-        if step % params.simplification_interval == 0 {
-            simplify(&mut alive, &mut tables);
+    let demography = match (&options.demography, options.growth_rate) {
+        (Some(path), _) => Demography::from_tsv(path)?,
+        (None, Some(rate)) => {
+            Demography::exponential(options.params.popsize, rate, options.params.nsteps)
+                .map_err(SimulationError::InvalidParameters)?
         }
+        (None, None) => Demography::default(),
+    };
+
+    if let Some(sample_size) = options.sample_size {
+        let final_popsize = demography.popsize_after(options.params.popsize, 0);
+        let nodes = options.params.ploidy as usize * final_popsize as usize;
+        if sample_size > nodes {
+            return Err(SimulationError::InvalidParameters(format!(
+                "sample-size {} is larger than the {} nodes of the final generation",
+                sample_size, nodes
+            )));
+        }
+    }
+
+    let (mut tables, individuals, seed) = overlapping_generations(
+        options,
+        recombination_map,
+        mutation_map,
+        survival_schedule,
+        demography,
+        start_tables,
+    )?;
+    let samples = individuals.concat();
+
+    rescale_times(&mut tables, options.time_units.scale(&options.params))?;
+
+    let not_coalesced = if options.require_coalescence && !is_fully_coalesced(&tables)? {
+        Some(SimulationError::InvalidParameters(format!(
+            "the samples have not coalesced at every position after {} steps; try a larger --nsteps",
+            options.params.nsteps
+        )))
+    } else {
+        None
+    };
+
+    if let Some(vcf) = &options.vcf {
+        write_vcf(&tables, &individuals, vcf)?;
+    }
+
+    if options.print_diversity {
+        eprintln!("{}", mean_pairwise_diversity(&tables, &samples)?);
+    }
+
+    if options.print_tajimas_d {
+        eprintln!("{}", tajimas_d(&tables, &samples)?);
+    }
+
+    if options.validate_coalescent {
+        let scale = options.time_units.scale(&options.params);
+        eprintln!(
+            "mean TMRCA: {}, expected: {}",
+            mean_tmrca(&tables)?,
+            expected_tmrca(&options.params, samples.len()) * scale
+        );
+    }
+
+    if let Some(sfs) = &options.sfs {
+        let spectrum = allele_frequency_spectrum(&tables, &samples, !options.fold_sfs)?;
+        write_spectrum(sfs, &spectrum)?;
+    }
+
+    if let Some(newick) = &options.newick {
+        let trees = trees_to_newick(&tables)?;
+        std::fs::write(newick, trees.join("\n") + "\n")?;
+    }
+
+    if let Some(path) = &options.edge_diff_log {
+        write_edge_diffs(&tables, path)?;
     }
 
-    tables.build_index().unwrap();
+    if options.csv {
+        dump_tables_csv(&tables, file_prefix(&options.treefile))?;
+    }
 
-    tables
+    if let Some(path) = &options.stats_only {
+        if options.check {
+            check_integrity(&tables)?;
+        }
+        let mut statistics = vec![];
+        for statistic in &options.stats {
+            statistics.push((*statistic, statistic.compute(&tables, &samples)?));
+        }
+        append_statistics(path, seed, &options.params, &statistics)?;
+        return not_coalesced.map_or(Ok(()), Err);
+    }
+
+    write_tables(&tables, &options.treefile, options.check)?;
+    if options.compress {
+        gzip_file(&options.treefile)?;
+    }
+    not_coalesced.map_or(Ok(()), Err)
 }
 
 fn main() {
-    let options = ProgramOptions::new();
+    let mut options = ProgramOptions::new();
+    let start_tables = options.start_tables.take();
+    if let Err(e) = run(&options, start_tables) {
+        exit_with_error(e);
+    }
+}
 
-    let tables = overlapping_generations(options.params, options.seed);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    tables
-        .dump(&options.treefile, tskit::TableOutputOptions::empty())
-        .unwrap();
+    fn message(options: &ProgramOptions) -> String {
+        options.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn default_options_are_valid() {
+        assert!(ProgramOptions::default().validate().is_ok());
+    }
+
+    #[test]
+    fn simulation_parameters_are_checked() {
+        let mut options = ProgramOptions::default();
+        options.params.mutrate = -1.0;
+        assert!(message(&options).starts_with("mutrate must be"));
+    }
+
+    #[test]
+    fn max_seconds_must_be_finite_and_not_negative() {
+        for bad in [-1.0, f64::NAN, f64::INFINITY] {
+            let options = ProgramOptions {
+                max_seconds: Some(bad),
+                ..Default::default()
+            };
+            assert_eq!(message(&options), "max-seconds must be finite and >= 0.0");
+        }
+    }
+
+    #[test]
+    fn sample_size_must_be_positive() {
+        let options = ProgramOptions {
+            sample_size: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(message(&options), "sample-size must be > 0");
+    }
+
+    #[test]
+    fn growth_rate_must_be_finite() {
+        for bad in [f64::NAN, f64::INFINITY] {
+            let options = ProgramOptions {
+                growth_rate: Some(bad),
+                ..Default::default()
+            };
+            assert_eq!(message(&options), "growth-rate must be finite");
+        }
+    }
 }
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use crate::diploid::{EdgeBuffer, Individual};
+use crate::error::SimulationError;
+use crate::output::create_parent_dir;
+use crate::selection::Genomes;
+
+/// The state, besides the tables, needed to resume
+/// a simulation.
+///
+/// A checkpoint with prefix `p` is written as `p.trees`,
+/// holding the tables, and `p.json`, holding this struct.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The birth time of the last completed step.
+    pub step: u64,
+    /// The seed the simulation was started with.
+    pub seed: u64,
+    /// How far along its stream the random number
+    /// generator for `seed` is.
+    pub rng_word_pos: u128,
+    /// The same for the generators of
+    /// [`Substreams`](crate::rng::Substreams), if used.
+    #[serde(default)]
+    pub substreams_word_pos: Option<(u128, u128)>,
+    pub alive: Vec<Individual>,
+    /// The selected mutations of each of `alive`,
+    /// empty if there is no selection.
+    #[serde(default)]
+    pub genomes: Vec<Genomes>,
+    /// The age of each of `alive`.
+    #[serde(default)]
+    pub ages: Vec<u32>,
+    #[serde(default)]
+    pub ancient_samples: Vec<tskit::tsk_id_t>,
+    /// Edges not yet in the tables, if they are buffered.
+    #[serde(default)]
+    pub edge_buffer: Option<EdgeBuffer>,
+}
+
+impl Checkpoint {
+    pub fn tables_path(prefix: &str) -> String {
+        format!("{}.trees", prefix)
+    }
+
+    pub fn state_path(prefix: &str) -> String {
+        format!("{}.json", prefix)
+    }
+
+    /// Write `tables` and `self` using `prefix`.
+    pub fn write(
+        &self,
+        tables: &tskit::TableCollection,
+        prefix: &str,
+    ) -> Result<(), SimulationError> {
+        create_parent_dir(prefix)?;
+        tables.dump(
+            &Self::tables_path(prefix),
+            tskit::TableOutputOptions::empty(),
+        )?;
+        let file = std::fs::File::create(Self::state_path(prefix))?;
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|e| SimulationError::Io(std::io::Error::from(e)))?;
+        Ok(())
+    }
+
+    /// Read the tables and state written by [`Checkpoint::write`].
+    pub fn read(prefix: &str) -> Result<(Self, tskit::TableCollection), SimulationError> {
+        let tables = tskit::TableCollection::new_from_file(&Self::tables_path(prefix))?;
+        let file = std::fs::File::open(Self::state_path(prefix))?;
+        let checkpoint = serde_json::from_reader(file)
+            .map_err(|e| SimulationError::Io(std::io::Error::from(e)))?;
+        Ok((checkpoint, tables))
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::diploid::SimParams;
+use crate::error::SimulationError;
+
+/// Simulation parameters and run options read from a
+/// TOML file, for example:
+///
+/// ```toml
+/// seed = 42
+/// treefile = "out/run.trees"
+///
+/// [params]
+/// popsize = 500
+/// nsteps = 2000
+/// xovers = 1.0
+/// reproduction = { model = "sweepstakes", alpha = 1.5 }
+/// ```
+///
+/// Missing fields keep their defaults, and unknown
+/// fields are an error.  The binaries let command-line
+/// options override the values from the file.  Flags
+/// without a value, such as `--buffer_edges`, can only
+/// turn a setting on, so one that the file sets to `true`
+/// stays on.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub treefile: Option<String>,
+    pub params: SimParams,
+}
+
+impl Config {
+    pub fn from_toml(s: &str) -> Result<Self, SimulationError> {
+        toml::from_str(s).map_err(|e| SimulationError::Config(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, SimulationError> {
+        // Going through a toml::Value puts the tables for
+        // the enums after the plain values, as TOML requires.
+        toml::Value::try_from(self)
+            .and_then(|v| toml::to_string(&v))
+            .map_err(|e| SimulationError::Config(e.to_string()))
+    }
+
+    /// Read a config from the TOML file at `path`.
+    pub fn read(path: &str) -> Result<Self, SimulationError> {
+        let s = std::fs::read_to_string(path)?;
+        Self::from_toml(&s)
+            .map_err(|e| SimulationError::Config(format!("error reading {}: {}", path, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diploid::{CrossoverModel, Reproduction};
+
+    #[test]
+    fn config_round_trips_through_toml() {
+        let config = Config {
+            seed: Some(42),
+            treefile: Some(String::from("out/run.trees")),
+            params: SimParams {
+                popsize: 500,
+                nsteps: 2000,
+                xovers: 1.5,
+                crossover_model: CrossoverModel::Gamma { shape: 2.0 },
+                reproduction: Reproduction::Sweepstakes { alpha: 1.25 },
+                buffer_edges: true,
+                ..SimParams::default()
+            },
+        };
+        let read = Config::from_toml(&config.to_toml().unwrap()).unwrap();
+        assert_eq!(read.seed, config.seed);
+        assert_eq!(read.treefile, config.treefile);
+        assert_eq!(read.params, config.params);
+    }
+
+    #[test]
+    fn missing_fields_keep_defaults_and_unknown_fields_are_errors() {
+        let config = Config::from_toml("[params]\npopsize = 7\n").unwrap();
+        assert_eq!(config.seed, None);
+        assert_eq!(
+            config.params,
+            SimParams {
+                popsize: 7,
+                ..SimParams::default()
+            }
+        );
+        assert!(Config::from_toml("[params]\npopsise = 7\n").is_err());
+    }
+}
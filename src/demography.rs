@@ -0,0 +1,249 @@
+use crate::diploid::SimParams;
+use crate::error::SimulationError;
+
+/// A change of population size.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SizeChange {
+    /// The birth step at which the size changes, counted
+    /// back from the end of the run like node times, so
+    /// that step 0 is the last.
+    pub step: u64,
+    /// The number of diploids from this step on.
+    pub popsize: u32,
+}
+
+/// A schedule of population size changes.
+///
+/// The population starts with `params.popsize` diploids.
+/// At the step of each [`SizeChange`], the population
+/// grows or shrinks to its `popsize`, and keeps that size
+/// until the next change.
+#[derive(Clone, Debug, Default)]
+pub struct Demography {
+    /// Sorted by decreasing step, so in the order in
+    /// which the simulation reaches them.
+    events: Vec<SizeChange>,
+}
+
+impl Demography {
+    /// Build a schedule from `events`, in any order.
+    ///
+    /// Sizes must be `> 0`, and no two events may share
+    /// a step.
+    pub fn new(mut events: Vec<SizeChange>) -> Result<Self, String> {
+        events.sort_by_key(|e| std::cmp::Reverse(e.step));
+        for w in events.windows(2) {
+            if w[0].step == w[1].step {
+                return Err(format!(
+                    "there are two population size changes at step {}",
+                    w[0].step
+                ));
+            }
+        }
+        if let Some(e) = events.iter().find(|e| e.popsize == 0) {
+            return Err(format!(
+                "the population size change at step {} must be to a size > 0",
+                e.step
+            ));
+        }
+        Ok(Self { events })
+    }
+
+    /// Exponential growth, or decline if `rate < 0.0`, from
+    /// `initial` diploids: once `k` of `nsteps` steps are done,
+    /// the size is `initial * exp(rate * k)`, rounded to the
+    /// nearest integer.  There is an event at each step where
+    /// the rounded size changes.
+    ///
+    /// The size must stay `> 0` and fit in a `u32`.
+    pub fn exponential(initial: u32, rate: f64, nsteps: u64) -> Result<Self, String> {
+        if !rate.is_finite() {
+            return Err(format!("growth rate must be finite, got {}", rate));
+        }
+        let size = |k: u64| (initial as f64 * (rate * k as f64).exp()).round();
+        let mut events = vec![];
+        let mut popsize = initial;
+        let mut k = 0;
+        // Jump straight to the step at which the rounded size
+        // next crosses a half-integer, rather than visit every
+        // step, then correct for rounding in the logarithm.
+        while rate != 0.0 && !(rate < 0.0 && popsize == 0) {
+            let threshold = if rate > 0.0 {
+                popsize as f64 + 0.5
+            } else {
+                popsize as f64 - 0.5
+            };
+            let guess = ((threshold / initial as f64).ln() / rate).ceil();
+            if guess.is_nan() || guess > nsteps as f64 {
+                break;
+            }
+            let mut next = (guess as u64).max(k + 1);
+            while next > k + 1 && size(next - 1) != popsize as f64 {
+                next -= 1;
+            }
+            while next <= nsteps && size(next) == popsize as f64 {
+                next += 1;
+            }
+            if next > nsteps {
+                break;
+            }
+            let n = size(next);
+            if n > u32::MAX as f64 {
+                return Err(format!(
+                    "growth rate {} gives a population size of {} after {} steps",
+                    rate, n, next
+                ));
+            }
+            popsize = n as u32;
+            events.push(SizeChange {
+                step: nsteps - next,
+                popsize,
+            });
+            k = next;
+        }
+        Self::new(events)
+    }
+
+    /// Read a schedule from a file with two tab-separated
+    /// columns, step and new population size, one change per
+    /// line.  Blank lines and lines starting with `#` are
+    /// skipped.
+    pub fn from_tsv(path: &str) -> Result<Self, SimulationError> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut events = vec![];
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let parsed = match fields.as_slice() {
+                [s, n] => s
+                    .trim()
+                    .parse::<u64>()
+                    .and_then(|s| n.trim().parse::<u32>().map(|n| (s, n)))
+                    .ok(),
+                _ => None,
+            };
+            match parsed {
+                Some((step, popsize)) => events.push(SizeChange { step, popsize }),
+                None => {
+                    return Err(SimulationError::InvalidParameters(format!(
+                        "{}, line {}: expected two integers, step and popsize, separated by a tab",
+                        path,
+                        lineno + 1
+                    )))
+                }
+            }
+        }
+        Self::new(events).map_err(SimulationError::InvalidParameters)
+    }
+
+    /// Check that every change happens during the run and
+    /// leaves at least one diploid per deme.
+    pub fn validate(&self, params: &SimParams) -> Result<(), String> {
+        for e in &self.events {
+            if e.step >= params.nsteps {
+                return Err(format!(
+                    "population size change at step {}, but there are only {} steps",
+                    e.step, params.nsteps
+                ));
+            }
+            if e.popsize < params.demes {
+                return Err(format!(
+                    "population size change at step {} to {}, fewer than the {} demes",
+                    e.step, e.popsize, params.demes
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn events(&self) -> &[SizeChange] {
+        &self.events
+    }
+
+    /// The new population size at `step`, if it changes then.
+    pub fn change_at(&self, step: u64) -> Option<u32> {
+        self.events
+            .binary_search_by(|e| step.cmp(&e.step))
+            .ok()
+            .map(|i| self.events[i].popsize)
+    }
+
+    /// The population size once the births at `step` are
+    /// done, for a population that started with `initial`
+    /// diploids.
+    pub fn popsize_after(&self, initial: u32, step: u64) -> u32 {
+        match self.events.partition_point(|e| e.step >= step) {
+            0 => initial,
+            i => self.events[i - 1].popsize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exponential_by_step(initial: u32, rate: f64, nsteps: u64) -> Vec<SizeChange> {
+        let mut events = vec![];
+        let mut popsize = initial;
+        for k in 1..=nsteps {
+            let n = (initial as f64 * (rate * k as f64).exp()).round() as u32;
+            if n != popsize {
+                popsize = n;
+                events.push(SizeChange {
+                    step: nsteps - k,
+                    popsize,
+                });
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn exponential_matches_a_step_by_step_schedule() {
+        for &(initial, rate, nsteps) in &[
+            (100, 0.01, 1000),
+            (100, -0.001, 2000),
+            (1000, 0.0001, 5000),
+            (3, 0.5, 40),
+            (50, 1e-7, 100),
+        ] {
+            let demography = Demography::exponential(initial, rate, nsteps).unwrap();
+            assert_eq!(
+                demography.events(),
+                exponential_by_step(initial, rate, nsteps).as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn constant_size_has_no_events_however_long_the_run() {
+        let demography = Demography::exponential(1000, 0.0, u64::MAX).unwrap();
+        assert!(demography.events().is_empty());
+    }
+
+    #[test]
+    fn lookups_follow_the_schedule() {
+        let demography = Demography::new(vec![
+            SizeChange {
+                step: 10,
+                popsize: 5,
+            },
+            SizeChange {
+                step: 50,
+                popsize: 20,
+            },
+        ])
+        .unwrap();
+        assert_eq!(demography.change_at(50), Some(20));
+        assert_eq!(demography.change_at(10), Some(5));
+        assert_eq!(demography.change_at(30), None);
+        assert_eq!(demography.popsize_after(100, 60), 100);
+        assert_eq!(demography.popsize_after(100, 50), 20);
+        assert_eq!(demography.popsize_after(100, 11), 20);
+        assert_eq!(demography.popsize_after(100, 0), 5);
+    }
+}
@@ -1,15 +1,146 @@
-use rand::rngs::StdRng;
+use rand::distributions::WeightedIndex;
+use rand::seq::SliceRandom;
 use rand::Rng;
-use rand_distr::{Exp, Uniform};
+use rand::SeedableRng;
+use rand_distr::{Beta, Binomial, Distribution, Exp, Gamma, Geometric, Poisson, Uniform};
+use std::io::Write;
+use tskit::TableAccess;
 
-#[derive(Copy, Clone)]
+use crate::checkpoint::Checkpoint;
+use crate::demography::Demography;
+use crate::error::{IntegrityError, SimulationError};
+use crate::metadata::{
+    has_population_metadata_schema, set_node_metadata_schema, set_population_metadata_schema,
+    NodeMetadata, PopulationMetadata,
+};
+use crate::mutmap::MutationMap;
+use crate::output::create_parent_dir;
+use crate::progress::Progress;
+use crate::recmap::RecombinationMap;
+use crate::rng::{SimRng, Substreams};
+use crate::selection::{self, Genomes, Meiosis};
+use crate::survival::SurvivalSchedule;
+use crate::time::shift_times;
+
+/// How the distances between crossover positions
+/// are distributed.
+///
+/// For the renewal models, `Poisson` and `Gamma`,
+/// the mean distance is `genome_length / xovers`.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "model", rename_all = "lowercase")]
+pub enum CrossoverModel {
+    /// Exponential distances, giving a Poisson
+    /// number of crossovers.
+    Poisson,
+    /// Gamma distances with the given shape, modelling
+    /// crossover interference when `shape > 1`.
+    /// A shape of 1.0 is the same as `Poisson`.
+    Gamma { shape: f64 },
+    /// Exactly `n_xovers` crossovers at uniform positions,
+    /// ignoring [`SimParams::xovers`].
+    Fixed { n_xovers: u32 },
+}
+
+/// How parents are chosen for each birth.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "model", rename_all = "lowercase")]
+pub enum Reproduction {
+    /// Parents are chosen uniformly, via [`UniformSelector`].
+    Standard,
+    /// High-fecundity sweepstakes reproduction, via
+    /// [`UniformSelector`] or [`FitnessSelector`].
+    /// Requires `0.0 < alpha < 2.0`.
+    Sweepstakes { alpha: f64 },
+}
+
+/// The largest [`SimParams::nsteps`].  Node times are
+/// `f64`, which hold every integer up to 2^53, but with
+/// [`SimParams::continuous_time`] a birth time also needs
+/// fractions of a step, and below 2^52 there are at least
+/// two `f64` values in each step.
+pub const MAX_NSTEPS: u64 = 1 << 52;
+
+/// Missing fields take their [`Default`] values when
+/// deserializing, so that a [`Config`](crate::config::Config)
+/// need only list the parameters it changes.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct SimParams {
     pub popsize: u32,
-    pub nsteps: u32,
+    /// At most [`MAX_NSTEPS`], so that every birth time
+    /// is exact as a node time.
+    pub nsteps: u64,
     pub xovers: f64,
+    pub crossover_model: CrossoverModel,
+    /// Mean number of gene conversion tracts per meiosis.
+    /// The number of tracts is Poisson and each starts at
+    /// a uniform position.
+    pub gc_rate: f64,
+    /// Mean gene conversion tract length.  Lengths are
+    /// geometric, so this must be >= 1.0 if `gc_rate > 0.0`.
+    pub gc_mean_tract: f64,
     pub psurvival: f64,
     pub genome_length: f64,
     pub simplification_interval: u32,
+    pub mutrate: f64,
+    pub buffer_edges: bool,
+    /// Record each diploid, and its parents, in the individual table.
+    /// Simplification during the run does not filter individuals, so the
+    /// full pedigree is kept unless the output is filtered, see
+    /// [`DiploidSimulation::set_filter_individuals`].
+    pub record_individuals: bool,
+    /// Number of demes.  The diploid at index `i` of the
+    /// population lives in deme `i % demes`, which is also
+    /// its nodes' row in the population table.
+    pub demes: u32,
+    /// Probability that one parent of an offspring comes
+    /// from a different deme, chosen uniformly.
+    pub migration: f64,
+    /// Probability that an offspring is produced by
+    /// self-fertilization, with both gametes from its
+    /// first parent.
+    pub selfing_rate: f64,
+    pub reproduction: Reproduction,
+    /// Rate of new selected mutations per unit genome
+    /// length per gamete.  Selection is off when this is 0.0.
+    pub selected_mutrate: f64,
+    /// Each selected mutation adds this to the fitness
+    /// of its carrier, starting from 1.0.
+    pub selection_coefficient: f64,
+    /// Give each new node [`NodeMetadata`] recording its
+    /// birth step.
+    pub node_metadata: bool,
+    /// Keep the nodes born at each birth time that is a
+    /// multiple of this as ancient samples.  0 turns
+    /// ancient sampling off, and other values must be
+    /// less than `nsteps`.
+    pub sample_every: u32,
+    /// Give the births of each step real-valued times,
+    /// spread over the unit of time before the step's
+    /// birth time, instead of all the same integer time.
+    /// See [`births`].
+    pub continuous_time: bool,
+    /// Treat the genome as `genome_length` sites at the
+    /// integer positions `0, 1, ...`.  Crossover and gene
+    /// conversion breakpoints are rounded down to integers,
+    /// so edges have integer coordinates, and mutations are
+    /// overlaid with [`overlay_discrete_mutations`].
+    /// `genome_length` must be a whole number.
+    pub discrete_genome: bool,
+    /// The number of nodes per individual, at least 2.
+    /// Offspring node `i` comes from a gamete of the first
+    /// parent if `i` is even and of the second if it is
+    /// odd.  Each gamete recombines two homologs of its
+    /// parent, chosen at random.  Selection needs a
+    /// ploidy of 2.
+    pub ploidy: u32,
+    /// Visit the alive individuals in a random order each
+    /// step, shuffled with the mating random number
+    /// generator, when deciding who dies and choosing
+    /// parents, rather than in index order.  See
+    /// [`death_and_parents`].
+    pub shuffle_deaths: bool,
 }
 
 impl Default for SimParams {
@@ -18,51 +149,778 @@ impl Default for SimParams {
             popsize: 1000,
             nsteps: 1000,
             xovers: 0.,
+            crossover_model: CrossoverModel::Poisson,
+            gc_rate: 0.0,
+            gc_mean_tract: 0.0,
             psurvival: 0.0,
             genome_length: 1e6,
             simplification_interval: 100,
+            mutrate: 0.0,
+            buffer_edges: false,
+            record_individuals: false,
+            demes: 1,
+            migration: 0.0,
+            selfing_rate: 0.0,
+            reproduction: Reproduction::Standard,
+            selected_mutrate: 0.0,
+            selection_coefficient: 0.0,
+            node_metadata: false,
+            sample_every: 0,
+            continuous_time: false,
+            discrete_genome: false,
+            ploidy: 2,
+            shuffle_deaths: false,
+        }
+    }
+}
+
+impl SimParams {
+    /// The number of nodes of the alive population,
+    /// `ploidy * popsize`.  After each step, the alive
+    /// population has `popsize` individuals with `ploidy`
+    /// nodes each: see [`check_popsize`].
+    pub fn num_alive_nodes(&self) -> usize {
+        self.ploidy as usize * self.popsize as usize
+    }
+
+    /// Check combinations of parameters that would otherwise
+    /// fail, or loop forever, part way through a simulation,
+    /// or silently skip something that was asked for.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.popsize == 0 {
+            return Err(String::from("popsize must be > 0"));
+        }
+        if !(self.genome_length.is_finite() && self.genome_length > 0.0) {
+            return Err(format!(
+                "genome_length must be finite and > 0.0, got {:e}",
+                self.genome_length
+            ));
+        }
+        if self.simplification_interval == 0 {
+            return Err(String::from("simplification_interval must be >= 1"));
+        }
+        if !(0.0..1.0).contains(&self.psurvival) {
+            return Err(format!(
+                "psurvival must be 0.0 <= p < 1.0, got {}",
+                self.psurvival
+            ));
+        }
+        if !self.xovers.is_finite() || self.xovers < 0.0 {
+            return Err(format!(
+                "xovers must be finite and >= 0.0, got {:e}",
+                self.xovers
+            ));
+        }
+        if !self.mutrate.is_finite() || self.mutrate < 0.0 {
+            return Err(format!(
+                "mutrate must be finite and >= 0.0, got {:e}",
+                self.mutrate
+            ));
+        }
+        if self.nsteps > MAX_NSTEPS {
+            return Err(format!(
+                "nsteps must be <= 2^52 = {}, got {}",
+                MAX_NSTEPS, self.nsteps
+            ));
+        }
+        // Ancient samples come from birth times 1 to nsteps - 1.
+        if self.sample_every > 0 && self.sample_every as u64 >= self.nsteps {
+            return Err(format!(
+                "sample_every must be < nsteps for any ancient samples to be kept, got {} and {}",
+                self.sample_every, self.nsteps
+            ));
+        }
+        if self.xovers > 0.0 {
+            let rate = self.xovers / self.genome_length;
+            if !rate.is_finite() || rate <= 0.0 {
+                return Err(format!(
+                    "xovers / genome_length must be finite and > 0.0 when xovers > 0.0, got {:e} / {:e} = {:e}",
+                    self.xovers, self.genome_length, rate
+                ));
+            }
+        }
+        if let CrossoverModel::Gamma { shape } = self.crossover_model {
+            if !shape.is_finite() || shape <= 0.0 {
+                return Err(format!(
+                    "the gamma crossover model needs a finite shape > 0.0, got {:e}",
+                    shape
+                ));
+            }
+        }
+        if self.demes == 0 || self.demes > self.popsize {
+            return Err(format!(
+                "demes must be >= 1 and <= popsize, got {} demes and popsize {}",
+                self.demes, self.popsize
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.migration) {
+            return Err(format!(
+                "migration must be 0.0 <= m <= 1.0, got {}",
+                self.migration
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.selfing_rate) {
+            return Err(format!(
+                "selfing_rate must be 0.0 <= r <= 1.0, got {}",
+                self.selfing_rate
+            ));
+        }
+        if let Reproduction::Sweepstakes { alpha } = self.reproduction {
+            if !(alpha > 0.0 && alpha < 2.0) {
+                return Err(format!(
+                    "sweepstakes reproduction needs 0.0 < alpha < 2.0, got {}",
+                    alpha
+                ));
+            }
+        }
+        match self.gc_rate.partial_cmp(&0.0) {
+            Some(std::cmp::Ordering::Less) | None => {
+                return Err(format!("gc_rate must be >= 0.0, got {:e}", self.gc_rate));
+            }
+            Some(std::cmp::Ordering::Greater) => {
+                if !self.gc_rate.is_finite()
+                    || !self.gc_mean_tract.is_finite()
+                    || self.gc_mean_tract < 1.0
+                {
+                    return Err(format!(
+                        "gene conversion needs a finite gc_rate and a finite gc_mean_tract >= 1.0, got {:e} and {:e}",
+                        self.gc_rate, self.gc_mean_tract
+                    ));
+                }
+            }
+            Some(std::cmp::Ordering::Equal) => (),
+        }
+        if !self.selected_mutrate.is_finite() || self.selected_mutrate < 0.0 {
+            return Err(format!(
+                "selected_mutrate must be finite and >= 0.0, got {:e}",
+                self.selected_mutrate
+            ));
+        }
+        if !self.selection_coefficient.is_finite() {
+            return Err(format!(
+                "selection_coefficient must be finite, got {:e}",
+                self.selection_coefficient
+            ));
+        }
+        if self.ploidy < 2 {
+            return Err(format!("ploidy must be >= 2, got {}", self.ploidy));
+        }
+        if self.ploidy != 2 && self.selected_mutrate > 0.0 {
+            return Err(format!(
+                "selected mutations need a ploidy of 2, got {}",
+                self.ploidy
+            ));
+        }
+        if self.discrete_genome {
+            if self.genome_length.fract() != 0.0 || self.genome_length < 1.0 {
+                return Err(format!(
+                    "a discrete genome needs a whole number of sites >= 1, got genome_length {}",
+                    self.genome_length
+                ));
+            }
+            if self.selected_mutrate > 0.0 {
+                return Err(String::from(
+                    "selected mutations are not supported with a discrete genome",
+                ));
+            }
         }
+        Ok(())
     }
 }
 
-#[derive(Copy, Clone)]
-pub struct Diploid {
-    pub node0: tskit::tsk_id_t,
-    pub node1: tskit::tsk_id_t,
+/// The [`SimParams::ploidy`] nodes of an individual and,
+/// if [`SimParams::record_individuals`] is `true`,
+/// its row in the individual table.
+/// Otherwise, `individual` is [`tskit::TSK_NULL`].
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Individual {
+    pub nodes: Vec<tskit::tsk_id_t>,
+    pub individual: tskit::tsk_id_t,
+}
+
+/// An [`Individual`] with the default ploidy of 2.
+pub type Diploid = Individual;
+
+/// The nodes of each individual in `alive`, in order.
+/// These are the samples of the tables after [`simplify`].
+pub fn alive_sample_nodes(alive: &[Individual]) -> Vec<tskit::tsk_id_t> {
+    alive.iter().flat_map(|a| a.nodes.iter().copied()).collect()
 }
 
 pub struct Parents {
     pub index: usize,
-    pub parent0: Diploid,
-    pub parent1: Diploid,
+    pub parent0: Individual,
+    pub parent1: Individual,
+    /// The indexes of `parent0` and `parent1` in the population.
+    pub parent_indexes: (usize, usize),
+}
+
+/// The deme of the diploid at `index` in the population.
+pub fn deme(index: usize, params: &SimParams) -> usize {
+    index % params.demes as usize
+}
+
+/// Chooses the index of a parent within a deme.
+enum ParentSampler {
+    Uniform(Uniform<usize>),
+    Weighted(WeightedIndex<f64>),
+}
+
+/// Chooses parents within each deme.
+pub struct ParentSamplers {
+    samplers: Vec<ParentSampler>,
+}
+
+impl ParentSamplers {
+    /// Choose parents uniformly within each deme.
+    pub fn uniform(params: &SimParams) -> Self {
+        let demes = params.demes as usize;
+        // Deme d holds indexes d, d + demes, d + 2 * demes, ...
+        let samplers = (0..demes)
+            .map(|d| {
+                ParentSampler::Uniform(Uniform::new(
+                    0_usize,
+                    (params.popsize as usize - d).div_ceil(demes),
+                ))
+            })
+            .collect();
+        Self { samplers }
+    }
+
+    /// Choose parents within each deme with probability
+    /// proportional to `fitness`, which has one value per
+    /// diploid in the population.
+    pub fn weighted(params: &SimParams, fitness: &[f64]) -> Result<Self, SimulationError> {
+        let demes = params.demes as usize;
+        let mut samplers = vec![];
+        for d in 0..demes {
+            let weights = fitness.iter().skip(d).step_by(demes);
+            match WeightedIndex::new(weights) {
+                Ok(w) => samplers.push(ParentSampler::Weighted(w)),
+                Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+            }
+        }
+        Ok(Self { samplers })
+    }
+
+    /// The index in the population of a parent from deme `d`.
+    pub fn pick(&self, d: usize, rng: &mut SimRng) -> usize {
+        let j = match &self.samplers[d] {
+            ParentSampler::Uniform(u) => rng.sample(u),
+            ParentSampler::Weighted(w) => rng.sample(w),
+        };
+        d + j * self.samplers.len()
+    }
+}
+
+/// Chooses the parents of each offspring, so that
+/// library users can plug in their own mating schemes.
+///
+/// See [`UniformSelector`] and [`FitnessSelector`].
+pub trait ParentSelector {
+    /// Called once per step by [`death_and_parents`]
+    /// before any parents are chosen.
+    fn prepare(&mut self, _alive: &[Individual], _rng: &mut SimRng) -> Result<(), SimulationError> {
+        Ok(())
+    }
+
+    /// The indexes in `alive` of the two parents of the
+    /// offspring that will replace the diploid at
+    /// `offspring_index`.
+    fn choose(
+        &mut self,
+        alive: &[Individual],
+        offspring_index: usize,
+        rng: &mut SimRng,
+    ) -> (usize, usize);
+}
+
+/// The built-in choice of parents, shared by
+/// [`UniformSelector`] and [`FitnessSelector`].
+struct DemeParents {
+    params: SimParams,
+    samplers: ParentSamplers,
+    /// For sweepstakes reproduction, the fraction `psi`
+    /// and the lucky diploid of each deme for this step.
+    lucky: Option<(f64, Vec<usize>)>,
+}
+
+impl DemeParents {
+    fn new(params: &SimParams, samplers: ParentSamplers) -> Self {
+        Self {
+            params: *params,
+            samplers,
+            lucky: None,
+        }
+    }
+
+    /// For sweepstakes reproduction, draw a fraction `psi`
+    /// from a Beta(2 - alpha, alpha) distribution and one
+    /// lucky diploid per deme.
+    fn prepare(&mut self, rng: &mut SimRng) -> Result<(), SimulationError> {
+        if let Reproduction::Sweepstakes { alpha } = self.params.reproduction {
+            let psi = match Beta::new(2.0 - alpha, alpha) {
+                Ok(b) => rng.sample(b),
+                Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+            };
+            let lucky = (0..self.params.demes as usize)
+                .map(|d| self.samplers.pick(d, rng))
+                .collect::<Vec<_>>();
+            self.lucky = Some((psi, lucky));
+        }
+        Ok(())
+    }
+
+    fn choose(&self, offspring_index: usize, rng: &mut SimRng) -> (usize, usize) {
+        let demes = self.params.demes as usize;
+        let d = deme(offspring_index, &self.params);
+        let parent0 = match &self.lucky {
+            Some((psi, lucky)) if rng.gen::<f64>() < *psi => lucky[d],
+            _ => self.samplers.pick(d, rng),
+        };
+        if self.params.selfing_rate > 0.0 && rng.gen::<f64>() < self.params.selfing_rate {
+            return (parent0, parent0);
+        }
+        let mut d1 = d;
+        if demes > 1 && self.params.migration > 0.0 && rng.gen::<f64>() < self.params.migration {
+            d1 = rng.gen_range(0..demes - 1);
+            if d1 >= d {
+                d1 += 1;
+            }
+        }
+        (parent0, self.samplers.pick(d1, rng))
+    }
+}
+
+/// Parents come uniformly from the offspring's deme,
+/// except that, with probability `params.migration`,
+/// the second parent comes from another deme.  With
+/// probability `params.selfing_rate`, the first parent
+/// is both parents.
+///
+/// With sweepstakes reproduction, each step draws a
+/// fraction `psi` from a Beta(2 - alpha, alpha)
+/// distribution and one lucky diploid per deme.  Each
+/// offspring has the lucky diploid of its deme as its
+/// first parent with probability `psi`.  Smaller values
+/// of `alpha` give larger, more skewed, families.
+pub struct UniformSelector {
+    parents: DemeParents,
+}
+
+impl UniformSelector {
+    pub fn new(params: &SimParams) -> Self {
+        Self {
+            parents: DemeParents::new(params, ParentSamplers::uniform(params)),
+        }
+    }
+}
+
+impl ParentSelector for UniformSelector {
+    fn prepare(&mut self, _alive: &[Individual], rng: &mut SimRng) -> Result<(), SimulationError> {
+        self.parents.prepare(rng)
+    }
+
+    fn choose(
+        &mut self,
+        _alive: &[Individual],
+        offspring_index: usize,
+        rng: &mut SimRng,
+    ) -> (usize, usize) {
+        self.parents.choose(offspring_index, rng)
+    }
+}
+
+/// Like [`UniformSelector`], but parents are chosen
+/// within a deme with probability proportional to their
+/// fitness.
+pub struct FitnessSelector {
+    parents: DemeParents,
+}
+
+impl FitnessSelector {
+    /// `fitness` has one value per diploid in the population.
+    pub fn new(params: &SimParams, fitness: &[f64]) -> Result<Self, SimulationError> {
+        Ok(Self {
+            parents: DemeParents::new(params, ParentSamplers::weighted(params, fitness)?),
+        })
+    }
 }
 
+impl ParentSelector for FitnessSelector {
+    fn prepare(&mut self, _alive: &[Individual], rng: &mut SimRng) -> Result<(), SimulationError> {
+        self.parents.prepare(rng)
+    }
+
+    fn choose(
+        &mut self,
+        _alive: &[Individual],
+        offspring_index: usize,
+        rng: &mut SimRng,
+    ) -> (usize, usize) {
+        self.parents.choose(offspring_index, rng)
+    }
+}
+
+/// For each diploid that dies, record the index it
+/// occupies and a pair of parents, chosen by `selector`,
+/// for its replacement.  Each diploid survives with
+/// probability `params.psurvival`.
+///
+/// The records are appended to `parents`, which callers
+/// clear, and so reuse, between steps.  They are in index
+/// order, or, if `params.shuffle_deaths` is `true`, in an
+/// order drawn first from `rng` by a Fisher-Yates shuffle.
 pub fn death_and_parents(
-    alive: &[Diploid],
+    alive: &[Individual],
     params: &SimParams,
+    selector: &mut dyn ParentSelector,
     parents: &mut Vec<Parents>,
-    rng: &mut StdRng,
-) {
-    let random_parents = Uniform::new(0_usize, params.popsize as usize);
-    for index in 0..alive.len() {
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    deaths(alive, params, |_| params.psurvival, selector, parents, rng)
+}
+
+/// Like [`death_and_parents`], but the diploid at index
+/// `i` survives with probability `schedule.psurvival(ages[i])`
+/// instead of `params.psurvival`.
+pub fn death_by_age_and_parents(
+    alive: &[Individual],
+    ages: &[u32],
+    schedule: &SurvivalSchedule,
+    params: &SimParams,
+    selector: &mut dyn ParentSelector,
+    parents: &mut Vec<Parents>,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    let psurvival = |index: usize| schedule.psurvival(ages[index]);
+    deaths(alive, params, psurvival, selector, parents, rng)
+}
+
+fn deaths(
+    alive: &[Individual],
+    params: &SimParams,
+    psurvival: impl Fn(usize) -> f64,
+    selector: &mut dyn ParentSelector,
+    parents: &mut Vec<Parents>,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    selector.prepare(alive, rng)?;
+    let mut order = (0..alive.len()).collect::<Vec<_>>();
+    if params.shuffle_deaths {
+        order.shuffle(rng);
+    }
+    for index in order {
         let x: f64 = rng.gen();
-        match x.partial_cmp(&params.psurvival) {
-            Some(std::cmp::Ordering::Greater) => {
-                let parent0 = alive[rng.sample(random_parents)];
-                let parent1 = alive[rng.sample(random_parents)];
+        // Survive with probability psurvival, so that
+        // psurvival = 0.0 replaces everyone.
+        match x.partial_cmp(&psurvival(index)) {
+            Some(std::cmp::Ordering::Greater) | Some(std::cmp::Ordering::Equal) => {
+                let (parent0, parent1) = selector.choose(alive, index, rng);
                 parents.push(Parents {
                     index,
-                    parent0,
-                    parent1,
+                    parent0: alive[parent0].clone(),
+                    parent1: alive[parent1].clone(),
+                    parent_indexes: (parent0, parent1),
                 });
             }
             Some(_) => (),
             None => (),
         }
     }
+    Ok(())
+}
+
+/// Sample the parents of the next births with a
+/// [`FitnessSelector`] if `fitness` is `Some`, or a
+/// [`UniformSelector`] otherwise.
+pub fn reproduce(
+    alive: &[Individual],
+    params: &SimParams,
+    fitness: Option<&[f64]>,
+    parents: &mut Vec<Parents>,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    match fitness {
+        Some(fitness) => {
+            let mut selector = FitnessSelector::new(params, fitness)?;
+            death_and_parents(alive, params, &mut selector, parents, rng)
+        }
+        None => {
+            let mut selector = UniformSelector::new(params);
+            death_and_parents(alive, params, &mut selector, parents, rng)
+        }
+    }
+}
+
+/// Grow the population to `popsize` diploids.
+///
+/// For each new index, a pair of parents is chosen
+/// uniformly from the deme of that index and appended
+/// to `parents`.  `alive`, and `genomes` if `Some`, are
+/// extended with placeholders for [`births`] to replace.
+/// `params.popsize` must still be the old size.
+pub fn grow(
+    alive: &mut Vec<Individual>,
+    genomes: Option<&mut Vec<Genomes>>,
+    params: &SimParams,
+    popsize: u32,
+    parents: &mut Vec<Parents>,
+    rng: &mut SimRng,
+) {
+    let samplers = ParentSamplers::uniform(params);
+    for index in alive.len()..popsize as usize {
+        let d = deme(index, params);
+        let (parent0, parent1) = (samplers.pick(d, rng), samplers.pick(d, rng));
+        parents.push(Parents {
+            index,
+            parent0: alive[parent0].clone(),
+            parent1: alive[parent1].clone(),
+            parent_indexes: (parent0, parent1),
+        });
+    }
+    alive.resize(
+        popsize as usize,
+        Individual {
+            nodes: vec![tskit::TSK_NULL; params.ploidy as usize],
+            individual: tskit::TSK_NULL,
+        },
+    );
+    if let Some(genomes) = genomes {
+        genomes.resize(popsize as usize, Genomes::default());
+    }
+}
+
+/// Shrink the population to `popsize` diploids.
+///
+/// Each deme keeps a uniformly chosen subset of its
+/// diploids, in their original order, so that no
+/// diploid changes deme.  `genomes` and `ages`, if
+/// `Some`, are shrunk to match.
+pub fn shrink(
+    alive: &mut Vec<Individual>,
+    genomes: Option<&mut Vec<Genomes>>,
+    ages: Option<&mut Vec<u32>>,
+    params: &SimParams,
+    popsize: u32,
+    rng: &mut SimRng,
+) {
+    let demes = params.demes as usize;
+    // Deme d holds indexes d, d + demes, d + 2 * demes, ...
+    let kept = (0..demes)
+        .map(|d| {
+            let before = (alive.len() - d).div_ceil(demes);
+            let after = (popsize as usize - d).div_ceil(demes);
+            let mut kept = rand::seq::index::sample(rng, before, after).into_vec();
+            kept.sort_unstable();
+            kept
+        })
+        .collect::<Vec<_>>();
+    let old_index = |i: usize| i % demes + kept[i % demes][i / demes] * demes;
+    *alive = (0..popsize as usize)
+        .map(|i| alive[old_index(i)].clone())
+        .collect();
+    if let Some(genomes) = genomes {
+        *genomes = (0..popsize as usize)
+            .map(|i| genomes[old_index(i)].clone())
+            .collect();
+    }
+    if let Some(ages) = ages {
+        *ages = (0..popsize as usize).map(|i| ages[old_index(i)]).collect();
+    }
+}
+
+/// Holds edges recorded since the last simplification,
+/// grouped by parent node.
+///
+/// Offspring are always younger than every node present
+/// at the last simplification, so the buffered edges can
+/// be merged into the (already sorted) edge table in one
+/// pass, which lets [`simplify`] skip sorting the tables.
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct EdgeBuffer {
+    edges: Vec<Vec<BufferedEdge>>,
+    parents: Vec<tskit::tsk_id_t>,
+    len: usize,
+}
+
+#[derive(Copy, Clone, serde::Serialize, serde::Deserialize)]
+struct BufferedEdge {
+    left: f64,
+    right: f64,
+    child: tskit::tsk_id_t,
+}
+
+impl EdgeBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_edge(
+        &mut self,
+        left: f64,
+        right: f64,
+        parent: tskit::tsk_id_t,
+        child: tskit::tsk_id_t,
+    ) {
+        let p = parent as usize;
+        if p >= self.edges.len() {
+            self.edges.resize_with(p + 1, Vec::new);
+        }
+        if self.edges[p].is_empty() {
+            self.parents.push(parent);
+        }
+        self.edges[p].push(BufferedEdge { left, right, child });
+        self.len += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// The number of buffered edges.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    fn emit(
+        &mut self,
+        parent: tskit::tsk_id_t,
+        tables: &mut tskit::TableCollection,
+    ) -> Result<(), tskit::TskitError> {
+        for e in self.edges[parent as usize].drain(..) {
+            tables.add_edge(e.left, e.right, parent, e.child)?;
+        }
+        Ok(())
+    }
+
+    /// Merge the buffered edges into the edge table,
+    /// then empty the buffer.
+    ///
+    /// The edge table must satisfy tskit's edge ordering
+    /// requirements on input, and still does on output:
+    /// a parent's buffered edges follow its existing edges,
+    /// and other parents are inserted in order of birth time.
+    pub fn flush(&mut self, tables: &mut tskit::TableCollection) -> Result<(), tskit::TskitError> {
+        let mut parents = std::mem::take(&mut self.parents);
+        let mut existing = vec![];
+        let mut has_existing_edges = vec![false; self.edges.len()];
+        let mut new_parents = vec![];
+        {
+            let nodes = tables.nodes();
+            let edges = tables.edges();
+            for e in 0..edges.num_rows() as tskit::tsk_id_t {
+                let parent = edges.parent(e)?;
+                if (parent as usize) < has_existing_edges.len() {
+                    has_existing_edges[parent as usize] = true;
+                }
+                existing.push((
+                    edges.left(e)?,
+                    edges.right(e)?,
+                    parent,
+                    edges.child(e)?,
+                    nodes.time(parent)?,
+                ));
+            }
+            for &p in parents.iter() {
+                if !has_existing_edges[p as usize] {
+                    new_parents.push((nodes.time(p)?, p));
+                }
+            }
+        }
+        // Simplification keeps the edge table sorted by parent
+        // time, but sample nodes keep their ids, so parents
+        // born at the same time need not be in id order.
+        // Sort as full_sort does, so that buffering does not
+        // change the output.  The edges are nearly sorted, so
+        // this is much cheaper than sorting the tables.
+        existing.sort_by(|a, b| {
+            a.4.partial_cmp(&b.4)
+                .unwrap()
+                .then(a.2.cmp(&b.2))
+                .then(a.3.cmp(&b.3))
+                .then(a.0.partial_cmp(&b.0).unwrap())
+        });
+        new_parents.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        clear_edge_table(tables)?;
+
+        let mut next = 0;
+        let mut i = 0;
+        while i < existing.len() {
+            let parent = existing[i].2;
+            let parent_time = existing[i].4;
+            // full_sort orders parents by time, then by id.
+            while next < new_parents.len()
+                && (new_parents[next].0 < parent_time
+                    || (new_parents[next].0 == parent_time && new_parents[next].1 < parent))
+            {
+                self.emit(new_parents[next].1, tables)?;
+                next += 1;
+            }
+            while i < existing.len() && existing[i].2 == parent {
+                let (left, right, parent, child, _) = existing[i];
+                tables.add_edge(left, right, parent, child)?;
+                i += 1;
+            }
+            // Offspring have larger ids than the existing
+            // children, so they follow the existing edges.
+            if (parent as usize) < self.edges.len() {
+                self.emit(parent, tables)?;
+            }
+        }
+        while next < new_parents.len() {
+            self.emit(new_parents[next].1, tables)?;
+            next += 1;
+        }
+
+        parents.clear();
+        self.parents = parents;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+fn clear_edge_table(tables: &mut tskit::TableCollection) -> Result<(), tskit::TskitError> {
+    use tskit::TskitTypeAccess;
+    // tskit 0.3.0 has no safe API to clear a single table.
+    let rv = unsafe { tskit::bindings::tsk_edge_table_clear(&mut (*tables.as_mut_ptr()).edges) };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv });
+    }
+    Ok(())
+}
+
+fn record_edge(
+    left: f64,
+    right: f64,
+    parent: tskit::tsk_id_t,
+    child: tskit::tsk_id_t,
+    tables: &mut tskit::TableCollection,
+    edge_buffer: Option<&mut EdgeBuffer>,
+) -> Result<(), tskit::TskitError> {
+    match edge_buffer {
+        Some(buffer) => buffer.add_edge(left, right, parent, child),
+        None => {
+            tables.add_edge(left, right, parent, child)?;
+        }
+    }
+    Ok(())
+}
+
+/// The two homologs of `parent` that a gamete recombines,
+/// in random order.  A diploid parent always uses both.
+fn homologs(parent: &Individual, rng: &mut SimRng) -> (tskit::tsk_id_t, tskit::tsk_id_t) {
+    if let [node0, node1] = parent.nodes[..] {
+        let mut pnodes = (node0, node1);
+        mendel(&mut pnodes, rng);
+        return pnodes;
+    }
+    let pair = rand::seq::index::sample(rng, parent.nodes.len(), 2);
+    (parent.nodes[pair.index(0)], parent.nodes[pair.index(1)])
 }
 
-fn mendel(pnodes: &mut (tskit::tsk_id_t, tskit::tsk_id_t), rng: &mut StdRng) {
+fn mendel(pnodes: &mut (tskit::tsk_id_t, tskit::tsk_id_t), rng: &mut SimRng) {
     let x: f64 = rng.gen();
     match x.partial_cmp(&0.5) {
         Some(std::cmp::Ordering::Less) => {
@@ -73,130 +931,2286 @@ fn mendel(pnodes: &mut (tskit::tsk_id_t, tskit::tsk_id_t), rng: &mut StdRng) {
     }
 }
 
+/// Crossover positions of a renewal process whose
+/// spacing is drawn from `distances`.
+fn renewal_breakpoints<D: Distribution<f64>>(
+    distances: D,
+    sequence_length: f64,
+    breakpoints: &mut Vec<f64>,
+    rng: &mut SimRng,
+) {
+    let mut current_pos = 0.0;
+    loop {
+        let next_length = rng.sample(&distances);
+        match (current_pos + next_length).partial_cmp(&sequence_length) {
+            Some(std::cmp::Ordering::Less) => {
+                current_pos += next_length;
+                breakpoints.push(current_pos);
+            }
+            Some(_) => break,
+            None => panic!("Unexpected None"),
+        }
+    }
+}
+
+/// Add the start and end of each gene conversion tract
+/// to `breakpoints`.  Switching nodes at both ends copies
+/// the tract from the other node, reverting afterwards.
+fn gene_conversion_breakpoints(
+    params: &SimParams,
+    sequence_length: f64,
+    breakpoints: &mut Vec<f64>,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    let ntracts = match Poisson::new(params.gc_rate) {
+        Ok(p) => rng.sample(p) as u64,
+        Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+    };
+    // Geometric counts failures, so add 1 to get a
+    // tract length >= 1 with mean gc_mean_tract.
+    let tract_length = match Geometric::new(1.0 / params.gc_mean_tract) {
+        Ok(g) => g,
+        Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+    };
+    for _ in 0..ntracts {
+        let start = rng.gen_range(0.0..sequence_length);
+        let length = (rng.sample(tract_length) + 1) as f64;
+        breakpoints.push(start);
+        breakpoints.push((start + length).min(sequence_length));
+    }
+    Ok(())
+}
+
+/// Record the edges from one parent's two nodes to
+/// `offspring_node`, switching nodes at each of the
+/// sorted `breakpoints`.
+///
+/// A breakpoint equal to the previous one, to 0.0, or
+/// to the sequence length switches nodes without
+/// recording an empty edge, so repeated positions
+/// cancel out.
+fn record_breakpoint_edges(
+    breakpoints: &[f64],
+    mut pnodes: (tskit::tsk_id_t, tskit::tsk_id_t),
+    offspring_node: tskit::tsk_id_t,
+    tables: &mut tskit::TableCollection,
+    mut edge_buffer: Option<&mut EdgeBuffer>,
+) -> Result<(), tskit::TskitError> {
+    let sequence_length = tables.sequence_length();
+    let mut current_pos = 0.0;
+    for &b in breakpoints {
+        if b > current_pos {
+            record_edge(
+                current_pos,
+                b,
+                pnodes.0,
+                offspring_node,
+                tables,
+                edge_buffer.as_deref_mut(),
+            )?;
+            current_pos = b;
+        }
+        std::mem::swap(&mut pnodes.0, &mut pnodes.1);
+    }
+    if current_pos < sequence_length {
+        record_edge(
+            current_pos,
+            sequence_length,
+            pnodes.0,
+            offspring_node,
+            tables,
+            edge_buffer,
+        )?;
+    }
+    Ok(())
+}
+
+/// Remove pairs of equal positions from the sorted
+/// `breakpoints`.  Switching nodes twice at the same
+/// position has no effect, but would split an edge into
+/// two adjacent edges with the same parent.
+fn cancel_repeated_breakpoints(breakpoints: &mut Vec<f64>) {
+    let mut kept: Vec<f64> = Vec::with_capacity(breakpoints.len());
+    for &b in breakpoints.iter() {
+        if kept.last() == Some(&b) {
+            kept.pop();
+        } else {
+            kept.push(b);
+        }
+    }
+    *breakpoints = kept;
+}
+
+/// Record the edges inherited by `offspring_node` from `parent`,
+/// returning the details of the meiosis.
+///
+/// If `recombination_map` is `Some`, crossovers follow the map
+/// and `params.xovers` is ignored.  Otherwise, crossovers are
+/// uniform along the genome.
 pub fn crossover_and_record_edges_details(
-    parent: Diploid,
+    parent: &Individual,
     offspring_node: tskit::tsk_id_t,
     params: &SimParams,
+    recombination_map: Option<&RecombinationMap>,
     tables: &mut tskit::TableCollection,
-    rng: &mut StdRng,
-) {
-    let mut pnodes = (parent.node0, parent.node1);
-    mendel(&mut pnodes, rng);
+    edge_buffer: Option<&mut EdgeBuffer>,
+    rng: &mut SimRng,
+) -> Result<Meiosis, SimulationError> {
+    let pnodes = homologs(parent, rng);
+    let first = if pnodes.0 == parent.nodes[0] { 0 } else { 1 };
+
+    let sequence_length = tables.sequence_length();
+
+    // Crossovers are placed on [0, extent) at `rate` per unit
+    // length, then mapped to genomic positions if there is a map.
+    let (extent, rate) = match recombination_map {
+        Some(map) => (map.total(), 1.0),
+        None => (sequence_length, params.xovers / sequence_length),
+    };
 
-    if params.xovers == 0.0 {
-        match tables.add_edge(0., tables.sequence_length(), pnodes.0, offspring_node) {
-            Ok(_) => (),
-            Err(e) => panic!("{}", e),
+    let mut breakpoints = vec![];
+    match params.crossover_model {
+        CrossoverModel::Poisson => {
+            if rate > 0.0 {
+                let exp = match Exp::new(rate) {
+                    Ok(e) => e,
+                    Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+                };
+                renewal_breakpoints(exp, extent, &mut breakpoints, rng);
+            }
         }
-    } else {
-        let exp = match Exp::new(params.xovers / tables.sequence_length()) {
-            Ok(e) => e,
-            Err(e) => panic!("{}", e),
-        };
-        let mut current_pos = 0.0;
-        loop {
-            let next_length = rng.sample(exp);
-            match (current_pos + next_length).partial_cmp(&tables.sequence_length()) {
-                Some(std::cmp::Ordering::Less) => {
-                    match tables.add_edge(
-                        current_pos,
-                        current_pos + next_length,
-                        pnodes.0,
-                        offspring_node,
-                    ) {
-                        Ok(_) => (),
-                        Err(e) => panic!("{}", e),
-                    }
-                    std::mem::swap(&mut pnodes.0, &mut pnodes.1);
-                    current_pos += next_length;
-                }
-                Some(_) => {
-                    match tables.add_edge(
-                        current_pos,
-                        tables.sequence_length(),
-                        pnodes.0,
-                        offspring_node,
-                    ) {
-                        Ok(_) => (),
-                        Err(e) => panic!("{}", e),
-                    }
-                    break;
-                }
-                None => panic!("Unexpected None"),
+        CrossoverModel::Gamma { shape } => {
+            if rate > 0.0 {
+                let gamma = match Gamma::new(shape, 1.0 / (rate * shape)) {
+                    Ok(g) => g,
+                    Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+                };
+                renewal_breakpoints(gamma, extent, &mut breakpoints, rng);
+            }
+        }
+        CrossoverModel::Fixed { n_xovers } => {
+            for _ in 0..n_xovers {
+                breakpoints.push(rng.gen_range(0.0..extent));
             }
         }
     }
+
+    if let Some(map) = recombination_map {
+        for b in breakpoints.iter_mut() {
+            *b = map.invert(*b);
+        }
+    }
+
+    if params.gc_rate > 0.0 {
+        gene_conversion_breakpoints(params, sequence_length, &mut breakpoints, rng)?;
+    }
+
+    if params.discrete_genome {
+        for b in breakpoints.iter_mut() {
+            *b = b.floor();
+        }
+    }
+
+    breakpoints.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    if params.discrete_genome {
+        cancel_repeated_breakpoints(&mut breakpoints);
+    }
+
+    record_breakpoint_edges(&breakpoints, pnodes, offspring_node, tables, edge_buffer)?;
+    Ok(Meiosis { first, breakpoints })
 }
 
+/// Record the edges inherited by each of `offspring_nodes`,
+/// from `parents.parent0` for even indexes and from
+/// `parents.parent1` for odd ones, returning the meioses
+/// in the same order.
 pub fn crossover_and_record_edges(
     parents: &Parents,
-    offspring_nodes: (tskit::tsk_id_t, tskit::tsk_id_t),
+    offspring_nodes: &[tskit::tsk_id_t],
     params: &SimParams,
+    recombination_map: Option<&RecombinationMap>,
     tables: &mut tskit::TableCollection,
-    rng: &mut StdRng,
-) {
-    crossover_and_record_edges_details(parents.parent0, offspring_nodes.0, params, tables, rng);
-    crossover_and_record_edges_details(parents.parent1, offspring_nodes.1, params, tables, rng);
-}
-
-pub fn births(
-    parents: &[Parents],
-    params: &SimParams,
-    birth_time: u32,
-    tables: &mut tskit::TableCollection,
-    alive: &mut [Diploid],
-    rng: &mut StdRng,
-) {
-    for p in parents {
-        // Register the two nodes for our offspring
-        let node0 = match tables.add_node(
-            0,                 // flags
-            birth_time as f64, // time
-            tskit::TSK_NULL,   // population
-            // individual
-            tskit::TSK_NULL,
-        ) {
-            Ok(x) => x,
-            Err(e) => panic!("{}", e),
-        };
-        let node1 = match tables.add_node(0, birth_time as f64, tskit::TSK_NULL, tskit::TSK_NULL) {
-            Ok(x) => x,
-            Err(e) => panic!("{}", e),
+    mut edge_buffer: Option<&mut EdgeBuffer>,
+    rng: &mut SimRng,
+) -> Result<Vec<Meiosis>, SimulationError> {
+    let mut meioses = Vec::with_capacity(offspring_nodes.len());
+    for (i, &node) in offspring_nodes.iter().enumerate() {
+        let parent = if i % 2 == 0 {
+            &parents.parent0
+        } else {
+            &parents.parent1
         };
+        meioses.push(crossover_and_record_edges_details(
+            parent,
+            node,
+            params,
+            recombination_map,
+            tables,
+            edge_buffer.as_deref_mut(),
+            rng,
+        )?);
+    }
+    Ok(meioses)
+}
 
-        // Replace a dead individual
-        // with our newborn.
-        alive[p.index] = Diploid { node0, node1 };
+/// Whether meioses can have breakpoints.  If not, every
+/// gamete inherits the whole genome from one parental
+/// node, which [`births`] records in bulk.
+fn recombines(params: &SimParams, recombination_map: Option<&RecombinationMap>) -> bool {
+    let crossovers = match params.crossover_model {
+        CrossoverModel::Fixed { n_xovers } => n_xovers > 0,
+        _ => recombination_map.is_some() || params.xovers > 0.0,
+    };
+    crossovers || params.gc_rate > 0.0
+}
 
-        crossover_and_record_edges(p, (node0, node1), params, tables, rng);
+/// Append an edge over the whole genome from each of
+/// `parents` to the child at the same index, growing the
+/// edge table once rather than row by row.
+fn append_whole_genome_edges(
+    parents: &[tskit::tsk_id_t],
+    children: &[tskit::tsk_id_t],
+    tables: &mut tskit::TableCollection,
+) -> Result<(), tskit::TskitError> {
+    use tskit::TskitTypeAccess;
+    let left = vec![0.0; children.len()];
+    let right = vec![tables.sequence_length(); children.len()];
+    // tskit 0.3.0 has no safe API for this.
+    let rv = unsafe {
+        tskit::bindings::tsk_edge_table_append_columns(
+            &mut (*tables.as_mut_ptr()).edges,
+            children.len() as tskit::tsk_size_t,
+            left.as_ptr(),
+            right.as_ptr(),
+            parents.as_ptr(),
+            children.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv });
     }
+    Ok(())
 }
 
-pub fn simplify(alive: &mut [Diploid], tables: &mut tskit::TableCollection) {
-    let mut samples = vec![];
-    for a in alive.iter() {
-        assert!(a.node0 != a.node1);
-        samples.push(a.node0);
-        samples.push(a.node1);
+/// The times of `n` births in the step at `birth_time`.
+///
+/// With `params.continuous_time`, the births are events
+/// separated by exponential waiting times, scaled so that
+/// the `n + 1` waits span the unit of time before
+/// `birth_time + 1`.  These are the event times of a
+/// Poisson process on that interval given `n` events,
+/// with earlier births older.  Every time is below
+/// `birth_time + 1`, so offspring are strictly younger
+/// than parents born in earlier steps.  Otherwise, all
+/// births are at `birth_time`.
+fn birth_times(
+    n: usize,
+    birth_time: u64,
+    params: &SimParams,
+    rng: &mut SimRng,
+) -> Result<Vec<f64>, SimulationError> {
+    if !params.continuous_time {
+        return Ok(vec![birth_time as f64; n]);
     }
-
-    match tables.full_sort(tskit::TableSortOptions::default()) {
-        Ok(_) => (),
-        Err(e) => panic!("{}", e),
+    let exp = match Exp::new(1.0) {
+        Ok(e) => e,
+        Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+    };
+    let waits = (0..=n).map(|_| exp.sample(rng)).collect::<Vec<f64>>();
+    let total: f64 = waits.iter().sum();
+    let upper = birth_time as f64 + 1.0;
+    let mut elapsed = 0.0;
+    let mut times = Vec::with_capacity(n);
+    for w in &waits[..n] {
+        elapsed += w;
+        let t = upper - elapsed / total;
+        times.push(if t < upper {
+            t.max(birth_time as f64)
+        } else {
+            upper * (1.0 - f64::EPSILON)
+        });
     }
+    Ok(times)
+}
+
+/// Add a diploid for each of `parents`, replacing
+/// the one at `p.index` in `alive`.
+///
+/// If `genomes` is `Some`, it holds the selected mutations
+/// of each diploid in `alive`.  Offspring inherit their
+/// parents' selected mutations and gain new ones at rate
+/// `params.selected_mutrate`.
+///
+/// Without recombination or an `edge_buffer`, each
+/// offspring node gets one edge, and the step's edges are
+/// appended to the edge table together at the end, in the
+/// order they would otherwise have been added.
+#[allow(clippy::too_many_arguments)]
+pub fn births(
+    parents: &[Parents],
+    params: &SimParams,
+    recombination_map: Option<&RecombinationMap>,
+    birth_time: u64,
+    tables: &mut tskit::TableCollection,
+    alive: &mut [Individual],
+    mut genomes: Option<&mut Vec<Genomes>>,
+    mut edge_buffer: Option<&mut EdgeBuffer>,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    // Parents may be replaced within this step,
+    // so inherit from a copy.
+    let parent_genomes = genomes.as_deref().cloned();
+    let metadata = if params.node_metadata {
+        Some(NodeMetadata {
+            birth_step: birth_time,
+        })
+    } else {
+        None
+    };
+    let times = birth_times(parents.len(), birth_time, params, rng)?;
+    // The parent and child of each whole-genome edge,
+    // if they are appended in bulk.
+    let mut whole_genome_edges = if edge_buffer.is_none() && !recombines(params, recombination_map)
+    {
+        let n = parents.len() * params.ploidy as usize;
+        Some((Vec::with_capacity(n), Vec::with_capacity(n)))
+    } else {
+        None
+    };
+    for (p, &time) in parents.iter().zip(times.iter()) {
+        let individual = if params.record_individuals {
+            tables.add_individual(0, &[], &[p.parent0.individual, p.parent1.individual])?
+        } else {
+            tskit::TSK_NULL
+        };
+
+        // Register the nodes for our offspring
+        let population = deme(p.index, params) as tskit::tsk_id_t;
+        let mut nodes = Vec::with_capacity(params.ploidy as usize);
+        for _ in 0..params.ploidy {
+            nodes.push(tables.add_node_with_metadata(
+                0,    // flags
+                time, // time
+                population,
+                individual,
+                metadata.as_ref().map(|m| m as _),
+            )?);
+        }
 
-    match tables.simplify(&samples, tskit::SimplificationOptions::empty(), true) {
-        Ok(x) => match x {
-            Some(idmap) => {
-                for a in alive.iter_mut() {
-                    a.node0 = idmap[a.node0 as usize];
-                    assert!(a.node0 != tskit::TSK_NULL);
-                    a.node1 = idmap[a.node1 as usize];
-                    assert!(a.node1 != tskit::TSK_NULL);
+        let meioses = match whole_genome_edges.as_mut() {
+            Some((edge_parents, edge_children)) => {
+                let mut meioses = Vec::with_capacity(nodes.len());
+                for (i, &node) in nodes.iter().enumerate() {
+                    let parent = if i % 2 == 0 { &p.parent0 } else { &p.parent1 };
+                    let pnodes = homologs(parent, rng);
+                    edge_parents.push(pnodes.0);
+                    edge_children.push(node);
+                    meioses.push(Meiosis {
+                        first: if pnodes.0 == parent.nodes[0] { 0 } else { 1 },
+                        breakpoints: vec![],
+                    });
                 }
+                meioses
             }
-            None => panic!("Unexpected None"),
-        },
-        Err(e) => panic!("{}", e),
+            None => crossover_and_record_edges(
+                p,
+                &nodes,
+                params,
+                recombination_map,
+                tables,
+                edge_buffer.as_deref_mut(),
+                rng,
+            )?,
+        };
+
+        if let (Some(genomes), Some(parent_genomes)) = (genomes.as_deref_mut(), &parent_genomes) {
+            let mut offspring = [
+                selection::inherit(&parent_genomes[p.parent_indexes.0], &meioses[0]),
+                selection::inherit(&parent_genomes[p.parent_indexes.1], &meioses[1]),
+            ];
+            if params.selected_mutrate > 0.0 {
+                for (genome, &node) in offspring.iter_mut().zip(nodes.iter()) {
+                    selection::mutate(genome, node, time, params.selected_mutrate, tables, rng)?;
+                }
+            }
+            genomes[p.index] = offspring;
+        }
+
+        // Replace a dead individual
+        // with our newborn.
+        alive[p.index] = Individual { nodes, individual };
+    }
+    if let Some((edge_parents, edge_children)) = whole_genome_edges {
+        append_whole_genome_edges(&edge_parents, &edge_children, tables)?;
+    }
+    Ok(())
+}
+
+/// Simplify the tables with respect to the alive nodes,
+/// remapping `alive` to the new node ids.
+///
+/// Returns the map from old to new node ids, so that
+/// callers can remap their own nodes.  Nodes that are
+/// not kept map to [`tskit::TSK_NULL`].
+///
+/// If `edge_buffer` is `Some`, its edges are merged into
+/// the edge table and only the sites and mutations are
+/// sorted.  Otherwise, the tables are sorted first.
+///
+/// `options` are passed to tskit as they are, so sites,
+/// populations and individuals are only removed if the
+/// matching filter is set.  With
+/// [`KEEP_UNARY`](tskit::SimplificationOptions::KEEP_UNARY),
+/// nodes that are ancestral to the samples but are not
+/// coalescences are kept, with their edges, so that each
+/// lineage records every ancestor it passes through.  The
+/// tables can be much larger.
+///
+/// With
+/// [`FILTER_INDIVIDUALS`](tskit::SimplificationOptions::FILTER_INDIVIDUALS),
+/// the `individual` of each diploid in `alive` is remapped
+/// too.  With
+/// [`FILTER_POPULATIONS`](tskit::SimplificationOptions::FILTER_POPULATIONS),
+/// a deme's population may be dropped or renumbered, so
+/// [`births`] into the tables are only correct if every
+/// deme still has alive nodes.
+pub fn simplify(
+    alive: &mut [Individual],
+    tables: &mut tskit::TableCollection,
+    edge_buffer: Option<&mut EdgeBuffer>,
+    options: tskit::SimplificationOptions,
+) -> Result<Vec<tskit::tsk_id_t>, SimulationError> {
+    simplify_with_ancient_samples(alive, &mut [], tables, edge_buffer, options)
+}
+
+/// Like [`simplify`], but also keeps `ancient_samples`
+/// as samples, remapping them to the new node ids.
+///
+/// The alive nodes come first in the output, followed by
+/// the ancient samples that are not alive.
+pub fn simplify_with_ancient_samples(
+    alive: &mut [Individual],
+    ancient_samples: &mut [tskit::tsk_id_t],
+    tables: &mut tskit::TableCollection,
+    edge_buffer: Option<&mut EdgeBuffer>,
+    options: tskit::SimplificationOptions,
+) -> Result<Vec<tskit::tsk_id_t>, SimulationError> {
+    check_alive(alive, tables)?;
+    let num_nodes = tables.nodes().num_rows() as tskit::tsk_id_t;
+    if let Some(&u) = ancient_samples.iter().find(|&&u| u < 0 || u >= num_nodes) {
+        return Err(SimulationError::Invariant(format!(
+            "ancient sample {} is not a node",
+            u
+        )));
+    }
+
+    let mut samples = alive_sample_nodes(alive);
+    if !ancient_samples.is_empty() {
+        // With overlapping generations, an ancient sample
+        // may still be alive, and tskit rejects duplicates.
+        let mut is_sample = vec![false; tables.nodes().num_rows() as usize];
+        for &u in samples.iter() {
+            is_sample[u as usize] = true;
+        }
+        for &u in ancient_samples.iter() {
+            if !is_sample[u as usize] {
+                is_sample[u as usize] = true;
+                samples.push(u);
+            }
+        }
+    }
+
+    match edge_buffer {
+        Some(buffer) => {
+            buffer.flush(tables)?;
+            // The edges are already sorted, but sort the
+            // sites, mutations and individuals as full_sort
+            // does, so that the output does not depend on
+            // whether edges were buffered.
+            let mut bookmark = tskit::types::Bookmark::new();
+            bookmark.offsets.edges = tables.edges().num_rows();
+            tables.sort(&bookmark, tskit::TableSortOptions::default())?;
+        }
+        None => {
+            tables.full_sort(tskit::TableSortOptions::default())?;
+        }
+    }
+
+    let idmap = match tables.simplify(&samples, options, true)? {
+        Some(idmap) => {
+            // Samples are always kept, so these only fail
+            // if tskit does not behave as documented.
+            let remap = |u: tskit::tsk_id_t| match idmap[u as usize] {
+                tskit::TSK_NULL => Err(SimulationError::Invariant(format!(
+                    "sample node {} was removed by simplification",
+                    u
+                ))),
+                v => Ok(v),
+            };
+            for a in alive.iter_mut() {
+                for u in a.nodes.iter_mut() {
+                    *u = remap(*u)?;
+                }
+            }
+            for u in ancient_samples.iter_mut() {
+                *u = remap(*u)?;
+            }
+            idmap.to_vec()
+        }
+        None => {
+            return Err(SimulationError::Invariant(String::from(
+                "simplification did not return a node id map",
+            )))
+        }
     };
+
+    if options.contains(tskit::SimplificationOptions::FILTER_INDIVIDUALS) {
+        let nodes = tables.nodes();
+        for a in alive.iter_mut() {
+            if a.individual != tskit::TSK_NULL {
+                a.individual = nodes.individual(a.nodes[0])?;
+            }
+        }
+    }
+    Ok(idmap)
+}
+
+/// Simplify `tables` down to `sample_size` nodes
+/// chosen uniformly, without replacement, from `samples`,
+/// and rebuild the index if it existed on input.  Other
+/// sample nodes, such as ancient samples, are kept as well.
+///
+/// Returns the new id of each node in `samples`, or
+/// [`tskit::TSK_NULL`] if it was not chosen.  `options`
+/// are passed to tskit as they are, as by [`simplify`].
+///
+/// # Errors
+///
+/// [`SimulationError::InvalidParameters`] if `sample_size`
+/// is larger than `samples`.
+pub fn subsample(
+    tables: &mut tskit::TableCollection,
+    samples: &[tskit::tsk_id_t],
+    sample_size: usize,
+    options: tskit::SimplificationOptions,
+    rng: &mut SimRng,
+) -> Result<Vec<tskit::tsk_id_t>, SimulationError> {
+    if sample_size > samples.len() {
+        return Err(SimulationError::InvalidParameters(format!(
+            "cannot subsample {} of {} sample nodes",
+            sample_size,
+            samples.len()
+        )));
+    }
+    let mut chosen = rand::seq::index::sample(rng, samples.len(), sample_size).into_vec();
+    chosen.sort_unstable();
+    let mut keep: Vec<tskit::tsk_id_t> = chosen.iter().map(|&i| samples[i]).collect();
+
+    let mut in_samples = vec![false; tables.nodes().num_rows() as usize];
+    for &u in samples {
+        in_samples[u as usize] = true;
+    }
+    for u in tables.nodes().samples_as_vector() {
+        if !in_samples[u as usize] {
+            keep.push(u);
+        }
+    }
+
+    let indexed = tables.is_indexed();
+    let idmap = match tables.simplify(&keep, options, true)? {
+        Some(idmap) => idmap.to_vec(),
+        None => {
+            return Err(SimulationError::Invariant(String::from(
+                "simplification did not return a node id map",
+            )))
+        }
+    };
+    if indexed {
+        tables.build_index()?;
+    }
+    Ok(samples.iter().map(|&u| idmap[u as usize]).collect())
+}
+
+/// Check that each diploid in `alive` has two distinct
+/// nodes that exist in `tables`, and that no node
+/// belongs to two diploids.
+///
+/// [`simplify`] and [`simplify_with_ancient_samples`]
+/// always run this check, since a violation would give
+/// wrong output rather than a crash.  With
+/// [`DiploidSimulation::set_strict`], it also runs after
+/// every step, so that a violation is caught where it
+/// happens.  It needs one flag per node, so it is not
+/// free.
+///
+/// # Errors
+///
+/// [`SimulationError::Invariant`] naming the first
+/// diploid that fails.
+pub fn check_alive(
+    alive: &[Individual],
+    tables: &tskit::TableCollection,
+) -> Result<(), SimulationError> {
+    let num_nodes = tables.nodes().num_rows() as usize;
+    let mut seen = vec![false; num_nodes];
+    for (i, a) in alive.iter().enumerate() {
+        for (j, &u) in a.nodes.iter().enumerate() {
+            if a.nodes[..j].contains(&u) {
+                return Err(SimulationError::Invariant(format!(
+                    "diploid {} has the same node, {}, twice",
+                    i, u
+                )));
+            }
+            if u < 0 || u as usize >= num_nodes {
+                return Err(SimulationError::Invariant(format!(
+                    "diploid {} has node {}, but there are {} nodes",
+                    i, u, num_nodes
+                )));
+            }
+            if seen[u as usize] {
+                return Err(SimulationError::Invariant(format!(
+                    "node {} of diploid {} also belongs to another diploid",
+                    u, i
+                )));
+            }
+            seen[u as usize] = true;
+        }
+    }
+    Ok(())
+}
+
+/// Check that `alive` has `params.popsize` individuals,
+/// each with `params.ploidy` nodes, so that it has
+/// [`SimParams::num_alive_nodes`] nodes.  Parents are
+/// chosen by index in `0..params.popsize`, so the two
+/// must not drift apart.
+///
+/// # Errors
+///
+/// [`SimulationError::Invariant`] if the sizes differ.
+pub fn check_popsize(alive: &[Individual], params: &SimParams) -> Result<(), SimulationError> {
+    if alive.len() != params.popsize as usize {
+        return Err(SimulationError::Invariant(format!(
+            "there are {} individuals alive, but popsize is {}",
+            alive.len(),
+            params.popsize
+        )));
+    }
+    if let Some((i, a)) = alive
+        .iter()
+        .enumerate()
+        .find(|(_, a)| a.nodes.len() != params.ploidy as usize)
+    {
+        return Err(SimulationError::Invariant(format!(
+            "individual {} has {} nodes, but ploidy is {}",
+            i,
+            a.nodes.len(),
+            params.ploidy
+        )));
+    }
+    Ok(())
+}
+
+/// Sort `tables` into tskit's canonical order, which,
+/// unlike [`tskit::TableCollection::full_sort`], also
+/// puts mutations and individuals in an order that does
+/// not depend on the order they were added in.  The
+/// `individual` of each diploid in `alive` is remapped.
+///
+/// Node ids do not change, and rows that nothing refers
+/// to are kept.  The tables must be indexed again.
+pub fn sort_canonical(
+    alive: &mut [Individual],
+    tables: &mut tskit::TableCollection,
+) -> Result<(), SimulationError> {
+    use tskit::TskitTypeAccess;
+
+    // tskit 0.3.0 has no safe API for this.
+    let rv = unsafe {
+        tskit::bindings::tsk_table_collection_canonicalise(
+            tables.as_mut_ptr(),
+            tskit::bindings::TSK_KEEP_UNREFERENCED,
+        )
+    };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv }.into());
+    }
+    let nodes = tables.nodes();
+    for a in alive.iter_mut() {
+        if a.individual != tskit::TSK_NULL {
+            a.individual = nodes.individual(a.nodes[0])?;
+        }
+    }
+    Ok(())
+}
+
+/// Get `tables` ready for output without simplifying:
+/// merge in `edge_buffer`, if it is `Some`, make the
+/// alive nodes and `ancient_samples` the only samples,
+/// and sort the tables.
+///
+/// Nodes born since the last simplification are kept
+/// even if they have no descendants among the samples.
+pub fn sort_unsimplified(
+    alive: &[Individual],
+    ancient_samples: &[tskit::tsk_id_t],
+    tables: &mut tskit::TableCollection,
+    edge_buffer: Option<&mut EdgeBuffer>,
+) -> Result<(), tskit::TskitError> {
+    if let Some(buffer) = edge_buffer {
+        buffer.flush(tables)?;
+    }
+    {
+        let mut nodes = tables.nodes();
+        let flags = nodes.flags_array_mut();
+        for f in flags.iter_mut() {
+            *f &= !tskit::TSK_NODE_IS_SAMPLE;
+        }
+        for u in alive_sample_nodes(alive) {
+            flags[u as usize] |= tskit::TSK_NODE_IS_SAMPLE;
+        }
+        for &u in ancient_samples {
+            flags[u as usize] |= tskit::TSK_NODE_IS_SAMPLE;
+        }
+    }
+    tables.full_sort(tskit::TableSortOptions::default())?;
+    Ok(())
+}
+
+/// Add one population per deme, and `params.popsize`
+/// founders born at time `params.nsteps`, to `tables`.
+/// Each population's metadata is a [`PopulationMetadata`]
+/// naming its deme, `deme_0` and so on.
+///
+/// If `params.node_metadata` is `true`, this also
+/// registers the node metadata schema.
+pub fn founders(
+    params: &SimParams,
+    tables: &mut tskit::TableCollection,
+) -> Result<Vec<Individual>, tskit::TskitError> {
+    set_population_metadata_schema(tables)?;
+    for d in 0..params.demes as usize {
+        tables.add_population_with_metadata(Some(&PopulationMetadata::deme(d)))?;
+    }
+
+    let metadata = if params.node_metadata {
+        set_node_metadata_schema(tables)?;
+        Some(NodeMetadata {
+            birth_step: params.nsteps,
+        })
+    } else {
+        None
+    };
+
+    let mut alive: Vec<Individual> = vec![];
+    for index in 0..params.popsize as usize {
+        let individual = if params.record_individuals {
+            tables.add_individual(0, &[], &[])?
+        } else {
+            tskit::TSK_NULL
+        };
+        let population = deme(index, params) as tskit::tsk_id_t;
+        let mut nodes = Vec::with_capacity(params.ploidy as usize);
+        for _ in 0..params.ploidy {
+            nodes.push(tables.add_node_with_metadata(
+                0,
+                params.nsteps as f64,
+                population,
+                individual,
+                metadata.as_ref().map(|m| m as _),
+            )?);
+        }
+        alive.push(Individual { nodes, individual });
+    }
+    Ok(alive)
+}
+
+/// Use the sample nodes of existing `tables`, such as
+/// the output of an earlier run, as the founders.
+///
+/// Each run of `params.ploidy` consecutive sample nodes,
+/// in order of node id, forms an individual.  All times are shifted so that the youngest
+/// node is at `params.nsteps`, the birth time of
+/// [`founders`].  Populations are added if there are
+/// fewer than `params.demes`, and named as by [`founders`]
+/// unless the existing populations use another metadata
+/// schema.  If `params.node_metadata`
+/// is `true`, the node metadata schema is registered, but
+/// existing nodes keep their metadata.
+///
+/// # Errors
+///
+/// [`SimulationError::InvalidParameters`] if the sequence
+/// length of `tables` is not `params.genome_length`, which
+/// new edges would span, if the number of sample nodes is
+/// not `params.ploidy * params.popsize`, or if a node time
+/// is not finite.
+pub fn founders_from_tables(
+    params: &SimParams,
+    tables: &mut tskit::TableCollection,
+) -> Result<Vec<Individual>, SimulationError> {
+    if tables.sequence_length() != params.genome_length {
+        return Err(SimulationError::InvalidParameters(format!(
+            "the tables have sequence length {}, but genome_length is {}",
+            tables.sequence_length(),
+            params.genome_length
+        )));
+    }
+
+    let samples = tables.nodes().samples_as_vector();
+    let ploidy = params.ploidy as usize;
+    if samples.len() != params.num_alive_nodes() {
+        return Err(SimulationError::InvalidParameters(format!(
+            "the tables have {} sample nodes, but popsize {} with ploidy {} needs {}",
+            samples.len(),
+            params.popsize,
+            params.ploidy,
+            params.num_alive_nodes()
+        )));
+    }
+
+    let mut youngest = f64::INFINITY;
+    let nodes = tables.nodes();
+    for u in 0..nodes.num_rows() as tskit::tsk_id_t {
+        let t = nodes.time(u)?;
+        if !t.is_finite() {
+            return Err(SimulationError::InvalidParameters(format!(
+                "the tables have a node with time {}",
+                t
+            )));
+        }
+        youngest = youngest.min(t);
+    }
+    shift_times(tables, params.nsteps as f64 - youngest);
+
+    let existing = tables.populations().num_rows();
+    let named = existing == 0 || has_population_metadata_schema(tables);
+    if existing == 0 {
+        set_population_metadata_schema(tables)?;
+    }
+    for d in existing..params.demes as tskit::tsk_size_t {
+        if named {
+            tables.add_population_with_metadata(Some(&PopulationMetadata::deme(d as usize)))?;
+        } else {
+            tables.add_population()?;
+        }
+    }
+    if params.node_metadata {
+        set_node_metadata_schema(tables)?;
+    }
+
+    let nodes = tables.nodes();
+    let mut alive = vec![];
+    for group in samples.chunks(ploidy) {
+        let individual = nodes.individual(group[0])?;
+        let mut shared = true;
+        for &u in &group[1..] {
+            shared &= nodes.individual(u)? == individual;
+        }
+        alive.push(Individual {
+            nodes: group.to_vec(),
+            individual: if params.record_individuals && shared {
+                individual
+            } else {
+                tskit::TSK_NULL
+            },
+        });
+    }
+    Ok(alive)
+}
+
+/// A callback for [`DiploidSimulation::set_on_step`],
+/// given the birth time of the step, the tables, and the
+/// alive individuals.
+pub type OnStep = Box<dyn FnMut(u64, &tskit::TableCollection, &[Individual])>;
+
+/// A diploid simulation that owns its tables,
+/// population, and random number generator.
+///
+/// Calling [`DiploidSimulation::step`] repeatedly
+/// lets callers record their own statistics between
+/// birth steps.  [`DiploidSimulation::run`] does all
+/// `nsteps` steps and returns the final tables.
+pub struct DiploidSimulation {
+    params: SimParams,
+    tables: tskit::TableCollection,
+    alive: Vec<Individual>,
+    /// The selected mutations of each diploid in `alive`,
+    /// if `params.selected_mutrate > 0.0`.
+    genomes: Option<Vec<Genomes>>,
+    /// The number of steps each diploid in `alive` has
+    /// survived, 0 at birth.
+    ages: Vec<u32>,
+    /// If `Some`, used instead of `params.psurvival`.
+    survival_schedule: Option<SurvivalSchedule>,
+    fitness: Vec<f64>,
+    /// Nodes kept as samples at each simplification,
+    /// see [`SimParams::sample_every`].
+    ancient_samples: Vec<tskit::tsk_id_t>,
+    /// Built once, and used when there is no selection.
+    uniform_selector: UniformSelector,
+    /// Cleared and refilled at each step.
+    parents: Vec<Parents>,
+    recombination_map: Option<RecombinationMap>,
+    /// If `Some`, used instead of `params.mutrate`.
+    mutation_map: Option<MutationMap>,
+    edge_buffer: Option<EdgeBuffer>,
+    seed: u64,
+    rng: SimRng,
+    /// If `Some`, used for parents and births instead of `rng`.
+    substreams: Option<Substreams>,
+    /// Steps left for [`DiploidSimulation::run`] to do.
+    remaining_steps: u64,
+    checkpoint_interval: u32,
+    checkpoint_prefix: String,
+    show_progress: bool,
+    /// If `false`, the last step does not simplify.
+    final_simplify: bool,
+    /// Run [`check_alive`] after every step.
+    strict: bool,
+    /// Passed to [`simplify_with_ancient_samples`].
+    simplification_options: tskit::SimplificationOptions,
+    /// Added to `simplification_options` at the last step.
+    output_filters: tskit::SimplificationOptions,
+    /// Stop [`DiploidSimulation::run`] at the first
+    /// simplification after this much time has passed.
+    max_duration: Option<std::time::Duration>,
+    /// If `Some`, simplify when there are more edges than
+    /// this, rather than every `simplification_interval`.
+    simplify_when_edges: Option<usize>,
+    /// Whether the last step ended with a simplification.
+    simplified: bool,
+    /// Apply [`sort_canonical`] before simplifying.
+    canonical_sort: bool,
+    /// Index the tables at the end of [`DiploidSimulation::run`].
+    build_index: bool,
+    /// Called at each step, before any simplification.
+    on_step: Option<OnStep>,
+    /// Gets a `step,num_nodes,num_edges` row at each step.
+    table_size_log: Option<std::io::BufWriter<std::fs::File>>,
+    /// Gets a `step,position,frequency` row at each step
+    /// once `trajectory_position` is chosen.
+    trajectory: Option<std::io::BufWriter<std::fs::File>>,
+    /// The selected mutation whose frequency is written
+    /// to `trajectory`.
+    trajectory_position: Option<f64>,
+    /// `params.popsize` is updated at each change.
+    demography: Demography,
+}
+
+impl DiploidSimulation {
+    /// Create the founder population with [`founders`].
+    pub fn new(params: SimParams, seed: u64) -> Result<Self, SimulationError> {
+        params
+            .validate()
+            .map_err(SimulationError::InvalidParameters)?;
+
+        let mut tables = tskit::TableCollection::new(params.genome_length)?;
+        let alive = founders(&params, &mut tables)?;
+
+        Ok(Self::with_population(params, seed, tables, alive))
+    }
+
+    /// Continue from existing `tables`, using their
+    /// sample nodes as the founders via [`founders_from_tables`].
+    pub fn start_from(
+        params: SimParams,
+        seed: u64,
+        mut tables: tskit::TableCollection,
+    ) -> Result<Self, SimulationError> {
+        params
+            .validate()
+            .map_err(SimulationError::InvalidParameters)?;
+
+        let alive = founders_from_tables(&params, &mut tables)?;
+
+        Ok(Self::with_population(params, seed, tables, alive))
+    }
+
+    fn with_population(
+        params: SimParams,
+        seed: u64,
+        tables: tskit::TableCollection,
+        alive: Vec<Individual>,
+    ) -> Self {
+        Self {
+            params,
+            tables,
+            alive,
+            genomes: if params.selected_mutrate > 0.0 {
+                Some(vec![Genomes::default(); params.popsize as usize])
+            } else {
+                None
+            },
+            ages: vec![0; params.popsize as usize],
+            survival_schedule: None,
+            fitness: vec![],
+            ancient_samples: vec![],
+            uniform_selector: UniformSelector::new(&params),
+            parents: vec![],
+            recombination_map: None,
+            mutation_map: None,
+            edge_buffer: if params.buffer_edges {
+                Some(EdgeBuffer::new())
+            } else {
+                None
+            },
+            seed,
+            rng: SimRng::seed_from_u64(seed),
+            substreams: None,
+            remaining_steps: params.nsteps,
+            checkpoint_interval: 0,
+            checkpoint_prefix: String::new(),
+            show_progress: false,
+            final_simplify: true,
+            strict: false,
+            // Drop the sites of selected mutations that have been lost.
+            simplification_options: tskit::SimplificationOptions::FILTER_SITES,
+            output_filters: tskit::SimplificationOptions::FILTER_POPULATIONS
+                | tskit::SimplificationOptions::FILTER_INDIVIDUALS,
+            max_duration: None,
+            simplify_when_edges: None,
+            simplified: false,
+            canonical_sort: false,
+            build_index: true,
+            on_step: None,
+            table_size_log: None,
+            trajectory: None,
+            trajectory_position: None,
+            demography: Demography::default(),
+        }
+    }
+
+    /// Resume from a checkpoint written by a simulation
+    /// with the same `params`.
+    ///
+    /// # Errors
+    ///
+    /// [`SimulationError::InvalidParameters`] if `params`
+    /// does not match the checkpoint's population size
+    /// or genome length.
+    pub fn from_checkpoint(params: SimParams, prefix: &str) -> Result<Self, SimulationError> {
+        Self::from_checkpoint_with_demography(params, Demography::default(), prefix)
+    }
+
+    /// Like [`DiploidSimulation::from_checkpoint`], for a
+    /// simulation with `demography`.  The checkpoint's
+    /// population size must be the one that `demography`
+    /// gives at its step, starting from `params.popsize`.
+    pub fn from_checkpoint_with_demography(
+        params: SimParams,
+        demography: Demography,
+        prefix: &str,
+    ) -> Result<Self, SimulationError> {
+        demography
+            .validate(&params)
+            .map_err(SimulationError::InvalidParameters)?;
+        let (checkpoint, tables) = Checkpoint::read(prefix)?;
+        let mut params = params;
+        params.popsize = demography.popsize_after(params.popsize, checkpoint.step);
+        let mut sim = Self::new(params, checkpoint.seed)?;
+        sim.demography = demography;
+        if checkpoint.alive.len() != params.popsize as usize
+            || tables.sequence_length() != params.genome_length
+        {
+            return Err(SimulationError::InvalidParameters(format!(
+                "checkpoint {} has popsize {} and genome_length {}, but the parameters have {} and {}",
+                prefix,
+                checkpoint.alive.len(),
+                tables.sequence_length(),
+                params.popsize,
+                params.genome_length
+            )));
+        }
+        sim.tables = tables;
+        sim.alive = checkpoint.alive;
+        if sim.genomes.is_some() {
+            if checkpoint.genomes.len() != sim.alive.len() {
+                return Err(SimulationError::InvalidParameters(format!(
+                    "checkpoint {} has no selected mutations, but selected_mutrate is {}",
+                    prefix, params.selected_mutrate
+                )));
+            }
+            sim.genomes = Some(checkpoint.genomes);
+        }
+        sim.rng.set_word_pos(checkpoint.rng_word_pos);
+        sim.substreams = checkpoint
+            .substreams_word_pos
+            .map(|word_pos| Substreams::at_word_pos(sim.seed, word_pos));
+        match (sim.edge_buffer.is_some(), checkpoint.edge_buffer) {
+            (true, buffer) => sim.edge_buffer = Some(buffer.unwrap_or_default()),
+            (false, Some(_)) => {
+                return Err(SimulationError::InvalidParameters(format!(
+                    "checkpoint {} has buffered edges, but buffer_edges is false",
+                    prefix
+                )))
+            }
+            (false, None) => (),
+        }
+        sim.remaining_steps = checkpoint.step;
+        sim.ancient_samples = checkpoint.ancient_samples;
+        // Checkpoints from before ages were tracked have none.
+        if checkpoint.ages.len() == sim.alive.len() {
+            sim.ages = checkpoint.ages;
+        }
+        Ok(sim)
+    }
+
+    /// Have [`DiploidSimulation::run`] write a checkpoint
+    /// with `prefix` after every `interval` steps.
+    /// An `interval` of 0 turns checkpointing off.
+    ///
+    /// Writing a checkpoint does not change the simulation,
+    /// so a run resumed from one gives the same output as
+    /// an uninterrupted run, whatever the `interval`.
+    pub fn set_checkpointing(&mut self, interval: u32, prefix: &str) {
+        self.checkpoint_interval = interval;
+        self.checkpoint_prefix = prefix.to_string();
+    }
+
+    /// Have [`DiploidSimulation::run`] report its progress,
+    /// and an estimate of the time remaining, to stderr.
+    pub fn set_progress(&mut self, show_progress: bool) {
+        self.show_progress = show_progress;
+    }
+
+    /// If `false`, [`DiploidSimulation::run`] skips the
+    /// simplification at the last step and returns the
+    /// tables prepared by [`sort_unsimplified`], so that
+    /// every node and edge from the last simplification
+    /// interval is kept.  The output can be much larger:
+    /// up to the [`estimate_memory`](crate::estimate::estimate_memory)
+    /// peak.  Defaults to `true`.
+    pub fn set_final_simplify(&mut self, final_simplify: bool) {
+        self.final_simplify = final_simplify;
+    }
+
+    /// If `true`, run [`check_alive`] after every step,
+    /// not only before each simplification.  This costs
+    /// one pass over a flag per node at each step.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// If `true`, choose parents and do births with the
+    /// separate generators of [`Substreams`], derived from
+    /// the seed, rather than the one generator.  This changes
+    /// the output for a given seed.  Mutations are still
+    /// overlaid with the one generator.  Defaults to `false`.
+    /// Generators restored from a checkpoint are kept.
+    pub fn set_substreams(&mut self, substreams: bool) {
+        if !substreams {
+            self.substreams = None;
+        } else if self.substreams.is_none() {
+            self.substreams = Some(Substreams::new(self.seed));
+        }
+    }
+
+    /// Change the population size at the steps in
+    /// `demography`.  At a change, the population grows
+    /// by [`grow`], with births at that step, or shrinks
+    /// by [`shrink`] after that step's births.  Changes at
+    /// steps already done are ignored.
+    ///
+    /// # Errors
+    ///
+    /// [`SimulationError::InvalidParameters`] if
+    /// [`Demography::validate`] fails.
+    pub fn set_demography(&mut self, demography: Demography) -> Result<(), SimulationError> {
+        demography
+            .validate(&self.params)
+            .map_err(SimulationError::InvalidParameters)?;
+        self.demography = demography;
+        Ok(())
+    }
+
+    /// If `true`, keep unary nodes when simplifying.
+    /// See [`simplify`].  Defaults to `false`.
+    pub fn set_keep_unary(&mut self, keep_unary: bool) {
+        self.simplification_options
+            .set(tskit::SimplificationOptions::KEEP_UNARY, keep_unary);
+    }
+
+    /// If `true`, the simplification at the last step
+    /// removes populations that no node refers to, and
+    /// renumbers the rest.  Earlier simplifications never
+    /// do, as each deme's population must keep its row.
+    /// Defaults to `true`, as in tskit's Python API.
+    pub fn set_filter_populations(&mut self, filter: bool) {
+        self.output_filters
+            .set(tskit::SimplificationOptions::FILTER_POPULATIONS, filter);
+    }
+
+    /// If `true`, the simplification at the last step
+    /// removes individuals that no node refers to, and
+    /// renumbers the rest, keeping only the part of the
+    /// pedigree from [`SimParams::record_individuals`]
+    /// that has nodes in the output.  Earlier
+    /// simplifications never do.  Defaults to `true`, as
+    /// in tskit's Python API.
+    pub fn set_filter_individuals(&mut self, filter: bool) {
+        self.output_filters
+            .set(tskit::SimplificationOptions::FILTER_INDIVIDUALS, filter);
+    }
+
+    /// Have [`DiploidSimulation::run`] stop early, at the
+    /// first simplification after `max_duration` of wall
+    /// clock time, and return the tables so far.  The time
+    /// is only checked when the tables have just been
+    /// simplified, so the output is complete up to that
+    /// step.  Node times are not shifted, so the youngest
+    /// nodes are at [`DiploidSimulation::remaining_steps`],
+    /// not 0.  `None`, the default, means no limit.
+    pub fn set_max_duration(&mut self, max_duration: Option<std::time::Duration>) {
+        self.max_duration = max_duration;
+    }
+
+    /// Simplify whenever the edge table, plus any buffered
+    /// edges, has more than `max_edges` rows at the end of
+    /// a step, instead of every `params.simplification_interval`
+    /// steps.  This bounds the edge table at `max_edges` plus
+    /// one step's edges, whatever the recombination rate,
+    /// at the cost of counting the edges each step.  If the
+    /// simplified tables alone have more than `max_edges`
+    /// edges, every step simplifies.  The last step always
+    /// simplifies.  `None`, the default, uses the interval.
+    pub fn set_simplify_when_edges(&mut self, max_edges: Option<usize>) {
+        self.simplify_when_edges = max_edges;
+    }
+
+    /// If `true`, put the tables in tskit's canonical order
+    /// with [`sort_canonical`] before each simplification,
+    /// and before output with
+    /// [`DiploidSimulation::set_final_simplify`] `false`.
+    /// Any buffered edges are merged in first.  Runs whose
+    /// tables only differ in the order that rows were added
+    /// then give the same output.  Defaults to `false`.
+    pub fn set_canonical_sort(&mut self, canonical_sort: bool) {
+        self.canonical_sort = canonical_sort;
+    }
+
+    /// If `false`, [`DiploidSimulation::run`] returns tables
+    /// without an index, for callers that will modify them
+    /// before building one.  Mutations are still overlaid.
+    /// Defaults to `true`.
+    pub fn set_build_index(&mut self, build_index: bool) {
+        self.build_index = build_index;
+    }
+
+    /// Call `on_step` at the end of each step's births,
+    /// deaths and population size change, before any
+    /// simplification, with the step's birth time, the
+    /// tables and the alive individuals.  Over a run, the
+    /// birth times go down from `nsteps - 1` to 0.  With
+    /// [`SimParams::buffer_edges`], the edges born since the
+    /// last simplification are not yet in the tables.  The
+    /// callback is not saved in checkpoints.  `None`, the
+    /// default, calls nothing.
+    pub fn set_on_step(&mut self, on_step: Option<OnStep>) {
+        self.on_step = on_step;
+    }
+
+    /// Write the table sizes at the end of each step, after
+    /// any simplification, to a CSV file at `path` with the
+    /// columns `step,num_nodes,num_edges`.  `step` is the
+    /// birth time, and `num_edges` counts any buffered edges.
+    /// The sizes grow between simplifications and drop at
+    /// each one.  An existing file is replaced.  `None`, the
+    /// default, writes nothing.
+    ///
+    /// # Errors
+    ///
+    /// [`SimulationError::Io`] if the file cannot be created.
+    pub fn set_table_size_log(&mut self, path: Option<&str>) -> Result<(), SimulationError> {
+        self.table_size_log = match path {
+            Some(path) => {
+                create_parent_dir(path)?;
+                let mut log = std::io::BufWriter::new(std::fs::File::create(path)?);
+                writeln!(log, "step,num_nodes,num_edges")?;
+                Some(log)
+            }
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Follow the first selected mutation to arise, and
+    /// write its frequency among the alive nodes at the end
+    /// of each step to a CSV file at `path` with the columns
+    /// `step,position,frequency`.  `step` is the birth time.
+    /// If several mutations first arise in the same step,
+    /// the one at the smallest position is followed.  Rows
+    /// start at that step and go on to the end of the run,
+    /// with a frequency of 0 once the mutation is lost and
+    /// 1 once it is fixed.  An existing file is replaced.
+    /// The choice is not saved in checkpoints, so a resumed
+    /// run follows a mutation present when it resumes.
+    /// `None`, the default, writes nothing.
+    ///
+    /// # Errors
+    ///
+    /// [`SimulationError::InvalidParameters`] if
+    /// `params.selected_mutrate` is 0.0, so that there
+    /// are no selected mutations to follow.
+    ///
+    /// [`SimulationError::Io`] if the file cannot be created.
+    pub fn set_trajectory(&mut self, path: Option<&str>) -> Result<(), SimulationError> {
+        self.trajectory = match path {
+            Some(path) => {
+                if self.genomes.is_none() {
+                    return Err(SimulationError::InvalidParameters(String::from(
+                        "a selected mutation trajectory needs selected_mutrate > 0.0",
+                    )));
+                }
+                create_parent_dir(path)?;
+                let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+                writeln!(out, "step,position,frequency")?;
+                Some(out)
+            }
+            None => None,
+        };
+        self.trajectory_position = None;
+        Ok(())
+    }
+
+    /// Write a row to the trajectory file, first choosing
+    /// the mutation to follow if there is none yet.
+    fn record_trajectory(&mut self, birth_time: u64) -> Result<(), SimulationError> {
+        let (out, genomes) = match (self.trajectory.as_mut(), self.genomes.as_ref()) {
+            (Some(out), Some(genomes)) => (out, genomes),
+            _ => return Ok(()),
+        };
+        if self.trajectory_position.is_none() {
+            self.trajectory_position = genomes
+                .iter()
+                .flat_map(|g| g.iter())
+                .filter_map(|genome| genome.first().copied())
+                .min_by(|a, b| a.total_cmp(b));
+        }
+        if let Some(position) = self.trajectory_position {
+            writeln!(
+                out,
+                "{},{},{}",
+                birth_time,
+                position,
+                selection::frequency(genomes, position)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The number of edges in the tables and the edge buffer.
+    fn num_edges(&self) -> usize {
+        self.tables.edges().num_rows() as usize + self.edge_buffer.as_ref().map_or(0, |b| b.len())
+    }
+
+    /// Whether to simplify at the end of the step at `birth_time`.
+    fn simplify_due(&self, birth_time: u64) -> bool {
+        if birth_time == 0 {
+            return self.final_simplify;
+        }
+        match self.simplify_when_edges {
+            Some(max_edges) => self.num_edges() > max_edges,
+            None => birth_time.is_multiple_of(self.params.simplification_interval as u64),
+        }
+    }
+
+    /// Write the tables and state, as they are, so that
+    /// resuming continues exactly where this run is.
+    /// `birth_time` is that of the step just done.
+    fn checkpoint(&mut self, birth_time: u64) -> Result<(), SimulationError> {
+        let checkpoint = Checkpoint {
+            step: birth_time,
+            seed: self.seed,
+            rng_word_pos: self.rng.get_word_pos(),
+            substreams_word_pos: self.substreams.as_ref().map(Substreams::word_pos),
+            alive: self.alive.clone(),
+            genomes: self.genomes.clone().unwrap_or_default(),
+            ages: self.ages.clone(),
+            ancient_samples: self.ancient_samples.clone(),
+            edge_buffer: self.edge_buffer.clone(),
+        };
+        checkpoint.write(&self.tables, &self.checkpoint_prefix)
+    }
+
+    /// Apply one round of deaths and births.
+    /// Simplifies if `birth_time` falls on the
+    /// simplification interval, or as set by
+    /// [`DiploidSimulation::set_simplify_when_edges`].
+    pub fn step(&mut self, birth_time: u64) -> Result<(), SimulationError> {
+        self.parents.clear();
+        let popsize = self
+            .demography
+            .change_at(birth_time)
+            .unwrap_or(self.params.popsize);
+        let rng = match &mut self.substreams {
+            Some(s) => &mut s.mating,
+            None => &mut self.rng,
+        };
+        let mut fitness_selector;
+        let selector: &mut dyn ParentSelector = match &self.genomes {
+            Some(genomes) => {
+                self.fitness.clear();
+                for g in genomes {
+                    self.fitness.push(selection::additive_fitness(
+                        g,
+                        self.params.selection_coefficient,
+                    ));
+                }
+                fitness_selector = FitnessSelector::new(&self.params, &self.fitness)?;
+                &mut fitness_selector
+            }
+            None => &mut self.uniform_selector,
+        };
+        match &self.survival_schedule {
+            Some(schedule) => death_by_age_and_parents(
+                &self.alive,
+                &self.ages,
+                schedule,
+                &self.params,
+                selector,
+                &mut self.parents,
+                rng,
+            )?,
+            None => death_and_parents(&self.alive, &self.params, selector, &mut self.parents, rng)?,
+        }
+        if popsize > self.params.popsize {
+            grow(
+                &mut self.alive,
+                self.genomes.as_mut(),
+                &self.params,
+                popsize,
+                &mut self.parents,
+                rng,
+            );
+            self.ages.resize(popsize as usize, 0);
+        }
+        let rng = match &mut self.substreams {
+            Some(s) => &mut s.recombination,
+            None => &mut self.rng,
+        };
+        births(
+            &self.parents,
+            &self.params,
+            self.recombination_map.as_ref(),
+            birth_time,
+            &mut self.tables,
+            &mut self.alive,
+            self.genomes.as_mut(),
+            self.edge_buffer.as_mut(),
+            rng,
+        )?;
+        for age in self.ages.iter_mut() {
+            *age += 1;
+        }
+        for p in self.parents.iter() {
+            self.ages[p.index] = 0;
+        }
+
+        if self.params.sample_every > 0
+            && birth_time > 0
+            && birth_time.is_multiple_of(self.params.sample_every as u64)
+        {
+            for p in self.parents.iter() {
+                self.ancient_samples
+                    .extend_from_slice(&self.alive[p.index].nodes);
+            }
+        }
+
+        if popsize != self.params.popsize {
+            if popsize < self.params.popsize {
+                let rng = match &mut self.substreams {
+                    Some(s) => &mut s.mating,
+                    None => &mut self.rng,
+                };
+                shrink(
+                    &mut self.alive,
+                    self.genomes.as_mut(),
+                    Some(&mut self.ages),
+                    &self.params,
+                    popsize,
+                    rng,
+                );
+            }
+            self.params.popsize = popsize;
+            self.uniform_selector = UniformSelector::new(&self.params);
+        }
+        check_popsize(&self.alive, &self.params)?;
+
+        if self.strict {
+            check_alive(&self.alive, &self.tables)?;
+        }
+
+        if let Some(on_step) = self.on_step.as_mut() {
+            on_step(birth_time, &self.tables, &self.alive);
+        }
+
+        self.simplified = self.simplify_due(birth_time);
+        if self.simplified {
+            self.simplify(birth_time == 0)?;
+            log::info!(
+                "step {}: simplified to {} nodes and {} edges",
+                birth_time,
+                self.tables.nodes().num_rows(),
+                self.tables.edges().num_rows()
+            );
+        }
+        log::debug!(
+            "step {}: {} diploids alive, {} nodes, {} edges",
+            birth_time,
+            self.alive.len(),
+            self.tables.nodes().num_rows(),
+            self.tables.edges().num_rows()
+        );
+        let num_edges = self.num_edges();
+        if let Some(log) = self.table_size_log.as_mut() {
+            writeln!(
+                log,
+                "{},{},{}",
+                birth_time,
+                self.tables.nodes().num_rows(),
+                num_edges
+            )?;
+        }
+        self.record_trajectory(birth_time)?;
+        Ok(())
+    }
+
+    /// Simplify, applying `output_filters` if `last`.
+    fn simplify(&mut self, last: bool) -> Result<(), SimulationError> {
+        let mut options = self.simplification_options;
+        if last {
+            options |= self.output_filters;
+        }
+        if self.canonical_sort {
+            if let Some(buffer) = self.edge_buffer.as_mut() {
+                buffer.flush(&mut self.tables)?;
+            }
+            sort_canonical(&mut self.alive, &mut self.tables)?;
+        }
+        simplify_with_ancient_samples(
+            &mut self.alive,
+            &mut self.ancient_samples,
+            &mut self.tables,
+            self.edge_buffer.as_mut(),
+            options,
+        )?;
+        Ok(())
+    }
+
+    /// Simulate the remaining birth steps, or until the
+    /// limit from [`DiploidSimulation::set_max_duration`]
+    /// is reached, index the tables unless
+    /// [`DiploidSimulation::set_build_index`] is `false`,
+    /// and overlay mutations
+    /// if `params.mutrate > 0.0` or there is a mutation map.
+    /// The remaining steps are all `nsteps` for a new
+    /// simulation, or those after the checkpoint for one
+    /// made by [`DiploidSimulation::from_checkpoint`].
+    ///
+    /// The tables are moved out of `self`, which is left
+    /// holding an empty table collection.
+    pub fn run(&mut self) -> Result<tskit::TableCollection, SimulationError> {
+        if self.simplify_when_edges.is_none()
+            && self.params.nsteps > 1
+            && self.params.simplification_interval as u64 >= self.params.nsteps
+        {
+            log::warn!(
+                "simplification_interval {} is not less than nsteps {}, so only the last step simplifies",
+                self.params.simplification_interval,
+                self.params.nsteps
+            );
+        }
+        let mut progress = if self.show_progress {
+            Some(Progress::new(
+                self.params.nsteps,
+                self.params.nsteps - self.remaining_steps,
+            ))
+        } else {
+            None
+        };
+        let start = std::time::Instant::now();
+        while self.remaining_steps > 0 {
+            self.remaining_steps -= 1;
+            self.step(self.remaining_steps)?;
+            let done = self.params.nsteps - self.remaining_steps;
+            if let Some(p) = progress.as_mut() {
+                p.update(done);
+            }
+            if self.checkpoint_interval > 0
+                && self.remaining_steps > 0
+                && done.is_multiple_of(self.checkpoint_interval as u64)
+            {
+                self.checkpoint(self.remaining_steps)?;
+            }
+            if let Some(max_duration) = self.max_duration {
+                if self.remaining_steps > 0 && self.simplified && start.elapsed() > max_duration {
+                    break;
+                }
+            }
+        }
+        if let Some(log) = self.table_size_log.as_mut() {
+            log.flush()?;
+        }
+        if let Some(out) = self.trajectory.as_mut() {
+            out.flush()?;
+        }
+
+        if !self.final_simplify {
+            sort_unsimplified(
+                &self.alive,
+                &self.ancient_samples,
+                &mut self.tables,
+                self.edge_buffer.as_mut(),
+            )?;
+            if self.canonical_sort {
+                sort_canonical(&mut self.alive, &mut self.tables)?;
+            }
+        }
+        // Mutations are drawn before indexing, as adding them
+        // sorts the tables, so the index is only built once.
+        let new_mutations = if let Some(map) = &self.mutation_map {
+            draw_mutations_with_map(&self.tables, map, &mut self.rng)?
+        } else if self.params.mutrate > 0.0 {
+            if self.params.discrete_genome {
+                draw_discrete_mutations(&self.tables, self.params.mutrate, &mut self.rng)?
+            } else {
+                draw_mutations(&self.tables, self.params.mutrate, &mut self.rng)?
+            }
+        } else {
+            vec![]
+        };
+        add_new_mutations(&mut self.tables, new_mutations, self.build_index)?;
+
+        let empty = tskit::TableCollection::new(self.params.genome_length)?;
+        Ok(std::mem::replace(&mut self.tables, empty))
+    }
+
+    /// Use `map` for crossover positions instead of
+    /// `params.xovers`.
+    ///
+    /// # Errors
+    ///
+    /// [`SimulationError::InvalidParameters`] if the map's
+    /// sequence length differs from `params.genome_length`.
+    pub fn set_recombination_map(&mut self, map: RecombinationMap) -> Result<(), SimulationError> {
+        if map.sequence_length() != self.params.genome_length {
+            return Err(SimulationError::InvalidParameters(format!(
+                "the recombination map has length {}, but genome_length is {}",
+                map.sequence_length(),
+                self.params.genome_length
+            )));
+        }
+        self.recombination_map = Some(map);
+        Ok(())
+    }
+
+    /// Use `map` for neutral mutations instead of
+    /// `params.mutrate`, see [`overlay_mutations_with_map`].
+    ///
+    /// # Errors
+    ///
+    /// [`SimulationError::InvalidParameters`] if the map's
+    /// sequence length differs from `params.genome_length`,
+    /// or if `params.discrete_genome` is set.
+    pub fn set_mutation_map(&mut self, map: MutationMap) -> Result<(), SimulationError> {
+        if map.sequence_length() != self.params.genome_length {
+            return Err(SimulationError::InvalidParameters(format!(
+                "the mutation map has length {}, but genome_length is {}",
+                map.sequence_length(),
+                self.params.genome_length
+            )));
+        }
+        if self.params.discrete_genome {
+            return Err(SimulationError::InvalidParameters(
+                "a mutation map cannot be used with a discrete genome".to_string(),
+            ));
+        }
+        self.mutation_map = Some(map);
+        Ok(())
+    }
+
+    /// Use `schedule` for survival instead of
+    /// `params.psurvival`, see [`death_by_age_and_parents`].
+    pub fn set_survival_schedule(&mut self, schedule: SurvivalSchedule) {
+        self.survival_schedule = Some(schedule);
+    }
+
+    pub fn params(&self) -> &SimParams {
+        &self.params
+    }
+
+    /// The number of steps not yet simulated.  After
+    /// [`DiploidSimulation::run`], this is 0 unless the
+    /// limit from [`DiploidSimulation::set_max_duration`]
+    /// stopped the run early, when it is the birth time of
+    /// the last step done.
+    pub fn remaining_steps(&self) -> u64 {
+        self.remaining_steps
+    }
+
+    /// The seed the simulation was started with, which
+    /// is the checkpoint's seed for a resumed simulation.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn tables(&self) -> &tskit::TableCollection {
+        &self.tables
+    }
+
+    pub fn alive(&self) -> &[Individual] {
+        &self.alive
+    }
+
+    /// The age of each diploid in [`DiploidSimulation::alive`]:
+    /// the number of steps it has survived, 0 at birth.
+    pub fn ages(&self) -> &[u32] {
+        &self.ages
+    }
+
+    /// The options of the simplification at the last step,
+    /// from [`DiploidSimulation::set_keep_unary`] and the
+    /// output filters, for simplifying the output further
+    /// with [`subsample`].  Every simplification also uses
+    /// [`FILTER_SITES`](tskit::SimplificationOptions::FILTER_SITES),
+    /// so that the sites of lost selected mutations are
+    /// removed.
+    pub fn final_simplification_options(&self) -> tskit::SimplificationOptions {
+        self.simplification_options | self.output_filters
+    }
+
+    /// The ancient samples, in the node ids of
+    /// [`DiploidSimulation::tables`].
+    pub fn ancient_samples(&self) -> &[tskit::tsk_id_t] {
+        &self.ancient_samples
+    }
+
+    /// The selected mutations of each diploid in
+    /// [`DiploidSimulation::alive`], if selection is on.
+    pub fn genomes(&self) -> Option<&[Genomes]> {
+        self.genomes.as_deref()
+    }
+}
+
+/// Check `tables` before writing them out.
+///
+/// The checks are run in the order of the variants of
+/// [`IntegrityError`], which names the first to fail.
+/// The tables must be sorted and indexed.  Because
+/// simplified tables are expected, every node must also
+/// be a sample or the parent of an edge, so tables from
+/// [`DiploidSimulation::set_final_simplify`] with `false`
+/// fail this check.
+pub fn check_integrity(tables: &tskit::TableCollection) -> Result<(), IntegrityError> {
+    use tskit::TskitTypeAccess;
+
+    type Check = fn(tskit::TskitError) -> IntegrityError;
+    let checks: [(tskit::tsk_flags_t, Check); 6] = [
+        (0, IntegrityError::Tables),
+        (
+            tskit::bindings::TSK_CHECK_EDGE_ORDERING,
+            IntegrityError::EdgeOrdering,
+        ),
+        (
+            tskit::bindings::TSK_CHECK_SITE_ORDERING | tskit::bindings::TSK_CHECK_SITE_DUPLICATES,
+            IntegrityError::SiteOrdering,
+        ),
+        (
+            tskit::bindings::TSK_CHECK_MUTATION_ORDERING,
+            IntegrityError::MutationOrdering,
+        ),
+        (tskit::bindings::TSK_CHECK_INDEXES, IntegrityError::Indexes),
+        (tskit::bindings::TSK_CHECK_TREES, IntegrityError::Trees),
+    ];
+    for (flags, error) in checks.iter() {
+        let rv = unsafe {
+            tskit::bindings::tsk_table_collection_check_integrity(tables.as_ptr(), *flags)
+        };
+        if rv < 0 {
+            return Err(error(tskit::TskitError::ErrorCode { code: rv }));
+        }
+    }
+
+    let nodes = tables.nodes();
+    let edges = tables.edges();
+    let mut is_parent = vec![false; nodes.num_rows() as usize];
+    for e in 0..edges.num_rows() as tskit::tsk_id_t {
+        is_parent[edges.parent(e).map_err(IntegrityError::Tables)? as usize] = true;
+    }
+    for u in 0..nodes.num_rows() as tskit::tsk_id_t {
+        let flags = nodes.flags(u).map_err(IntegrityError::Tables)?;
+        if flags & tskit::TSK_NODE_IS_SAMPLE == 0 && !is_parent[u as usize] {
+            return Err(IntegrityError::OrphanNode(u));
+        }
+    }
+    Ok(())
+}
+
+struct NewMutation {
+    position: f64,
+    node: tskit::tsk_id_t,
+    time: f64,
+}
+
+/// Overlay neutral mutations onto the tables.
+///
+/// For each edge, the number of mutations is Poisson
+/// with mean `mutation_rate` times the edge span times
+/// the branch length.  Mutations sharing a position
+/// share a site, including any site already present
+/// in `tables`.  New sites have ancestral state `"0"`
+/// and new mutations have derived state `"1"`.
+///
+/// The tables are sorted afterwards, with older mutations
+/// first within a site, and the `parent` column of the
+/// mutation table is recomputed.  A mutation's parent is
+/// the mutation at the same site closest above it in the
+/// tree, or `TSK_NULL`.  A mutation below another one is
+/// therefore a silent `"1"` to `"1"` change.  The index is
+/// rebuilt if it existed on input.
+pub fn overlay_mutations(
+    tables: &mut tskit::TableCollection,
+    mutation_rate: f64,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    let new_mutations = draw_mutations(tables, mutation_rate, rng)?;
+    let indexed = tables.is_indexed();
+    add_new_mutations(tables, new_mutations, indexed)
+}
+
+fn draw_mutations(
+    tables: &tskit::TableCollection,
+    mutation_rate: f64,
+    rng: &mut SimRng,
+) -> Result<Vec<NewMutation>, SimulationError> {
+    let mut new_mutations = vec![];
+    {
+        let nodes = tables.nodes();
+        let edges = tables.edges();
+        for e in 0..edges.num_rows() as tskit::tsk_id_t {
+            let left = edges.left(e)?;
+            let right = edges.right(e)?;
+            let node = edges.child(e)?;
+            let child_time = nodes.time(node)?;
+            let parent_time = nodes.time(edges.parent(e)?)?;
+            let mean = mutation_rate * (right - left) * (parent_time - child_time);
+            if mean <= 0.0 {
+                continue;
+            }
+            let poisson = match Poisson::new(mean) {
+                Ok(p) => p,
+                Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+            };
+            let nmutations = rng.sample(poisson) as u64;
+            for _ in 0..nmutations {
+                new_mutations.push(NewMutation {
+                    position: rng.gen_range(left..right),
+                    node,
+                    time: rng.gen_range(child_time..parent_time),
+                });
+            }
+        }
+    }
+    Ok(new_mutations)
+}
+
+/// Overlay neutral mutations onto the tables, with rates
+/// that vary along the genome as given by `map`.
+///
+/// For each edge, the number of mutations is Poisson with
+/// mean the integral of the rate over the edge's interval
+/// times the branch length, and positions are placed in
+/// proportion to the rate.  Otherwise, this is the same as
+/// [`overlay_mutations`].
+pub fn overlay_mutations_with_map(
+    tables: &mut tskit::TableCollection,
+    map: &MutationMap,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    let new_mutations = draw_mutations_with_map(tables, map, rng)?;
+    let indexed = tables.is_indexed();
+    add_new_mutations(tables, new_mutations, indexed)
+}
+
+fn draw_mutations_with_map(
+    tables: &tskit::TableCollection,
+    map: &MutationMap,
+    rng: &mut SimRng,
+) -> Result<Vec<NewMutation>, SimulationError> {
+    let mut new_mutations = vec![];
+    {
+        let nodes = tables.nodes();
+        let edges = tables.edges();
+        for e in 0..edges.num_rows() as tskit::tsk_id_t {
+            let left = edges.left(e)?;
+            let right = edges.right(e)?;
+            let node = edges.child(e)?;
+            let child_time = nodes.time(node)?;
+            let parent_time = nodes.time(edges.parent(e)?)?;
+            let (cleft, cright) = (map.cumulative(left), map.cumulative(right));
+            let mean = (cright - cleft) * (parent_time - child_time);
+            if mean <= 0.0 {
+                continue;
+            }
+            let poisson = match Poisson::new(mean) {
+                Ok(p) => p,
+                Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+            };
+            let nmutations = rng.sample(poisson) as u64;
+            for _ in 0..nmutations {
+                // Rounding in the inversion may land just outside the edge.
+                let position = map.invert(rng.gen_range(cleft..cright)).max(left);
+                if position >= right {
+                    continue;
+                }
+                new_mutations.push(NewMutation {
+                    position,
+                    node,
+                    time: rng.gen_range(child_time..parent_time),
+                });
+            }
+        }
+    }
+    Ok(new_mutations)
+}
+
+/// Overlay neutral mutations onto tables whose edges have
+/// integer coordinates, as with [`SimParams::discrete_genome`].
+///
+/// Each edge's branch gains at most one mutation at each
+/// integer position it spans, with probability
+/// `1 - exp(-mutation_rate * branch_length)`, so that the
+/// expected number per site is close to that of
+/// [`overlay_mutations`] when mutations are rare.  Different
+/// branches may mutate the same site.  Otherwise, this is the
+/// same as [`overlay_mutations`].
+pub fn overlay_discrete_mutations(
+    tables: &mut tskit::TableCollection,
+    mutation_rate: f64,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    let new_mutations = draw_discrete_mutations(tables, mutation_rate, rng)?;
+    let indexed = tables.is_indexed();
+    add_new_mutations(tables, new_mutations, indexed)
+}
+
+fn draw_discrete_mutations(
+    tables: &tskit::TableCollection,
+    mutation_rate: f64,
+    rng: &mut SimRng,
+) -> Result<Vec<NewMutation>, SimulationError> {
+    let mut new_mutations = vec![];
+    {
+        let nodes = tables.nodes();
+        let edges = tables.edges();
+        for e in 0..edges.num_rows() as tskit::tsk_id_t {
+            let left = edges.left(e)?;
+            let right = edges.right(e)?;
+            let node = edges.child(e)?;
+            let child_time = nodes.time(node)?;
+            let parent_time = nodes.time(edges.parent(e)?)?;
+            let nsites = (right.ceil() - left.ceil()) as u64;
+            let p = -(-mutation_rate * (parent_time - child_time)).exp_m1();
+            if nsites == 0 || p <= 0.0 {
+                continue;
+            }
+            let binomial = match Binomial::new(nsites, p.min(1.0)) {
+                Ok(b) => b,
+                Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+            };
+            let nmutations = rng.sample(binomial) as usize;
+            for i in rand::seq::index::sample(rng, nsites as usize, nmutations) {
+                new_mutations.push(NewMutation {
+                    position: left.ceil() + i as f64,
+                    node,
+                    time: rng.gen_range(child_time..parent_time),
+                });
+            }
+        }
+    }
+    Ok(new_mutations)
+}
+
+/// Add `new_mutations` to `tables`, sharing sites by position,
+/// then sort and recompute mutation parents as described for
+/// [`overlay_mutations`].  The tables are left indexed if
+/// `index` is `true`, and unindexed otherwise.
+fn add_new_mutations(
+    tables: &mut tskit::TableCollection,
+    mut new_mutations: Vec<NewMutation>,
+    index: bool,
+) -> Result<(), SimulationError> {
+    if new_mutations.is_empty() {
+        if index && !tables.is_indexed() {
+            tables.build_index()?;
+        }
+        return Ok(());
+    }
+
+    new_mutations.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap());
+
+    let mut site_ids = std::collections::HashMap::<u64, tskit::tsk_id_t>::new();
+    {
+        let sites = tables.sites();
+        for s in 0..sites.num_rows() as tskit::tsk_id_t {
+            site_ids.insert(sites.position(s)?.to_bits(), s);
+        }
+    }
+
+    for m in new_mutations {
+        let site = match site_ids.get(&m.position.to_bits()) {
+            Some(&s) => s,
+            None => {
+                let s = tables.add_site(m.position, Some(b"0"))?;
+                site_ids.insert(m.position.to_bits(), s);
+                s
+            }
+        };
+        tables.add_mutation(site, m.node, tskit::TSK_NULL, m.time, Some(b"1"))?;
+    }
+
+    tables.full_sort(tskit::TableSortOptions::default())?;
+    tables.build_index()?;
+    compute_mutation_parents(tables)?;
+    if !index {
+        drop_index(tables)?;
+    }
+    Ok(())
+}
+
+fn compute_mutation_parents(tables: &mut tskit::TableCollection) -> Result<(), tskit::TskitError> {
+    use tskit::TskitTypeAccess;
+    // tskit 0.3.0 has no safe API for this.
+    let rv = unsafe {
+        tskit::bindings::tsk_table_collection_compute_mutation_parents(tables.as_mut_ptr(), 0)
+    };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv });
+    }
+    Ok(())
+}
+
+fn drop_index(tables: &mut tskit::TableCollection) -> Result<(), tskit::TskitError> {
+    use tskit::TskitTypeAccess;
+    // tskit 0.3.0 has no safe API for this.
+    let rv = unsafe { tskit::bindings::tsk_table_collection_drop_index(tables.as_mut_ptr(), 0) };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mean_breakpoints<D: Distribution<f64> + Copy>(distances: D, rng: &mut SimRng) -> f64 {
+        let replicates = 20000;
+        let mut total = 0;
+        let mut breakpoints = vec![];
+        for _ in 0..replicates {
+            breakpoints.clear();
+            renewal_breakpoints(distances, 1e6, &mut breakpoints, rng);
+            total += breakpoints.len();
+        }
+        total as f64 / replicates as f64
+    }
+
+    #[test]
+    fn gamma_shape_one_matches_poisson_crossovers() {
+        let xovers = 3.0;
+        let rate = xovers / 1e6;
+        let mut rng = SimRng::seed_from_u64(1);
+        let poisson = mean_breakpoints(Exp::new(rate).unwrap(), &mut rng);
+        let gamma = mean_breakpoints(Gamma::new(1.0, 1.0 / rate).unwrap(), &mut rng);
+        // The standard error of each mean is sqrt(3 / 20000) ~ 0.012.
+        assert!((poisson - xovers).abs() < 0.05, "{}", poisson);
+        assert!((gamma - xovers).abs() < 0.05, "{}", gamma);
+        assert!((gamma - poisson).abs() < 0.07, "{} {}", gamma, poisson);
+    }
+
+    #[test]
+    fn gene_conversion_tracts_lie_within_the_genome() {
+        let params = SimParams {
+            gc_rate: 5.0,
+            gc_mean_tract: 100.0,
+            ..SimParams::default()
+        };
+        let sequence_length = 1e4;
+        let mut rng = SimRng::seed_from_u64(2);
+        let mut breakpoints = vec![];
+        let mut ntracts = 0;
+        let mut total_length = 0.0;
+        for _ in 0..1000 {
+            breakpoints.clear();
+            gene_conversion_breakpoints(&params, sequence_length, &mut breakpoints, &mut rng)
+                .unwrap();
+            assert_eq!(breakpoints.len() % 2, 0);
+            for tract in breakpoints.chunks(2) {
+                let (start, end) = (tract[0], tract[1]);
+                assert!(start >= 0.0);
+                assert!(start < end, "{} {}", start, end);
+                assert!(end <= sequence_length);
+                if end < sequence_length {
+                    assert!(end - start > 1.0 - 1e-9);
+                    ntracts += 1;
+                    total_length += end - start;
+                }
+            }
+        }
+        let mean = total_length / ntracts as f64;
+        assert!((mean - 100.0).abs() < 5.0, "{}", mean);
+    }
+
+    type Corrupt = fn(&mut SimParams);
+
+    #[test]
+    fn validate_rejects_each_bad_field() {
+        assert_eq!(SimParams::default().validate(), Ok(()));
+        let bad: Vec<(&str, Corrupt)> = vec![
+            ("popsize", |p| p.popsize = 0),
+            ("nsteps", |p| p.nsteps = MAX_NSTEPS + 1),
+            ("genome_length", |p| p.genome_length = 0.0),
+            ("genome_length", |p| p.genome_length = f64::NAN),
+            ("simplification_interval", |p| p.simplification_interval = 0),
+            ("psurvival", |p| p.psurvival = 1.0),
+            ("psurvival", |p| p.psurvival = -0.1),
+            ("xovers", |p| p.xovers = -1.0),
+            ("xovers", |p| p.xovers = f64::INFINITY),
+            ("mutrate", |p| p.mutrate = -1e-8),
+            ("mutrate", |p| p.mutrate = f64::NAN),
+            ("crossover_model", |p| {
+                p.crossover_model = CrossoverModel::Gamma { shape: 0.0 }
+            }),
+            ("demes", |p| p.demes = 0),
+            ("demes", |p| p.demes = p.popsize + 1),
+            ("migration", |p| p.migration = 1.5),
+            ("selfing_rate", |p| p.selfing_rate = -0.5),
+            ("reproduction", |p| {
+                p.reproduction = Reproduction::Sweepstakes { alpha: 2.0 }
+            }),
+            ("gc_rate", |p| p.gc_rate = -1.0),
+            ("gc_mean_tract", |p| {
+                p.gc_rate = 1.0;
+                p.gc_mean_tract = 0.5;
+            }),
+            ("selected_mutrate", |p| p.selected_mutrate = -1.0),
+            ("selection_coefficient", |p| {
+                p.selection_coefficient = f64::INFINITY
+            }),
+            ("ploidy", |p| p.ploidy = 1),
+            ("ploidy", |p| {
+                p.ploidy = 4;
+                p.selected_mutrate = 1e-3;
+            }),
+            ("discrete_genome", |p| {
+                p.discrete_genome = true;
+                p.genome_length = 10.5;
+            }),
+            ("discrete_genome", |p| {
+                p.discrete_genome = true;
+                p.selected_mutrate = 1e-3;
+            }),
+            ("sample_every", |p| p.sample_every = p.nsteps as u32),
+        ];
+        for (field, corrupt) in bad {
+            let mut params = SimParams::default();
+            corrupt(&mut params);
+            assert!(params.validate().is_err(), "bad {} was accepted", field);
+        }
+    }
+
+    #[test]
+    fn check_alive_catches_corrupted_diploids() {
+        let params = SimParams {
+            popsize: 5,
+            ..SimParams::default()
+        };
+        let mut tables = tskit::TableCollection::new(params.genome_length).unwrap();
+        let alive = founders(&params, &mut tables).unwrap();
+        check_alive(&alive, &tables).unwrap();
+
+        let mut repeated = alive.clone();
+        repeated[1].nodes[1] = repeated[1].nodes[0];
+        assert_eq!(
+            check_alive(&repeated, &tables).unwrap_err().to_string(),
+            "invariant violated: diploid 1 has the same node, 2, twice"
+        );
+
+        let mut missing = alive.clone();
+        missing[4].nodes[0] = 10;
+        assert_eq!(
+            check_alive(&missing, &tables).unwrap_err().to_string(),
+            "invariant violated: diploid 4 has node 10, but there are 10 nodes"
+        );
+
+        let mut shared = alive;
+        shared[3].nodes[1] = shared[0].nodes[0];
+        assert_eq!(
+            check_alive(&shared, &tables).unwrap_err().to_string(),
+            "invariant violated: node 0 of diploid 3 also belongs to another diploid"
+        );
+    }
+
+    #[test]
+    fn recurrent_mutation_gets_the_older_one_as_parent() {
+        // Node 2 is the root, with children 1 and 3, and
+        // node 1 is the parent of node 0.
+        let mut tables = tskit::TableCollection::new(100.0).unwrap();
+        for time in [0.0, 1.0, 2.0, 0.0] {
+            tables
+                .add_node(0, time, tskit::TSK_NULL, tskit::TSK_NULL)
+                .unwrap();
+        }
+        tables.add_edge(0.0, 100.0, 1, 0).unwrap();
+        tables.add_edge(0.0, 100.0, 2, 1).unwrap();
+        tables.add_edge(0.0, 100.0, 2, 3).unwrap();
+        let mutation = |node, time| NewMutation {
+            position: 50.0,
+            node,
+            time,
+        };
+        add_new_mutations(
+            &mut tables,
+            vec![mutation(0, 0.5), mutation(3, 0.5), mutation(1, 1.5)],
+            false,
+        )
+        .unwrap();
+
+        let mutations = tables.mutations();
+        assert_eq!(tables.sites().num_rows(), 1);
+        assert_eq!(mutations.num_rows(), 3);
+        let on_node = |node| {
+            (0..3)
+                .find(|&m| mutations.node(m).unwrap() == node)
+                .unwrap()
+        };
+        assert_eq!(mutations.parent(on_node(0)).unwrap(), on_node(1));
+        assert_eq!(mutations.parent(on_node(1)).unwrap(), tskit::TSK_NULL);
+        assert_eq!(mutations.parent(on_node(3)).unwrap(), tskit::TSK_NULL);
+    }
+
+    #[test]
+    fn alive_sample_nodes_flattens_in_order() {
+        let alive = vec![
+            Individual {
+                nodes: vec![4, 5],
+                individual: 0,
+            },
+            Individual {
+                nodes: vec![0, 7],
+                individual: 1,
+            },
+            Individual {
+                nodes: vec![2, 1],
+                individual: 2,
+            },
+        ];
+        assert_eq!(alive_sample_nodes(&alive), vec![4, 5, 0, 7, 2, 1]);
+        assert!(alive_sample_nodes(&[]).is_empty());
+    }
 }
@@ -0,0 +1,111 @@
+/// Errors returned by the simulation functions.
+#[derive(Debug)]
+pub enum SimulationError {
+    /// An error from tskit.
+    Tskit(tskit::TskitError),
+    /// A random number distribution could not be created
+    /// from the simulation parameters.
+    Distribution(String),
+    /// The simulation parameters are inconsistent.
+    InvalidParameters(String),
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// The tables must be indexed to build a tree sequence.
+    TablesNotIndexed,
+    /// The tables failed an integrity check.
+    Integrity(IntegrityError),
+    /// The directory for an output file could not be created.
+    CreateDirectory { path: String, error: std::io::Error },
+    /// A configuration file could not be parsed.
+    Config(String),
+    /// The population no longer matches the tables, which
+    /// is a bug.  See [`crate::diploid::check_alive`].
+    Invariant(String),
+    /// A summary statistic is not defined for the data.
+    UndefinedStatistic(String),
+}
+
+impl std::fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SimulationError::Tskit(e) => write!(f, "{}", e),
+            SimulationError::Distribution(msg) => write!(f, "{}", msg),
+            SimulationError::InvalidParameters(msg) => write!(f, "{}", msg),
+            SimulationError::Io(e) => write!(f, "{}", e),
+            SimulationError::TablesNotIndexed => {
+                write!(f, "tables are not indexed; call build_index() first")
+            }
+            SimulationError::Integrity(e) => write!(f, "{}", e),
+            SimulationError::CreateDirectory { path, error } => {
+                write!(f, "could not create directory {}: {}", path, error)
+            }
+            SimulationError::Config(msg) => write!(f, "{}", msg),
+            SimulationError::Invariant(msg) => write!(f, "invariant violated: {}", msg),
+            SimulationError::UndefinedStatistic(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+impl From<tskit::TskitError> for SimulationError {
+    fn from(e: tskit::TskitError) -> Self {
+        SimulationError::Tskit(e)
+    }
+}
+
+impl From<std::io::Error> for SimulationError {
+    fn from(e: std::io::Error) -> Self {
+        SimulationError::Io(e)
+    }
+}
+
+impl From<IntegrityError> for SimulationError {
+    fn from(e: IntegrityError) -> Self {
+        SimulationError::Integrity(e)
+    }
+}
+
+/// The first check failed by
+/// [`crate::diploid::check_integrity`], with tskit's
+/// error where there is one.
+#[derive(Debug)]
+pub enum IntegrityError {
+    /// Ids are in range, times are finite, and each
+    /// edge's parent is older than its child.
+    Tables(tskit::TskitError),
+    /// Edges are sorted by parent time, parent, child
+    /// and left, and do not overlap.
+    EdgeOrdering(tskit::TskitError),
+    /// Sites are sorted by position, without duplicates.
+    SiteOrdering(tskit::TskitError),
+    /// Mutations are sorted by site, with parents first.
+    MutationOrdering(tskit::TskitError),
+    /// The edge insertion and removal indexes exist.
+    Indexes(tskit::TskitError),
+    /// The edges form valid trees.
+    Trees(tskit::TskitError),
+    /// A node is neither a sample nor the parent
+    /// of an edge, so it is not in any tree.
+    OrphanNode(tskit::tsk_id_t),
+}
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IntegrityError::Tables(e) => write!(f, "table check failed: {}", e),
+            IntegrityError::EdgeOrdering(e) => write!(f, "edge ordering check failed: {}", e),
+            IntegrityError::SiteOrdering(e) => write!(f, "site ordering check failed: {}", e),
+            IntegrityError::MutationOrdering(e) => {
+                write!(f, "mutation ordering check failed: {}", e)
+            }
+            IntegrityError::Indexes(e) => write!(f, "index check failed: {}", e),
+            IntegrityError::Trees(e) => write!(f, "tree check failed: {}", e),
+            IntegrityError::OrphanNode(u) => {
+                write!(f, "node {} is neither a sample nor a parent", u)
+            }
+        }
+    }
+}
+
+impl std::error::Error for IntegrityError {}
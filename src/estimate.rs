@@ -0,0 +1,67 @@
+use crate::diploid::{CrossoverModel, SimParams};
+use crate::recmap::RecombinationMap;
+
+/// Bytes per node row: flags, time, population,
+/// individual, and a metadata offset.
+const NODE_ROW_BYTES: u64 = 4 + 8 + 4 + 4 + 4;
+/// Bytes per edge row: left, right, parent, child,
+/// and a metadata offset.
+const EDGE_ROW_BYTES: u64 = 8 + 8 + 4 + 4 + 4;
+
+/// The rough peak size of the node and edge tables.
+pub struct MemoryEstimate {
+    pub nodes: u64,
+    pub edges: u64,
+    pub bytes: u64,
+}
+
+/// Estimate the peak size of the node and edge tables,
+/// reached just before each simplification, without
+/// running a simulation.
+///
+/// This counts the alive nodes plus the nodes and edges
+/// added by `simplification_interval` steps of births,
+/// with `popsize * (1 - psurvival)` births per step.
+/// Each gamete has one edge plus one per expected
+/// breakpoint, from crossovers and both ends of each
+/// gene conversion tract.  The ancestry kept by earlier
+/// simplifications is not included, so this underestimates
+/// runs where that is large compared to the new rows.
+pub fn estimate_memory(
+    params: &SimParams,
+    recombination_map: Option<&RecombinationMap>,
+) -> MemoryEstimate {
+    let crossovers = match (recombination_map, params.crossover_model) {
+        (Some(map), CrossoverModel::Poisson) | (Some(map), CrossoverModel::Gamma { .. }) => {
+            map.total()
+        }
+        (_, CrossoverModel::Fixed { n_xovers }) => n_xovers as f64,
+        (None, _) => params.xovers,
+    };
+    let breakpoints = crossovers + 2.0 * params.gc_rate;
+
+    let interval = (params.simplification_interval as u64).min(params.nsteps) as f64;
+    let births = params.popsize as f64 * (1.0 - params.psurvival) * interval;
+
+    let ploidy = params.ploidy as f64;
+    let nodes = (ploidy * (params.popsize as f64 + births)).ceil() as u64;
+    let edges = (ploidy * births * (1.0 + breakpoints)).ceil() as u64;
+    MemoryEstimate {
+        nodes,
+        edges,
+        bytes: nodes * NODE_ROW_BYTES + edges * EDGE_ROW_BYTES,
+    }
+}
+
+impl std::fmt::Display for MemoryEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "peak nodes: {}", self.nodes)?;
+        writeln!(f, "peak edges: {}", self.edges)?;
+        write!(
+            f,
+            "peak bytes: {} ({:.1} MiB)",
+            self.bytes,
+            self.bytes as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
@@ -0,0 +1,363 @@
+use std::io::Write;
+
+use streaming_iterator::StreamingIterator;
+use tskit::TableAccess;
+
+use crate::error::SimulationError;
+use crate::output::create_parent_dir;
+
+pub(crate) fn tree_sequence(
+    tables: &tskit::TableCollection,
+) -> Result<tskit::TreeSequence, SimulationError> {
+    if !tables.is_indexed() {
+        return Err(SimulationError::TablesNotIndexed);
+    }
+    Ok(tables
+        .deepcopy()?
+        .tree_sequence(tskit::TreeSequenceFlags::default())?)
+}
+
+/// Write the genotypes of `individuals` at each site to a VCF file.
+///
+/// Each entry of `individuals` is the sample nodes of one
+/// individual, and gets a column, `tsk_0`, `tsk_1`, and
+/// so on, with a phased genotype such as `0|1`: one allele
+/// per node, `0` (ancestral) or `1` (derived). A node
+/// is derived if any mutation at the site is on the path
+/// from that node to the root.
+///
+/// Positions are continuous, so the VCF `POS` is
+/// `floor(position) + 1`, raised if need be to one more
+/// than that of the previous site, so that no two sites
+/// share a `POS`.  The contig is long enough for the last.
+///
+/// If there are no sites, only the header is written.
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+///
+/// [`SimulationError::CreateDirectory`] or
+/// [`SimulationError::Io`] if writing to `path` fails.
+pub fn write_vcf(
+    tables: &tskit::TableCollection,
+    individuals: &[Vec<tskit::tsk_id_t>],
+    path: &str,
+) -> Result<(), SimulationError> {
+    let treeseq = tree_sequence(tables)?;
+
+    let sites = tables.sites();
+    let mutations = tables.mutations();
+    let mut site_mutations: Vec<Vec<tskit::tsk_id_t>> = vec![vec![]; sites.num_rows() as usize];
+    for m in 0..mutations.num_rows() as tskit::tsk_id_t {
+        site_mutations[mutations.site(m)? as usize].push(mutations.node(m)?);
+    }
+    let mut vcf_positions = Vec::with_capacity(site_mutations.len());
+    for site in 0..sites.num_rows() as tskit::tsk_id_t {
+        let pos = sites.position(site)?.floor() as u64 + 1;
+        vcf_positions.push(match vcf_positions.last() {
+            Some(&previous) if pos <= previous => previous + 1,
+            _ => pos,
+        });
+    }
+    let contig_length =
+        (tables.sequence_length().ceil() as u64).max(vcf_positions.last().copied().unwrap_or(0));
+
+    create_parent_dir(path)?;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "##fileformat=VCFv4.2")?;
+    writeln!(out, "##source=example_tskit_rust_simulations")?;
+    writeln!(out, "##contig=<ID=1,length={}>", contig_length)?;
+    writeln!(
+        out,
+        "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">"
+    )?;
+    write!(out, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT")?;
+    for i in 0..individuals.len() {
+        write!(out, "\ttsk_{}", i)?;
+    }
+    writeln!(out)?;
+
+    let mut site: tskit::tsk_id_t = 0;
+    let mut tree_iterator = treeseq.tree_iterator(tskit::TreeFlags::NO_SAMPLE_COUNTS)?;
+    while let Some(tree) = tree_iterator.next() {
+        let (_, right) = tree.interval();
+        while (site as usize) < site_mutations.len() && sites.position(site)? < right {
+            let nodes = &site_mutations[site as usize];
+            write!(
+                out,
+                "1\t{}\t.\t0\t1\t.\tPASS\t.\tGT",
+                vcf_positions[site as usize]
+            )?;
+            for individual in individuals {
+                for (i, s) in individual.iter().enumerate() {
+                    let mut derived = false;
+                    for u in tree.parents(*s)? {
+                        if nodes.contains(&u) {
+                            derived = true;
+                            break;
+                        }
+                    }
+                    let separator = if i == 0 { '\t' } else { '|' };
+                    write!(out, "{}{}", separator, if derived { 1 } else { 0 })?;
+                }
+            }
+            writeln!(out)?;
+            site += 1;
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn newick_subtree(
+    tree: &tskit::Tree,
+    nodes: &tskit::NodeTable,
+    u: tskit::tsk_id_t,
+    newick: &mut String,
+) -> Result<(), SimulationError> {
+    let children = tree.children(u)?.collect::<Vec<_>>();
+    if children.is_empty() {
+        newick.push_str(&u.to_string());
+    } else {
+        newick.push('(');
+        for (i, c) in children.iter().enumerate() {
+            if i > 0 {
+                newick.push(',');
+            }
+            newick_subtree(tree, nodes, *c, newick)?;
+            newick.push_str(&format!(":{}", nodes.time(u)? - nodes.time(*c)?));
+        }
+        newick.push(')');
+    }
+    Ok(())
+}
+
+/// Convert each tree to a Newick string.
+///
+/// Each string starts with the tree's half-open genomic
+/// interval as a Newick comment, `[left,right]`.  Leaves are
+/// labelled by node id and branch lengths are in the
+/// units of the node times.  If a tree has more than one root,
+/// the roots are joined by a multifurcation without a
+/// branch length.
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+pub fn trees_to_newick(tables: &tskit::TableCollection) -> Result<Vec<String>, SimulationError> {
+    let treeseq = tree_sequence(tables)?;
+    let nodes = tables.nodes();
+
+    let mut rv = vec![];
+    let mut tree_iterator = treeseq.tree_iterator(tskit::TreeFlags::default())?;
+    while let Some(tree) = tree_iterator.next() {
+        let (left, right) = tree.interval();
+        let mut newick = format!("[{},{}]", left, right);
+        let roots = tree.roots_to_vec();
+        if roots.len() > 1 {
+            newick.push('(');
+        }
+        for (i, r) in roots.iter().enumerate() {
+            if i > 0 {
+                newick.push(',');
+            }
+            newick_subtree(tree, &nodes, *r, &mut newick)?;
+        }
+        if roots.len() > 1 {
+            newick.push(')');
+        }
+        newick.push(';');
+        rv.push(newick);
+    }
+
+    Ok(rv)
+}
+
+/// The edges removed and inserted when moving from one
+/// tree to the next, left to right along the genome.
+pub struct TreeTransition {
+    /// The interval of the tree moved to.
+    pub left: f64,
+    pub right: f64,
+    /// Edge ids, in the order tskit removes them.
+    pub edges_out: Vec<tskit::tsk_id_t>,
+    /// Edge ids, in the order tskit inserts them.
+    pub edges_in: Vec<tskit::tsk_id_t>,
+}
+
+/// The ids of the edges in a list filled by `tsk_diff_iter_next`.
+///
+/// # Safety
+///
+/// `list` must come from the last call to `tsk_diff_iter_next`
+/// on an iterator that has not been freed.
+unsafe fn edge_list_ids(list: &tskit::bindings::tsk_edge_list_t) -> Vec<tskit::tsk_id_t> {
+    let mut ids = vec![];
+    let mut node = list.head;
+    while !node.is_null() {
+        ids.push((*node).edge.id);
+        node = (*node).next;
+    }
+    ids
+}
+
+/// The transitions between adjacent trees, from tskit's
+/// edge difference iterator.  There is one fewer transition
+/// than there are trees: the edges of the first tree are
+/// not included.
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+pub fn edge_differences(
+    tables: &tskit::TableCollection,
+) -> Result<Vec<TreeTransition>, SimulationError> {
+    use tskit::TskitTypeAccess;
+
+    let treeseq = tree_sequence(tables)?;
+    let mut iter = std::mem::MaybeUninit::<tskit::bindings::tsk_diff_iter_t>::zeroed();
+    let mut transitions = vec![];
+    // tskit 0.3.0 has no safe API for this.
+    let mut rv =
+        unsafe { tskit::bindings::tsk_diff_iter_init(iter.as_mut_ptr(), treeseq.as_ptr(), 0) };
+    let mut first_tree = true;
+    while rv >= 0 {
+        let (mut left, mut right) = (0.0, 0.0);
+        let mut edges_out = tskit::bindings::tsk_edge_list_t {
+            head: std::ptr::null_mut(),
+            tail: std::ptr::null_mut(),
+        };
+        let mut edges_in = edges_out;
+        rv = unsafe {
+            tskit::bindings::tsk_diff_iter_next(
+                iter.as_mut_ptr(),
+                &mut left,
+                &mut right,
+                &mut edges_out,
+                &mut edges_in,
+            )
+        };
+        if rv <= 0 {
+            break;
+        }
+        if !first_tree {
+            transitions.push(TreeTransition {
+                left,
+                right,
+                edges_out: unsafe { edge_list_ids(&edges_out) },
+                edges_in: unsafe { edge_list_ids(&edges_in) },
+            });
+        }
+        first_tree = false;
+    }
+    unsafe { tskit::bindings::tsk_diff_iter_free(iter.as_mut_ptr()) };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv }.into());
+    }
+    Ok(transitions)
+}
+
+/// Write [`edge_differences`] to a CSV file at `path`,
+/// with one row per edge removed or inserted.
+///
+/// The columns are `transition`, counting from 1 for the
+/// move from the first tree to the second, `left` and
+/// `right` of the tree moved to, `change`, which is `out`
+/// or `in`, and the `edge` id with its `parent` and `child`.
+/// Within a transition, removals come first.
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+///
+/// [`SimulationError::Io`] if writing to `path` fails.
+pub fn write_edge_diffs(
+    tables: &tskit::TableCollection,
+    path: &str,
+) -> Result<(), SimulationError> {
+    let transitions = edge_differences(tables)?;
+    create_parent_dir(path)?;
+    let edges = tables.edges();
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "transition,left,right,change,edge,parent,child")?;
+    for (i, t) in transitions.iter().enumerate() {
+        let changes = t
+            .edges_out
+            .iter()
+            .map(|&e| ("out", e))
+            .chain(t.edges_in.iter().map(|&e| ("in", e)));
+        for (change, e) in changes {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                i + 1,
+                t.left,
+                t.right,
+                change,
+                e,
+                edges.parent(e)?,
+                edges.child(e)?
+            )?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Write the node and edge tables to `{prefix}.nodes.csv`
+/// and `{prefix}.edges.csv`, one row per table row, for
+/// inspection in a spreadsheet.
+///
+/// The columns are named as in tskit, after the row `id`:
+/// `flags`, `time`, `population` and `individual` for
+/// nodes, and `left`, `right`, `parent` and `child` for
+/// edges.  Metadata is not written.
+///
+/// # Errors
+///
+/// [`SimulationError::Io`] if writing either file fails.
+pub fn dump_tables_csv(
+    tables: &tskit::TableCollection,
+    prefix: &str,
+) -> Result<(), SimulationError> {
+    create_parent_dir(prefix)?;
+
+    let nodes = tables.nodes();
+    let mut out = std::io::BufWriter::new(std::fs::File::create(format!("{}.nodes.csv", prefix))?);
+    writeln!(out, "id,flags,time,population,individual")?;
+    for u in 0..nodes.num_rows() as tskit::tsk_id_t {
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            u,
+            nodes.flags(u)?,
+            nodes.time(u)?,
+            nodes.population(u)?,
+            nodes.individual(u)?
+        )?;
+    }
+    out.flush()?;
+
+    let edges = tables.edges();
+    let mut out = std::io::BufWriter::new(std::fs::File::create(format!("{}.edges.csv", prefix))?);
+    writeln!(out, "id,left,right,parent,child")?;
+    for e in 0..edges.num_rows() as tskit::tsk_id_t {
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            e,
+            edges.left(e)?,
+            edges.right(e)?,
+            edges.parent(e)?,
+            edges.child(e)?
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
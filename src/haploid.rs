@@ -0,0 +1,213 @@
+use rand::Rng;
+use rand_distr::Uniform;
+use tskit::TableAccess;
+
+use crate::error::SimulationError;
+use crate::rng::SimRng;
+
+/// Add `popsize` founder nodes born at `birth_time`.
+pub fn founders(
+    popsize: u32,
+    birth_time: f64,
+    tables: &mut tskit::TableCollection,
+) -> Result<Vec<tskit::tsk_id_t>, tskit::TskitError> {
+    let mut alive = vec![];
+    for _ in 0..popsize {
+        alive.push(tables.add_node(0, birth_time, tskit::TSK_NULL, tskit::TSK_NULL)?);
+    }
+    Ok(alive)
+}
+
+/// Add a node born at `birth_time` and an edge over the
+/// whole genome from `parent` to it.
+fn birth(
+    parent: tskit::tsk_id_t,
+    birth_time: f64,
+    tables: &mut tskit::TableCollection,
+) -> Result<tskit::tsk_id_t, tskit::TskitError> {
+    let child = tables.add_node(0, birth_time, tskit::TSK_NULL, tskit::TSK_NULL)?;
+    tables.add_edge(0., tables.sequence_length(), parent, child)?;
+    Ok(child)
+}
+
+/// Replace every haploid in `alive` with the offspring,
+/// born at `birth_time`, of a uniformly chosen parent.
+pub fn wright_fisher_step(
+    alive: &mut Vec<tskit::tsk_id_t>,
+    birth_time: f64,
+    tables: &mut tskit::TableCollection,
+    rng: &mut SimRng,
+) -> Result<(), tskit::TskitError> {
+    let parent = Uniform::new(0_usize, alive.len());
+    let mut offspring = Vec::with_capacity(alive.len());
+    for _ in 0..alive.len() {
+        offspring.push(birth(alive[rng.sample(parent)], birth_time, tables)?);
+    }
+    *alive = offspring;
+    Ok(())
+}
+
+/// Replace one uniformly chosen haploid in `alive` with
+/// the offspring, born at `birth_time`, of a uniformly
+/// chosen parent.  The parent is chosen before the death,
+/// so it may be the haploid that dies.
+pub fn moran_step(
+    alive: &mut [tskit::tsk_id_t],
+    birth_time: f64,
+    tables: &mut tskit::TableCollection,
+    rng: &mut SimRng,
+) -> Result<(), tskit::TskitError> {
+    let index = Uniform::new(0_usize, alive.len());
+    let dead = rng.sample(index);
+    let parent = alive[rng.sample(index)];
+    alive[dead] = birth(parent, birth_time, tables)?;
+    Ok(())
+}
+
+/// Replace `births` distinct, uniformly chosen haploids in
+/// `alive` with offspring born at `birth_time`, as that
+/// many [`moran_step`]s at once.  Each offspring has a
+/// uniformly chosen parent from `alive` as it was before
+/// the step, so a parent may be one of the haploids that
+/// die, and no offspring is a parent in the same step.
+///
+/// This adds `births` nodes and edges, and saves the loop
+/// overhead of stepping once per birth.
+///
+/// # Errors
+///
+/// [`SimulationError::InvalidParameters`] if `births` is
+/// larger than `alive`.
+pub fn moran_births(
+    alive: &mut [tskit::tsk_id_t],
+    births: usize,
+    birth_time: f64,
+    tables: &mut tskit::TableCollection,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    if births > alive.len() {
+        return Err(SimulationError::InvalidParameters(format!(
+            "{} births per step, but only {} haploids",
+            births,
+            alive.len()
+        )));
+    }
+    let dead = rand::seq::index::sample(rng, alive.len(), births);
+    let index = Uniform::new(0_usize, alive.len());
+    let parents: Vec<tskit::tsk_id_t> = (0..births).map(|_| alive[rng.sample(index)]).collect();
+    for (d, parent) in dead.iter().zip(parents) {
+        alive[d] = birth(parent, birth_time, tables)?;
+    }
+    Ok(())
+}
+
+/// Sort the tables and simplify them with `alive` as
+/// the samples, remapping `alive` to the new node ids.
+///
+/// Returns the map from old to new node ids.
+///
+/// If `keep_unary` is `true`, nodes that are ancestral
+/// to `alive` but are not coalescences are kept.
+///
+/// # Errors
+///
+/// [`SimulationError::Invariant`] if a node in `alive`
+/// is not in `tables` or appears twice.
+pub fn simplify_haploid(
+    alive: &mut [tskit::tsk_id_t],
+    tables: &mut tskit::TableCollection,
+    keep_unary: bool,
+) -> Result<Vec<tskit::tsk_id_t>, SimulationError> {
+    let mut seen = vec![false; tables.nodes().num_rows() as usize];
+    for &u in alive.iter() {
+        if u < 0 || u as usize >= seen.len() || seen[u as usize] {
+            return Err(SimulationError::Invariant(format!(
+                "node {} is not in the tables or is alive twice",
+                u
+            )));
+        }
+        seen[u as usize] = true;
+    }
+
+    tables.full_sort(tskit::TableSortOptions::default())?;
+    let mut options = tskit::SimplificationOptions::default();
+    if keep_unary {
+        options.insert(tskit::SimplificationOptions::KEEP_UNARY);
+    }
+    match tables.simplify(alive, options, true)? {
+        Some(idmap) => {
+            for a in alive.iter_mut() {
+                *a = match idmap[*a as usize] {
+                    tskit::TSK_NULL => {
+                        return Err(SimulationError::Invariant(format!(
+                            "sample node {} was removed by simplification",
+                            a
+                        )))
+                    }
+                    v => v,
+                };
+            }
+            Ok(idmap.to_vec())
+        }
+        None => panic!("Unexpected None"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn moran_step_adds_one_node_and_edge() {
+        let mut tables = tskit::TableCollection::new(10.0).unwrap();
+        let mut alive = founders(5, 2.0, &mut tables).unwrap();
+        let before = alive.clone();
+        let mut rng = SimRng::seed_from_u64(1);
+        moran_step(&mut alive, 1.0, &mut tables, &mut rng).unwrap();
+
+        assert_eq!(tables.nodes().num_rows(), 6);
+        assert_eq!(tables.nodes().time(5).unwrap(), 1.0);
+        let edges = tables.edges();
+        assert_eq!(edges.num_rows(), 1);
+        assert_eq!(edges.left(0).unwrap(), 0.0);
+        assert_eq!(edges.right(0).unwrap(), 10.0);
+        assert_eq!(edges.child(0).unwrap(), 5);
+        assert!(before.contains(&edges.parent(0).unwrap()));
+
+        // Exactly one haploid was replaced, by the new node.
+        let replaced = (0..alive.len())
+            .filter(|&i| alive[i] != before[i])
+            .collect::<Vec<_>>();
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(alive[replaced[0]], 5);
+    }
+
+    #[test]
+    fn moran_births_add_b_nodes_per_step_with_distinct_deaths() {
+        let births = 4;
+        let mut tables = tskit::TableCollection::new(10.0).unwrap();
+        let mut alive = founders(10, 20.0, &mut tables).unwrap();
+        let mut rng = SimRng::seed_from_u64(2);
+        for step in 1..=20 {
+            let before = alive.clone();
+            let birth_time = 20.0 - step as f64;
+            moran_births(&mut alive, births, birth_time, &mut tables, &mut rng).unwrap();
+            let nodes = 10 + births * step;
+            assert_eq!(tables.nodes().num_rows() as usize, nodes);
+            assert_eq!(tables.edges().num_rows() as usize, births * step);
+            // Each death is replaced by a distinct new node.
+            let mut born = (0..alive.len())
+                .filter(|&i| alive[i] != before[i])
+                .map(|i| alive[i])
+                .collect::<Vec<_>>();
+            born.sort_unstable();
+            let expected = ((nodes - births)..nodes)
+                .map(|u| u as tskit::tsk_id_t)
+                .collect::<Vec<_>>();
+            assert_eq!(born, expected);
+        }
+
+        assert!(moran_births(&mut alive, 11, 0.0, &mut tables, &mut rng).is_err());
+    }
+}
@@ -1 +1,20 @@
+pub mod checkpoint;
+pub mod config;
+pub mod demography;
 pub mod diploid;
+pub mod error;
+pub mod estimate;
+pub mod export;
+pub mod haploid;
+pub mod logging;
+pub mod metadata;
+pub mod mutmap;
+pub mod output;
+pub mod progress;
+pub mod provenance;
+pub mod recmap;
+pub mod rng;
+pub mod selection;
+pub mod stats;
+pub mod survival;
+pub mod time;
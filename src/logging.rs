@@ -0,0 +1,35 @@
+/// Send log messages to stderr, shared by the binaries.
+///
+/// Only errors are shown by default.  `verbose` is the
+/// number of `-v` flags: one adds info messages, such as
+/// one per simplification, and two or more add debug
+/// messages, one per step.  `quiet` hides everything
+/// except the fatal error of [`exit_with_error`].
+pub fn init_logging(verbose: u64, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => log::LevelFilter::Off,
+        (false, 0) => log::LevelFilter::Error,
+        (false, 1) => log::LevelFilter::Info,
+        (false, _) => log::LevelFilter::Debug,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format_timestamp(None)
+        .format_module_path(false)
+        .init();
+}
+
+/// Log `error` and exit with status 1, in place of a
+/// panic at the top level of a binary.
+///
+/// The error goes straight to stderr when logging is off,
+/// either with `quiet` or before [`init_logging`], so that
+/// a failed run always says why.
+pub fn exit_with_error<E: std::fmt::Display>(error: E) -> ! {
+    if log::log_enabled!(log::Level::Error) {
+        log::error!("{}", error);
+    } else {
+        eprintln!("[ERROR] {}", error);
+    }
+    std::process::exit(1)
+}
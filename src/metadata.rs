@@ -0,0 +1,201 @@
+use tskit::metadata::{MetadataError, MetadataRoundtrip};
+use tskit::TskitTypeAccess;
+
+/// Node metadata, stored as JSON.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct NodeMetadata {
+    /// The birth step, in steps before the end of the run.
+    /// This is the node's time before any rescaling.
+    pub birth_step: u64,
+}
+
+impl MetadataRoundtrip for NodeMetadata {
+    fn encode(&self) -> Result<Vec<u8>, MetadataError> {
+        tskit::handle_metadata_return!(serde_json::to_vec(&self))
+    }
+
+    fn decode(md: &[u8]) -> Result<Self, MetadataError> {
+        tskit::handle_metadata_return!(serde_json::from_slice(md))
+    }
+}
+
+/// The schema for [`NodeMetadata`], so that tskit-python
+/// can decode it.
+pub const NODE_METADATA_SCHEMA: &str = r#"{"codec":"json","type":"object","properties":{"birth_step":{"type":"integer","description":"The birth step, in steps before the end of the run"}},"required":["birth_step"],"additionalProperties":false}"#;
+
+/// Register [`NODE_METADATA_SCHEMA`] on the node table.
+pub fn set_node_metadata_schema(
+    tables: &mut tskit::TableCollection,
+) -> Result<(), tskit::TskitError> {
+    // tskit 0.3.0 has no safe API for metadata schemas.
+    let rv = unsafe {
+        tskit::bindings::tsk_node_table_set_metadata_schema(
+            &mut (*tables.as_mut_ptr()).nodes,
+            NODE_METADATA_SCHEMA.as_ptr() as *const std::os::raw::c_char,
+            NODE_METADATA_SCHEMA.len() as tskit::tsk_size_t,
+        )
+    };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv });
+    }
+    Ok(())
+}
+
+/// Population metadata, stored as JSON, naming each
+/// deme so that downstream tools can label it.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PopulationMetadata {
+    /// `deme_` followed by the deme's index.
+    pub name: String,
+}
+
+impl PopulationMetadata {
+    pub fn deme(index: usize) -> Self {
+        Self {
+            name: format!("deme_{}", index),
+        }
+    }
+}
+
+impl MetadataRoundtrip for PopulationMetadata {
+    fn encode(&self) -> Result<Vec<u8>, MetadataError> {
+        tskit::handle_metadata_return!(serde_json::to_vec(&self))
+    }
+
+    fn decode(md: &[u8]) -> Result<Self, MetadataError> {
+        tskit::handle_metadata_return!(serde_json::from_slice(md))
+    }
+}
+
+/// The schema for [`PopulationMetadata`].
+pub const POPULATION_METADATA_SCHEMA: &str = r#"{"codec":"json","type":"object","properties":{"name":{"type":"string","description":"The name of the deme"}},"required":["name"],"additionalProperties":false}"#;
+
+/// Register [`POPULATION_METADATA_SCHEMA`] on the
+/// population table.
+pub fn set_population_metadata_schema(
+    tables: &mut tskit::TableCollection,
+) -> Result<(), tskit::TskitError> {
+    // tskit 0.3.0 has no safe API for metadata schemas.
+    let rv = unsafe {
+        tskit::bindings::tsk_population_table_set_metadata_schema(
+            &mut (*tables.as_mut_ptr()).populations,
+            POPULATION_METADATA_SCHEMA.as_ptr() as *const std::os::raw::c_char,
+            POPULATION_METADATA_SCHEMA.len() as tskit::tsk_size_t,
+        )
+    };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv });
+    }
+    Ok(())
+}
+
+/// Whether the population table of `tables` has
+/// [`POPULATION_METADATA_SCHEMA`] as its schema.
+pub fn has_population_metadata_schema(tables: &tskit::TableCollection) -> bool {
+    // tskit 0.3.0 has no safe API for metadata schemas.
+    let schema = unsafe {
+        let p = &(*tables.as_ptr()).populations;
+        if p.metadata_schema_length == 0 {
+            return false;
+        }
+        std::slice::from_raw_parts(
+            p.metadata_schema as *const u8,
+            p.metadata_schema_length as usize,
+        )
+    };
+    schema == POPULATION_METADATA_SCHEMA.as_bytes()
+}
+
+/// Top-level metadata describing the run, stored as
+/// JSON alongside the provenance record, for pipelines
+/// that only need these fields.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SimulationMetadata {
+    /// The program that ran the simulation.
+    pub model: String,
+    /// The initial population size.
+    pub popsize: u32,
+    /// The number of birth steps, or generations.
+    pub nsteps: u64,
+    /// The seed actually used.
+    pub seed: u64,
+    /// The version of this crate.
+    pub version: String,
+}
+
+impl SimulationMetadata {
+    pub fn new(model: &str, popsize: u32, nsteps: u64, seed: u64) -> Self {
+        Self {
+            model: String::from(model),
+            popsize,
+            nsteps,
+            seed,
+            version: String::from(env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+impl MetadataRoundtrip for SimulationMetadata {
+    fn encode(&self) -> Result<Vec<u8>, MetadataError> {
+        tskit::handle_metadata_return!(serde_json::to_vec(&self))
+    }
+
+    fn decode(md: &[u8]) -> Result<Self, MetadataError> {
+        tskit::handle_metadata_return!(serde_json::from_slice(md))
+    }
+}
+
+/// The schema for [`SimulationMetadata`].
+pub const SIMULATION_METADATA_SCHEMA: &str = r#"{"codec":"json","type":"object","properties":{"model":{"type":"string","description":"The program that ran the simulation"},"popsize":{"type":"integer","description":"The initial population size"},"nsteps":{"type":"integer","description":"The number of birth steps, or generations"},"seed":{"type":"integer","description":"The seed actually used"},"version":{"type":"string","description":"The version of the simulating crate"}},"required":["model","popsize","nsteps","seed","version"],"additionalProperties":false}"#;
+
+/// Set the top-level metadata of `tables` to `metadata`,
+/// and register [`SIMULATION_METADATA_SCHEMA`].
+pub fn set_simulation_metadata(
+    tables: &mut tskit::TableCollection,
+    metadata: &SimulationMetadata,
+) -> Result<(), tskit::TskitError> {
+    let encoded = metadata.encode()?;
+    // tskit 0.3.0 has no safe API for top-level metadata.
+    let rv = unsafe {
+        tskit::bindings::tsk_table_collection_set_metadata_schema(
+            tables.as_mut_ptr(),
+            SIMULATION_METADATA_SCHEMA.as_ptr() as *const std::os::raw::c_char,
+            SIMULATION_METADATA_SCHEMA.len() as tskit::tsk_size_t,
+        )
+    };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv });
+    }
+    let rv = unsafe {
+        tskit::bindings::tsk_table_collection_set_metadata(
+            tables.as_mut_ptr(),
+            encoded.as_ptr() as *const std::os::raw::c_char,
+            encoded.len() as tskit::tsk_size_t,
+        )
+    };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv });
+    }
+    Ok(())
+}
+
+/// The top-level metadata of `tables`, or `None` if
+/// there is none.
+///
+/// # Errors
+///
+/// [`tskit::TskitError::MetadataError`] if the metadata
+/// is not a [`SimulationMetadata`].
+pub fn simulation_metadata(
+    tables: &tskit::TableCollection,
+) -> Result<Option<SimulationMetadata>, tskit::TskitError> {
+    // tskit 0.3.0 has no safe API for top-level metadata.
+    let md = unsafe {
+        let t = &*tables.as_ptr();
+        if t.metadata_length == 0 {
+            return Ok(None);
+        }
+        std::slice::from_raw_parts(t.metadata as *const u8, t.metadata_length as usize)
+    };
+    Ok(Some(SimulationMetadata::decode(md)?))
+}
@@ -0,0 +1,56 @@
+use crate::error::SimulationError;
+use crate::recmap::RateMap;
+
+/// A piecewise-constant map of the neutral mutation rate.
+///
+/// Segment `i` runs from `positions[i]` to `positions[i + 1]`,
+/// or to the sequence length for the last segment, and has
+/// a rate of `rates[i]` mutations per unit length per unit time,
+/// like [`crate::diploid::SimParams::mutrate`].
+/// The rate is 0.0 to the left of the first position.
+pub struct MutationMap {
+    map: RateMap,
+}
+
+impl MutationMap {
+    /// Build a map from segment start positions and rates.
+    ///
+    /// Positions must be strictly increasing and lie in
+    /// `[0, sequence_length)`.  Rates must be finite and
+    /// `>= 0.0`.
+    pub fn new(positions: Vec<f64>, rates: Vec<f64>, sequence_length: f64) -> Result<Self, String> {
+        let map = RateMap::new(positions, rates, sequence_length, "mutation")?;
+        if !map.total().is_finite() {
+            return Err(format!(
+                "the total mutation rate must be finite, got {}",
+                map.total()
+            ));
+        }
+        Ok(Self { map })
+    }
+
+    /// Read a map from a file with two tab-separated columns,
+    /// position and rate, one segment per line.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn from_tsv(path: &str, sequence_length: f64) -> Result<Self, SimulationError> {
+        let (positions, rates) = RateMap::read_tsv(path)?;
+        Self::new(positions, rates, sequence_length).map_err(SimulationError::InvalidParameters)
+    }
+
+    pub fn sequence_length(&self) -> f64 {
+        self.map.sequence_length()
+    }
+
+    /// The integral of the rate over `[0, position)`.
+    /// The integral over an interval is the difference of
+    /// this at its ends, however many segments it straddles.
+    pub fn cumulative(&self, position: f64) -> f64 {
+        self.map.cumulative(position)
+    }
+
+    /// The position at which [`MutationMap::cumulative`] is `x`,
+    /// for `0.0 <= x` less than the integral over the genome.
+    pub fn invert(&self, x: f64) -> f64 {
+        self.map.invert(x)
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::{BufRead, Write};
+
+use crate::diploid::{check_integrity, SimParams};
+use crate::error::SimulationError;
+use crate::stats::Statistic;
+
+/// `treefile` without its `.trees` extension, used to
+/// name the other files written alongside it.
+pub fn file_prefix(treefile: &str) -> &str {
+    treefile.strip_suffix(".trees").unwrap_or(treefile)
+}
+
+/// Create the directory that will hold `path`,
+/// and any missing parents, if `path` has one.
+///
+/// # Errors
+///
+/// [`SimulationError::CreateDirectory`] if that fails.
+pub fn create_parent_dir(path: &str) -> Result<(), SimulationError> {
+    match std::path::Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => {
+            std::fs::create_dir_all(dir).map_err(|error| SimulationError::CreateDirectory {
+                path: dir.display().to_string(),
+                error,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Write `seed` to `{prefix}.seed`, where `prefix` is
+/// [`file_prefix`] of `treefile`, so that a run that
+/// crashes can still be reproduced.
+pub fn write_seed(treefile: &str, seed: u64) -> Result<(), SimulationError> {
+    create_parent_dir(treefile)?;
+    std::fs::write(
+        format!("{}.seed", file_prefix(treefile)),
+        format!("{}\n", seed),
+    )?;
+    Ok(())
+}
+
+/// Write `tables` to `treefile`, shared by the binaries.
+/// The directory holding `treefile` is created if needed.
+///
+/// If `check` is `true`, [`check_integrity`] is run first
+/// and nothing is written if it fails.
+///
+/// # Errors
+///
+/// [`SimulationError::Integrity`] if a check fails.
+///
+/// [`SimulationError::CreateDirectory`] if the directory
+/// cannot be created.
+///
+/// [`SimulationError::Tskit`] if writing fails.
+pub fn write_tables(
+    tables: &tskit::TableCollection,
+    treefile: &str,
+    check: bool,
+) -> Result<(), SimulationError> {
+    if check {
+        check_integrity(tables)?;
+    }
+    create_parent_dir(treefile)?;
+    tables.dump(treefile, tskit::TableOutputOptions::empty())?;
+    Ok(())
+}
+
+/// Write `spectrum`, from
+/// [`allele_frequency_spectrum`](crate::stats::allele_frequency_spectrum),
+/// to `path`, one line per bin with the allele count and
+/// the number of sites separated by a tab.  The directory
+/// holding `path` is created if needed.
+pub fn write_spectrum(path: &str, spectrum: &[u64]) -> Result<(), SimulationError> {
+    create_parent_dir(path)?;
+    let lines: Vec<String> = spectrum
+        .iter()
+        .enumerate()
+        .map(|(k, n)| format!("{}\t{}\n", k, n))
+        .collect();
+    std::fs::write(path, lines.concat())?;
+    Ok(())
+}
+
+/// Compress the file at `path` to `{path}.gz` with gzip,
+/// and remove the original, returning the new path.
+///
+/// tskit cannot load a compressed tree file directly: it
+/// must first be decompressed, for example with `gunzip`.
+pub fn gzip_file(path: &str) -> Result<String, SimulationError> {
+    let gz_path = format!("{}.gz", path);
+    let mut input = std::io::BufReader::new(std::fs::File::open(path)?);
+    let output = std::io::BufWriter::new(std::fs::File::create(&gz_path)?);
+    let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    std::fs::remove_file(path)?;
+    Ok(gz_path)
+}
+
+/// `field`, quoted for CSV if it holds a comma,
+/// a double quote, or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+/// Append a row of `values` to the CSV file at `path`.
+/// A new or empty file first gets a header of `columns`.
+///
+/// The row is written with one call on a file opened for
+/// appending, so that rows from runs in separate processes
+/// sharing the file do not interleave.
+///
+/// # Errors
+///
+/// [`SimulationError::InvalidParameters`] if the file
+/// already has a different header.
+///
+/// [`SimulationError::Io`] if reading or writing fails.
+fn append_csv_row(
+    path: &str,
+    columns: &[String],
+    values: &[String],
+) -> Result<(), SimulationError> {
+    let header = columns
+        .iter()
+        .map(|c| csv_field(c))
+        .collect::<Vec<_>>()
+        .join(",");
+    create_parent_dir(path)?;
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let mut existing = String::new();
+    std::io::BufReader::new(&file).read_line(&mut existing)?;
+    let mut text = String::new();
+    if existing.is_empty() {
+        text.push_str(&header);
+        text.push('\n');
+    } else if existing.trim_end_matches(&['\n', '\r'][..]) != header {
+        return Err(SimulationError::InvalidParameters(format!(
+            "{} has the columns {}, but this run writes {}",
+            path,
+            existing.trim_end(),
+            header
+        )));
+    }
+    let row = values
+        .iter()
+        .map(|v| csv_field(v))
+        .collect::<Vec<_>>()
+        .join(",");
+    text.push_str(&row);
+    text.push('\n');
+    file.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// Append a row to the CSV file at `path` with `seed`, every
+/// field of `params`, and `statistics`, so that the results
+/// of a sweep over seeds and parameters collect in one file.
+///
+/// The columns are `seed`, the fields of `params` in
+/// alphabetical order, and the name of each statistic.
+/// Fields that are not numbers, booleans or strings, such
+/// as the reproduction model, are written as JSON.
+///
+/// # Errors
+///
+/// [`SimulationError::InvalidParameters`] if the file
+/// already has a header with other columns, for example
+/// from a run computing other statistics.
+///
+/// [`SimulationError::Io`] if reading or writing fails.
+pub fn append_statistics(
+    path: &str,
+    seed: u64,
+    params: &SimParams,
+    statistics: &[(Statistic, f64)],
+) -> Result<(), SimulationError> {
+    let mut columns = vec![String::from("seed")];
+    let mut values = vec![seed.to_string()];
+    let params =
+        serde_json::to_value(params).map_err(|e| SimulationError::Io(std::io::Error::from(e)))?;
+    if let serde_json::Value::Object(fields) = params {
+        for (name, value) in fields {
+            columns.push(name);
+            values.push(match value {
+                serde_json::Value::String(s) => s,
+                v => v.to_string(),
+            });
+        }
+    }
+    for (statistic, value) in statistics {
+        columns.push(String::from(statistic.name()));
+        values.push(value.to_string());
+    }
+    append_csv_row(path, &columns, &values)
+}
@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+/// Reports the progress of a simulation to stderr,
+/// at most once per second.
+///
+/// The time remaining is estimated from an exponentially
+/// weighted average of the step rate between reports,
+/// so it adapts when steps speed up or slow down.
+pub struct Progress {
+    total: u64,
+    last_report: Instant,
+    last_done: u64,
+    steps_per_second: Option<f64>,
+}
+
+impl Progress {
+    const INTERVAL: Duration = Duration::from_secs(1);
+    /// Weight of the newest rate in the average.
+    const SMOOTHING: f64 = 0.3;
+
+    /// `done` is the number of the `total` steps
+    /// already done, which is non-zero when resuming.
+    pub fn new(total: u64, done: u64) -> Self {
+        Self {
+            total,
+            last_report: Instant::now(),
+            last_done: done,
+            steps_per_second: None,
+        }
+    }
+
+    /// Record that `done` steps are complete, printing
+    /// a report if at least a second has passed since
+    /// the last one.
+    pub fn update(&mut self, done: u64) {
+        let elapsed = self.last_report.elapsed();
+        if elapsed < Self::INTERVAL {
+            return;
+        }
+
+        let rate = (done - self.last_done) as f64 / elapsed.as_secs_f64();
+        let rate = match self.steps_per_second {
+            Some(r) => Self::SMOOTHING * rate + (1.0 - Self::SMOOTHING) * r,
+            None => rate,
+        };
+        self.steps_per_second = Some(rate);
+        self.last_report = Instant::now();
+        self.last_done = done;
+
+        let remaining = (self.total - done) as f64 / rate;
+        eprintln!(
+            "step {}/{} ({:.1}%), about {} remaining",
+            done,
+            self.total,
+            100.0 * done as f64 / self.total as f64,
+            format_seconds(remaining)
+        );
+    }
+}
+
+fn format_seconds(seconds: f64) -> String {
+    if !seconds.is_finite() {
+        return String::from("unknown time");
+    }
+    let seconds = seconds.round() as u64;
+    format!(
+        "{}h{:02}m{:02}s",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,89 @@
+use serde::Serialize;
+use tskit::provenance::Provenance;
+
+use crate::error::SimulationError;
+
+/// A provenance record, as JSON, following tskit's
+/// provenance schema.
+///
+/// `parameters` are the program's options, which must
+/// serialize to a JSON object.  Its `"seed"` is set to
+/// `seed`, the seed actually used, which differs from
+/// the option when resuming from a checkpoint.
+pub fn provenance_record<P: Serialize>(
+    program: &str,
+    seed: u64,
+    parameters: &P,
+) -> Result<String, SimulationError> {
+    let mut parameters = serde_json::to_value(parameters)
+        .map_err(|e| SimulationError::Io(std::io::Error::from(e)))?;
+    if let Some(p) = parameters.as_object_mut() {
+        p.insert(String::from("command"), program.into());
+        p.insert(String::from("seed"), seed.into());
+    }
+    let record = serde_json::json!({
+        "schema_version": "1.0.0",
+        "software": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "parameters": parameters,
+        "environment": {
+            "os": {
+                "system": std::env::consts::OS,
+                "machine": std::env::consts::ARCH,
+            },
+        },
+    });
+    Ok(record.to_string())
+}
+
+/// Add a row with [`provenance_record`] to the provenance
+/// table of `tables`.  tskit stamps the row with the
+/// current time in ISO-8601 format.
+pub fn add_provenance<P: Serialize>(
+    tables: &mut tskit::TableCollection,
+    program: &str,
+    seed: u64,
+    parameters: &P,
+) -> Result<(), SimulationError> {
+    let record = provenance_record(program, seed, parameters)?;
+    tables.add_provenance(&record)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Options {
+        treefile: String,
+        seed: Option<u64>,
+        xovers: f64,
+    }
+
+    #[test]
+    fn provenance_is_valid_json() {
+        let options = Options {
+            treefile: String::from("a \"quoted\"\\path\n.trees"),
+            seed: None,
+            xovers: 0.5,
+        };
+        let mut tables = tskit::TableCollection::new(1.0).unwrap();
+        add_provenance(&mut tables, "test_program", 7, &options).unwrap();
+
+        let provenances = tables.provenances();
+        assert_eq!(provenances.num_rows(), 1);
+        let record: serde_json::Value =
+            serde_json::from_str(&provenances.record(0).unwrap()).unwrap();
+        assert_eq!(record["schema_version"], "1.0.0");
+        assert_eq!(record["software"]["version"], env!("CARGO_PKG_VERSION"));
+        let parameters = &record["parameters"];
+        assert_eq!(parameters["command"], "test_program");
+        assert_eq!(parameters["seed"], 7);
+        assert_eq!(parameters["treefile"], options.treefile.as_str());
+        assert_eq!(parameters["xovers"], 0.5);
+        assert!(!provenances.timestamp(0).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::error::SimulationError;
+
+/// A piecewise-constant rate along the genome, shared by
+/// [`RecombinationMap`] and [`crate::mutmap::MutationMap`].
+///
+/// Segment `i` runs from `positions[i]` to `positions[i + 1]`,
+/// or to the sequence length for the last segment, and has
+/// a rate of `rates[i]` per unit length.
+/// The rate is 0.0 to the left of the first position.
+pub(crate) struct RateMap {
+    positions: Vec<f64>,
+    /// The integral of the rate to the left
+    /// of each position, plus the total at the end.
+    cumulative: Vec<f64>,
+    sequence_length: f64,
+}
+
+impl RateMap {
+    /// Positions must be strictly increasing and lie in
+    /// `[0, sequence_length)`, and rates must be finite and
+    /// `>= 0.0`.  `kind` names the map in error messages.
+    pub(crate) fn new(
+        positions: Vec<f64>,
+        rates: Vec<f64>,
+        sequence_length: f64,
+        kind: &str,
+    ) -> Result<Self, String> {
+        if positions.is_empty() || positions.len() != rates.len() {
+            return Err(format!(
+                "a {} map needs the same, non-zero, number of positions and rates, got {} and {}",
+                kind,
+                positions.len(),
+                rates.len()
+            ));
+        }
+        let valid_range = 0.0..sequence_length;
+        if !valid_range.contains(&positions[0])
+            || !valid_range.contains(&positions[positions.len() - 1])
+        {
+            return Err(format!(
+                "{} map positions must be in [0, {}), got {} to {}",
+                kind,
+                sequence_length,
+                positions[0],
+                positions[positions.len() - 1]
+            ));
+        }
+
+        let mut cumulative = vec![0.0];
+        for (i, (&p, &r)) in positions.iter().zip(rates.iter()).enumerate() {
+            let right = match positions.get(i + 1) {
+                Some(&next) => next,
+                None => sequence_length,
+            };
+            match right.partial_cmp(&p) {
+                Some(std::cmp::Ordering::Greater) => (),
+                Some(_) | None => {
+                    return Err(format!(
+                        "{} map positions must be strictly increasing, got {} then {}",
+                        kind, p, right
+                    ));
+                }
+            }
+            if !r.is_finite() || r < 0.0 {
+                return Err(format!(
+                    "{} map rates must be finite and >= 0.0, got {} at position {}",
+                    kind, r, p
+                ));
+            }
+            cumulative.push(cumulative[i] + r * (right - p));
+        }
+
+        Ok(Self {
+            positions,
+            cumulative,
+            sequence_length,
+        })
+    }
+
+    /// Read the positions and rates of a map from a file with two
+    /// tab-separated columns, one segment per line.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub(crate) fn read_tsv(path: &str) -> Result<(Vec<f64>, Vec<f64>), SimulationError> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut positions = vec![];
+        let mut rates = vec![];
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let parsed = match fields.as_slice() {
+                [p, r] => p
+                    .trim()
+                    .parse::<f64>()
+                    .and_then(|p| r.trim().parse::<f64>().map(|r| (p, r)))
+                    .ok(),
+                _ => None,
+            };
+            match parsed {
+                Some((p, r)) => {
+                    positions.push(p);
+                    rates.push(r);
+                }
+                None => {
+                    return Err(SimulationError::InvalidParameters(format!(
+                        "{}, line {}: expected two numbers, position and rate, separated by a tab",
+                        path,
+                        lineno + 1
+                    )))
+                }
+            }
+        }
+        Ok((positions, rates))
+    }
+
+    pub(crate) fn sequence_length(&self) -> f64 {
+        self.sequence_length
+    }
+
+    pub(crate) fn total(&self) -> f64 {
+        self.cumulative[self.cumulative.len() - 1]
+    }
+
+    /// The integral of the rate from 0.0 to `position`.
+    pub(crate) fn cumulative(&self, position: f64) -> f64 {
+        let i = self.positions.partition_point(|&p| p <= position);
+        if i == 0 {
+            return 0.0;
+        }
+        let right = match self.positions.get(i) {
+            Some(&next) => next,
+            None => self.sequence_length,
+        };
+        let rate = (self.cumulative[i] - self.cumulative[i - 1]) / (right - self.positions[i - 1]);
+        self.cumulative[i - 1] + rate * (position - self.positions[i - 1])
+    }
+
+    /// The position at which [`RateMap::cumulative`] is `x`,
+    /// for `0.0 <= x < self.total()`.
+    pub(crate) fn invert(&self, x: f64) -> f64 {
+        // The last segment whose start is at or left of x.
+        // Zero-rate segments have zero width here, so are skipped.
+        let i = self.cumulative.partition_point(|&c| c <= x) - 1;
+        let i = i.min(self.positions.len() - 1);
+        let right = match self.positions.get(i + 1) {
+            Some(&next) => next,
+            None => self.sequence_length,
+        };
+        let rate = (self.cumulative[i + 1] - self.cumulative[i]) / (right - self.positions[i]);
+        (self.positions[i] + (x - self.cumulative[i]) / rate).min(right)
+    }
+}
+
+/// A piecewise-constant recombination map.
+///
+/// Segment `i` runs from `positions[i]` to `positions[i + 1]`,
+/// or to the sequence length for the last segment, and has
+/// a rate of `rates[i]` crossovers per unit length per meiosis.
+/// The rate is 0.0 to the left of the first position.
+pub struct RecombinationMap {
+    map: RateMap,
+}
+
+impl RecombinationMap {
+    /// Build a map from segment start positions and rates.
+    ///
+    /// Positions must be strictly increasing and lie in
+    /// `[0, sequence_length)`.  Rates must be finite and
+    /// `>= 0.0`, and at least one segment must have a
+    /// non-zero rate.
+    pub fn new(positions: Vec<f64>, rates: Vec<f64>, sequence_length: f64) -> Result<Self, String> {
+        let map = RateMap::new(positions, rates, sequence_length, "recombination")?;
+        let total = map.total();
+        if !total.is_finite() || total <= 0.0 {
+            return Err(format!(
+                "the total recombination rate must be finite and > 0.0, got {}",
+                total
+            ));
+        }
+        Ok(Self { map })
+    }
+
+    /// Read a map from a file with two tab-separated columns,
+    /// position and rate, one segment per line.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn from_tsv(path: &str, sequence_length: f64) -> Result<Self, SimulationError> {
+        let (positions, rates) = RateMap::read_tsv(path)?;
+        Self::new(positions, rates, sequence_length).map_err(SimulationError::InvalidParameters)
+    }
+
+    pub fn sequence_length(&self) -> f64 {
+        self.map.sequence_length()
+    }
+
+    /// The expected number of crossovers per meiosis.
+    pub fn total(&self) -> f64 {
+        self.map.total()
+    }
+
+    /// The genomic position at which the expected number of
+    /// crossovers to the left is `x`, for `0.0 <= x < self.total()`.
+    ///
+    /// Sampling `x` from a process on `[0, self.total())`
+    /// and inverting gives breakpoints that follow the map.
+    pub fn invert(&self, x: f64) -> f64 {
+        self.map.invert(x)
+    }
+}
@@ -0,0 +1,136 @@
+use rand::{RngCore, SeedableRng};
+
+/// The random number generator used by the simulations.
+///
+/// Unlike [`rand::rngs::StdRng`], whose algorithm may
+/// change between `rand` releases, this is pinned to
+/// ChaCha20, so a given seed gives the same output
+/// regardless of the `rand` version.
+pub type SimRng = rand_chacha::ChaCha20Rng;
+
+/// Separate random number generators for choosing
+/// parents and for births, so that changing how one
+/// draws random numbers does not change the other's
+/// stream.
+///
+/// Each is stream 1 or 2 of [`SimRng`] for the same seed.
+/// ChaCha20 streams are independent, and stream 0 is the
+/// one that [`rand::SeedableRng::seed_from_u64`] gives,
+/// so neither overlaps the default generator.
+pub struct Substreams {
+    /// Used by [`crate::diploid::death_and_parents`].
+    pub mating: SimRng,
+    /// Used by [`crate::diploid::births`], including
+    /// crossovers, birth times and selected mutations.
+    pub recombination: SimRng,
+}
+
+impl Substreams {
+    pub fn new(seed: u64) -> Self {
+        Self::at_word_pos(seed, (0, 0))
+    }
+
+    /// The generators for `seed`, moved on to the positions
+    /// returned by [`Substreams::word_pos`].
+    pub fn at_word_pos(seed: u64, word_pos: (u128, u128)) -> Self {
+        let mut mating = SimRng::seed_from_u64(seed);
+        mating.set_stream(1);
+        mating.set_word_pos(word_pos.0);
+        let mut recombination = SimRng::seed_from_u64(seed);
+        recombination.set_stream(2);
+        recombination.set_word_pos(word_pos.1);
+        Self {
+            mating,
+            recombination,
+        }
+    }
+
+    /// How far along its stream each generator is,
+    /// as `(mating, recombination)`.
+    pub fn word_pos(&self) -> (u128, u128) {
+        (
+            self.mating.get_word_pos(),
+            self.recombination.get_word_pos(),
+        )
+    }
+}
+
+/// The generator used to choose the samples kept by
+/// [`crate::diploid::subsample`]: stream 3 of [`SimRng`]
+/// for `seed`, so the choice is reproducible and does not
+/// overlap the streams used to simulate.
+pub fn subsample_rng(seed: u64) -> SimRng {
+    let mut rng = SimRng::seed_from_u64(seed);
+    rng.set_stream(3);
+    rng
+}
+
+/// A seed drawn from the operating system's entropy, for
+/// runs given no seed.  It is logged, and the binaries
+/// also record it in the `.seed` file and the provenance,
+/// so that the run can be reproduced.
+pub fn entropy_seed() -> u64 {
+    let seed = rand::rngs::OsRng.next_u64();
+    log::info!("no seed given, using {} from the operating system", seed);
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draws(rng: &mut SimRng) -> Vec<u64> {
+        (0..3).map(|_| rng.next_u64()).collect()
+    }
+
+    /// Changing these values changes the output of every
+    /// seeded run, which breaks reproducibility.
+    #[test]
+    fn streams_give_pinned_draws() {
+        assert_eq!(
+            draws(&mut SimRng::seed_from_u64(42)),
+            [
+                9482535800248027256,
+                7566832397956113305,
+                1804347359131428821
+            ]
+        );
+        let mut substreams = Substreams::new(42);
+        assert_eq!(
+            draws(&mut substreams.mating),
+            [
+                629220857678633262,
+                1525128642534362311,
+                16235084340552130026
+            ]
+        );
+        assert_eq!(
+            draws(&mut substreams.recombination),
+            [
+                4443715558123408678,
+                18345378823181584911,
+                13626821119396039686
+            ]
+        );
+        assert_eq!(
+            draws(&mut subsample_rng(42)),
+            [
+                13888388868697413957,
+                7935186139957444596,
+                13889647941191072734
+            ]
+        );
+    }
+
+    #[test]
+    fn substreams_resume_at_their_word_positions() {
+        let mut substreams = Substreams::new(42);
+        draws(&mut substreams.mating);
+        let mut resumed = Substreams::at_word_pos(42, substreams.word_pos());
+        assert_eq!(draws(&mut resumed.mating), draws(&mut substreams.mating));
+        assert_eq!(
+            draws(&mut resumed.recombination),
+            draws(&mut substreams.recombination)
+        );
+    }
+}
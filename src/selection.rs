@@ -0,0 +1,96 @@
+use rand::Rng;
+use rand_distr::Poisson;
+
+use crate::error::SimulationError;
+use crate::rng::SimRng;
+
+/// The positions of the selected mutations carried by
+/// a diploid's two nodes, each sorted.  Selection needs a
+/// [`SimParams::ploidy`](crate::diploid::SimParams::ploidy)
+/// of 2.
+pub type Genomes = [Vec<f64>; 2];
+
+/// The result of one meiosis, enough to work out
+/// which parental node each position came from.
+pub struct Meiosis {
+    /// 0 if the gamete starts with the parent's first
+    /// node, 1 if it starts with its second.  Only
+    /// meaningful for a diploid parent.
+    pub first: usize,
+    /// Sorted positions at which the gamete switches
+    /// between the parent's nodes.
+    pub breakpoints: Vec<f64>,
+}
+
+impl Meiosis {
+    /// Which of the parent's nodes, 0 or 1, the gamete
+    /// inherits `position` from.
+    pub fn source(&self, position: f64) -> usize {
+        let switches = self.breakpoints.partition_point(|&b| b <= position);
+        (self.first + switches) % 2
+    }
+}
+
+/// The selected mutations passed on by `parent` in `meiosis`.
+pub fn inherit(parent: &Genomes, meiosis: &Meiosis) -> Vec<f64> {
+    let mut gamete = vec![];
+    for (i, genome) in parent.iter().enumerate() {
+        for &p in genome {
+            if meiosis.source(p) == i {
+                gamete.push(p);
+            }
+        }
+    }
+    gamete.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    gamete
+}
+
+/// Fitness `1 + s * n`, where `n` is the number of selected
+/// mutations carried on both nodes.  Fitness is at least 0.0.
+pub fn additive_fitness(genomes: &Genomes, selection_coefficient: f64) -> f64 {
+    let n = (genomes[0].len() + genomes[1].len()) as f64;
+    (1.0 + selection_coefficient * n).max(0.0)
+}
+
+/// The fraction of the nodes of `genomes` that carry the
+/// selected mutation at `position`, or 0.0 if there are
+/// no genomes.
+pub fn frequency(genomes: &[Genomes], position: f64) -> f64 {
+    let carriers = genomes
+        .iter()
+        .flat_map(|g| g.iter())
+        .filter(|genome| genome.binary_search_by(|p| p.total_cmp(&position)).is_ok())
+        .count();
+    match genomes.len() {
+        0 => 0.0,
+        n => carriers as f64 / (2 * n) as f64,
+    }
+}
+
+/// Add a Poisson number of new selected mutations, with mean
+/// `rate * genome_length`, to `node`, recording each as a site
+/// and a mutation.  Sites have ancestral state `"0"` and
+/// mutations derived state `"1"`.  The positions are also
+/// added to `genome`.
+pub fn mutate(
+    genome: &mut Vec<f64>,
+    node: tskit::tsk_id_t,
+    birth_time: f64,
+    rate: f64,
+    tables: &mut tskit::TableCollection,
+    rng: &mut SimRng,
+) -> Result<(), SimulationError> {
+    let sequence_length = tables.sequence_length();
+    let nmutations = match Poisson::new(rate * sequence_length) {
+        Ok(p) => rng.sample(p) as u64,
+        Err(e) => return Err(SimulationError::Distribution(e.to_string())),
+    };
+    for _ in 0..nmutations {
+        let position = rng.gen_range(0.0..sequence_length);
+        let site = tables.add_site(position, Some(b"0"))?;
+        tables.add_mutation(site, node, tskit::TSK_NULL, birth_time, Some(b"1"))?;
+        let i = genome.partition_point(|&p| p < position);
+        genome.insert(i, position);
+    }
+    Ok(())
+}
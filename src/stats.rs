@@ -0,0 +1,460 @@
+use streaming_iterator::StreamingIterator;
+use tskit::TableAccess;
+
+use crate::diploid::SimParams;
+use crate::error::SimulationError;
+use crate::export::tree_sequence;
+
+/// Mean pairwise (site) diversity among `samples`,
+/// per unit genome length.
+///
+/// Computed by tskit's `tsk_treeseq_diversity` over
+/// a single window spanning the genome.
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+///
+/// [`SimulationError::Tskit`] if tskit rejects `samples`.
+pub fn mean_pairwise_diversity(
+    tables: &tskit::TableCollection,
+    samples: &[tskit::tsk_id_t],
+) -> Result<f64, SimulationError> {
+    use tskit::TskitTypeAccess;
+
+    let treeseq = tree_sequence(tables)?;
+    let sample_set_sizes = [samples.len() as tskit::tsk_size_t];
+    let mut result = 0.0;
+    // tskit 0.3.0 has no safe API for statistics.
+    let rv = unsafe {
+        tskit::bindings::tsk_treeseq_diversity(
+            treeseq.as_ptr(),
+            1,
+            sample_set_sizes.as_ptr(),
+            samples.as_ptr(),
+            0,
+            std::ptr::null(),
+            &mut result,
+            tskit::bindings::TSK_STAT_SITE | tskit::bindings::TSK_STAT_SPAN_NORMALISE,
+        )
+    };
+    if rv < 0 {
+        return Err(tskit::TskitError::ErrorCode { code: rv }.into());
+    }
+    Ok(result)
+}
+
+/// The site frequency spectrum of `samples`.
+///
+/// The result has `samples.len() + 1` bins.  As in
+/// [`write_vcf`](crate::export::write_vcf), a sample
+/// carries the derived allele at a site if any mutation
+/// at the site is on the path from the sample to the root.
+/// If `polarised` is `true`, bin `k` counts the sites where
+/// `k` samples carry the derived allele.  Otherwise the
+/// spectrum is folded: a site with `k` derived copies is in
+/// bin `min(k, n - k)`, and the bins above `n / 2` are 0.
+///
+/// Every site is counted, so the bins sum to the number
+/// of sites.  Sites that are monomorphic among `samples`
+/// are in bin 0.  Sites fixed for the derived allele are
+/// in the last bin, or in bin 0 if folded.
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+pub fn allele_frequency_spectrum(
+    tables: &tskit::TableCollection,
+    samples: &[tskit::tsk_id_t],
+    polarised: bool,
+) -> Result<Vec<u64>, SimulationError> {
+    let treeseq = tree_sequence(tables)?;
+
+    let sites = tables.sites();
+    let mutations = tables.mutations();
+    let mut site_mutations: Vec<Vec<tskit::tsk_id_t>> = vec![vec![]; sites.num_rows() as usize];
+    for m in 0..mutations.num_rows() as tskit::tsk_id_t {
+        site_mutations[mutations.site(m)? as usize].push(mutations.node(m)?);
+    }
+
+    let n = samples.len();
+    let mut sfs = vec![0; n + 1];
+    let mut site: tskit::tsk_id_t = 0;
+    let mut tree_iterator = treeseq.tree_iterator(tskit::TreeFlags::NO_SAMPLE_COUNTS)?;
+    while let Some(tree) = tree_iterator.next() {
+        let (_, right) = tree.interval();
+        while (site as usize) < site_mutations.len() && sites.position(site)? < right {
+            let nodes = &site_mutations[site as usize];
+            let mut derived = 0;
+            for s in samples {
+                for u in tree.parents(*s)? {
+                    if nodes.contains(&u) {
+                        derived += 1;
+                        break;
+                    }
+                }
+            }
+            if polarised {
+                sfs[derived] += 1;
+            } else {
+                sfs[derived.min(n - derived)] += 1;
+            }
+            site += 1;
+        }
+    }
+
+    Ok(sfs)
+}
+
+/// The number of sites segregating among `samples`: those
+/// where some, but not all, of them carry the derived
+/// allele, as counted by [`allele_frequency_spectrum`].
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+pub fn segregating_sites(
+    tables: &tskit::TableCollection,
+    samples: &[tskit::tsk_id_t],
+) -> Result<u64, SimulationError> {
+    let sfs = allele_frequency_spectrum(tables, samples, true)?;
+    Ok(sfs.iter().take(samples.len()).skip(1).sum())
+}
+
+/// Tajima's D for `samples`, from the number of segregating
+/// sites and the mean number of pairwise differences per
+/// site, both computed from the polarised
+/// [`allele_frequency_spectrum`].
+///
+/// Sites that are monomorphic or fixed among `samples` are
+/// not segregating and do not contribute.  D compares two
+/// estimates of the population mutation rate, so it is
+/// undefined, rather than 0, if no site segregates: the
+/// variance in the denominator is then 0 as well.
+///
+/// # Errors
+///
+/// [`SimulationError::UndefinedStatistic`] if there are
+/// fewer than two samples or no segregating sites.
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+pub fn tajimas_d(
+    tables: &tskit::TableCollection,
+    samples: &[tskit::tsk_id_t],
+) -> Result<f64, SimulationError> {
+    if samples.len() < 2 {
+        return Err(SimulationError::UndefinedStatistic(String::from(
+            "Tajima's D requires at least two samples",
+        )));
+    }
+    let sfs = allele_frequency_spectrum(tables, samples, true)?;
+
+    let n = samples.len() as f64;
+    let mut segregating = 0.0;
+    let mut differences = 0.0;
+    for (k, count) in sfs.iter().enumerate().take(samples.len()).skip(1) {
+        segregating += *count as f64;
+        differences += (k as f64) * (n - k as f64) * (*count as f64);
+    }
+    if segregating == 0.0 {
+        return Err(SimulationError::UndefinedStatistic(String::from(
+            "Tajima's D is undefined without segregating sites",
+        )));
+    }
+    let pi = differences / (n * (n - 1.0) / 2.0);
+
+    let a1: f64 = (1..samples.len()).map(|i| 1.0 / i as f64).sum();
+    let a2: f64 = (1..samples.len()).map(|i| 1.0 / (i * i) as f64).sum();
+    let b1 = (n + 1.0) / (3.0 * (n - 1.0));
+    let b2 = 2.0 * (n * n + n + 3.0) / (9.0 * n * (n - 1.0));
+    let c1 = b1 - 1.0 / a1;
+    let c2 = b2 - (n + 2.0) / (a1 * n) + a2 / (a1 * a1);
+    let e1 = c1 / a1;
+    let e2 = c2 / (a1 * a1 + a2);
+
+    Ok(
+        (pi - segregating / a1)
+            / (e1 * segregating + e2 * segregating * (segregating - 1.0)).sqrt(),
+    )
+}
+
+/// The mean, along the genome, of the time of the most
+/// recent common ancestor of the samples: each tree's
+/// root time, weighted by the tree's span.
+///
+/// A tree that has not coalesced has several roots, and
+/// the oldest is used, so the mean is then a lower bound.
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+pub fn mean_tmrca(tables: &tskit::TableCollection) -> Result<f64, SimulationError> {
+    let treeseq = tree_sequence(tables)?;
+    let nodes = tables.nodes();
+
+    let mut sum = 0.0;
+    let mut tree_iterator = treeseq.tree_iterator(tskit::TreeFlags::default())?;
+    while let Some(tree) = tree_iterator.next() {
+        let (left, right) = tree.interval();
+        let mut tmrca: f64 = 0.0;
+        for r in tree.roots_to_vec() {
+            tmrca = tmrca.max(nodes.time(r)?);
+        }
+        sum += (right - left) * tmrca;
+    }
+    Ok(sum / tables.sequence_length())
+}
+
+/// Whether the samples have a single common ancestor at
+/// every position: each tree has exactly one root.  If
+/// not, the run was too short for the samples to coalesce.
+///
+/// # Errors
+///
+/// [`SimulationError::TablesNotIndexed`] if the tables
+/// are not indexed.
+pub fn is_fully_coalesced(tables: &tskit::TableCollection) -> Result<bool, SimulationError> {
+    let treeseq = tree_sequence(tables)?;
+    let mut tree_iterator = treeseq.tree_iterator(tskit::TreeFlags::default())?;
+    while let Some(tree) = tree_iterator.next() {
+        if tree.roots_to_vec().len() != 1 {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// The expected time, in birth steps, of the most recent
+/// common ancestor of `nsamples` genomes under the neutral
+/// model: one deme, standard reproduction, and no
+/// selection or selfing.
+///
+/// Going back one step, two genomes in different individuals
+/// coalesce with probability `(1 - psurvival^2) / (ploidy * popsize)`:
+/// either both are in offspring that inherit from the
+/// same parental genome, or one is in an offspring that
+/// inherits from the other, which survived.  With this
+/// pairwise rate `r`, the coalescent gives
+/// `2 / r * (1 - 1 / nsamples)`.  For `psurvival = 0`
+/// and ploidy 2, that is the Wright-Fisher `4N(1 - 1/n)`
+/// generations.  With a ploidy above 2, two genomes of the
+/// same individual from the same parent coalesce much
+/// faster, so the true value is smaller.
+pub fn expected_tmrca(params: &SimParams, nsamples: usize) -> f64 {
+    let genomes = params.ploidy as f64 * params.popsize as f64;
+    let rate = (1.0 - params.psurvival * params.psurvival) / genomes;
+    2.0 / rate * (1.0 - 1.0 / nsamples as f64)
+}
+
+/// A summary statistic of the final generation,
+/// for the `--stats-only` results of the binaries.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Statistic {
+    /// [`mean_pairwise_diversity`].
+    Diversity,
+    /// [`segregating_sites`].
+    SegregatingSites,
+    /// [`mean_tmrca`], in the units of the node times.
+    MeanTmrca,
+}
+
+impl std::str::FromStr for Statistic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "diversity" => Ok(Statistic::Diversity),
+            "segregating_sites" => Ok(Statistic::SegregatingSites),
+            "mean_tmrca" => Ok(Statistic::MeanTmrca),
+            _ => Err(format!(
+                "unknown statistic {}, expected diversity, segregating_sites, or mean_tmrca",
+                s
+            )),
+        }
+    }
+}
+
+impl Statistic {
+    pub const ALL: [Statistic; 3] = [
+        Statistic::Diversity,
+        Statistic::SegregatingSites,
+        Statistic::MeanTmrca,
+    ];
+
+    /// The name of the statistic, as parsed by `from_str`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Statistic::Diversity => "diversity",
+            Statistic::SegregatingSites => "segregating_sites",
+            Statistic::MeanTmrca => "mean_tmrca",
+        }
+    }
+
+    /// The value of the statistic for `samples`.
+    ///
+    /// # Errors
+    ///
+    /// As for the function computing it.
+    pub fn compute(
+        &self,
+        tables: &tskit::TableCollection,
+        samples: &[tskit::tsk_id_t],
+    ) -> Result<f64, SimulationError> {
+        match self {
+            Statistic::Diversity => mean_pairwise_diversity(tables, samples),
+            Statistic::SegregatingSites => Ok(segregating_sites(tables, samples)? as f64),
+            Statistic::MeanTmrca => mean_tmrca(tables),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Four samples, 0 to 3, in one tree on `[0, 10)`:
+    /// ((0, 1)4, (2, 3)5)6, with nodes 4, 5 and 6 at
+    /// times 1, 2 and 3.  Sites are at positions 1 to 6,
+    /// with mutations above the listed nodes:
+    ///
+    /// | site | mutations | derived samples |
+    /// |------|-----------|-----------------|
+    /// | 1    | 0         | 1               |
+    /// | 2    | 4         | 2               |
+    /// | 3    | 5, 0      | 3               |
+    /// | 4    | 6         | 4               |
+    /// | 5    | none      | 0               |
+    /// | 6    | 2         | 1               |
+    fn four_sample_tables() -> tskit::TableCollection {
+        let mut tables = tskit::TableCollection::new(10.0).unwrap();
+        for time in [0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0] {
+            let flags = if time == 0.0 {
+                tskit::TSK_NODE_IS_SAMPLE
+            } else {
+                0
+            };
+            tables
+                .add_node(flags, time, tskit::TSK_NULL, tskit::TSK_NULL)
+                .unwrap();
+        }
+        for (parent, child) in [(4, 0), (4, 1), (5, 2), (5, 3), (6, 4), (6, 5)] {
+            tables.add_edge(0.0, 10.0, parent, child).unwrap();
+        }
+        let nodes_per_site: [&[tskit::tsk_id_t]; 6] = [&[0], &[4], &[5, 0], &[6], &[], &[2]];
+        for (i, nodes) in nodes_per_site.iter().enumerate() {
+            let site = tables.add_site(i as f64 + 1.0, Some(b"0")).unwrap();
+            for &u in nodes.iter() {
+                let time = tables.nodes().time(u).unwrap() + 0.5;
+                tables
+                    .add_mutation(site, u, tskit::TSK_NULL, time, Some(b"1"))
+                    .unwrap();
+            }
+        }
+        tables
+            .full_sort(tskit::TableSortOptions::default())
+            .unwrap();
+        tables.build_index().unwrap();
+        tables
+    }
+
+    #[test]
+    fn spectrum_bins_count_derived_samples() {
+        let tables = four_sample_tables();
+        let samples = [0, 1, 2, 3];
+        assert_eq!(
+            allele_frequency_spectrum(&tables, &samples, true).unwrap(),
+            [1, 2, 1, 1, 1]
+        );
+        assert_eq!(
+            allele_frequency_spectrum(&tables, &samples, false).unwrap(),
+            [2, 3, 1, 0, 0]
+        );
+        assert_eq!(segregating_sites(&tables, &samples).unwrap(), 4);
+        // Among samples 0 and 1 only, sites 1 and 3 segregate.
+        assert_eq!(
+            allele_frequency_spectrum(&tables, &[0, 1], true).unwrap(),
+            [2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn tajimas_d_matches_a_hand_computation() {
+        let tables = four_sample_tables();
+        // From the spectrum [1, 2, 1, 1, 1], S = 4 and
+        // pi = (2 * 1 * 3 + 1 * 2 * 2 + 1 * 3 * 1) / 6 = 13 / 6.
+        // With a1 = 11 / 6, a2 = 49 / 36, and e1 and e2 from
+        // Tajima (1989), D = (13 / 6 - 4 / a1) / sqrt(e1 S + e2 S (S - 1)).
+        let d = tajimas_d(&tables, &[0, 1, 2, 3]).unwrap();
+        assert!((d - -0.06501024948259031).abs() < 1e-12, "{}", d);
+
+        assert!(matches!(
+            tajimas_d(&tables, &[0]),
+            Err(SimulationError::UndefinedStatistic(_))
+        ));
+    }
+
+    #[test]
+    fn mean_tmrca_weights_root_times_by_span() {
+        // Samples 0 and 1 coalesce in node 3, at time 3, on
+        // [0, 4), and in node 2, at time 2, on [4, 10).
+        let mut tables = tskit::TableCollection::new(10.0).unwrap();
+        for time in [0.0, 0.0, 2.0, 3.0] {
+            let flags = if time == 0.0 {
+                tskit::TSK_NODE_IS_SAMPLE
+            } else {
+                0
+            };
+            tables
+                .add_node(flags, time, tskit::TSK_NULL, tskit::TSK_NULL)
+                .unwrap();
+        }
+        for child in [0, 1] {
+            tables.add_edge(4.0, 10.0, 2, child).unwrap();
+            tables.add_edge(0.0, 4.0, 3, child).unwrap();
+        }
+        tables
+            .full_sort(tskit::TableSortOptions::default())
+            .unwrap();
+        tables.build_index().unwrap();
+        assert_eq!(mean_tmrca(&tables).unwrap(), (4.0 * 3.0 + 6.0 * 2.0) / 10.0);
+    }
+
+    #[test]
+    fn mean_tmrca_is_close_to_its_expectation() {
+        let params = SimParams {
+            popsize: 10,
+            nsteps: 400,
+            xovers: 5.0,
+            genome_length: 1e4,
+            ..SimParams::default()
+        };
+        let replicates = 40;
+        let mut values = vec![];
+        for seed in 0..replicates {
+            let tables = crate::diploid::DiploidSimulation::new(params, seed)
+                .unwrap()
+                .run()
+                .unwrap();
+            assert!(is_fully_coalesced(&tables).unwrap());
+            values.push(mean_tmrca(&tables).unwrap());
+        }
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        let standard_error = (variance / n).sqrt();
+        let expected = expected_tmrca(&params, params.num_alive_nodes());
+        assert!(
+            (mean - expected).abs() < 4.0 * standard_error,
+            "mean {} +/- {}, expected {}",
+            mean,
+            standard_error,
+            expected
+        );
+    }
+}
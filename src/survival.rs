@@ -0,0 +1,91 @@
+use crate::error::SimulationError;
+
+/// Survival probabilities that depend on age.
+///
+/// A diploid's age is the number of steps it has survived,
+/// so 0 at birth.  At each step, a diploid of age `a`
+/// survives with probability `psurvival[a]`.  A diploid
+/// older than [`SurvivalSchedule::max_age`] always dies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SurvivalSchedule {
+    psurvival: Vec<f64>,
+}
+
+impl SurvivalSchedule {
+    /// Build a schedule from the survival probabilities
+    /// of ages `0, 1, ..., psurvival.len() - 1`.
+    ///
+    /// There must be at least one age, and probabilities
+    /// must be in `[0.0, 1.0]`.
+    pub fn new(psurvival: Vec<f64>) -> Result<Self, String> {
+        if psurvival.is_empty() {
+            return Err("a survival schedule needs at least one age".to_string());
+        }
+        if let Some((age, p)) = psurvival
+            .iter()
+            .enumerate()
+            .find(|(_, p)| !(0.0..=1.0).contains(*p))
+        {
+            return Err(format!(
+                "survival probabilities must be in [0.0, 1.0], got {} at age {}",
+                p, age
+            ));
+        }
+        Ok(Self { psurvival })
+    }
+
+    /// Read a schedule from a file with two tab-separated
+    /// columns, age and survival probability, one age per
+    /// line.  Ages must start at 0 and go up by one.  Blank
+    /// lines and lines starting with `#` are skipped.
+    pub fn from_tsv(path: &str) -> Result<Self, SimulationError> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut psurvival = vec![];
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let parsed = match fields.as_slice() {
+                [a, p] => a
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|a| p.trim().parse::<f64>().ok().map(|p| (a, p))),
+                _ => None,
+            };
+            match parsed {
+                Some((age, p)) if age == psurvival.len() => psurvival.push(p),
+                Some((age, _)) => {
+                    return Err(SimulationError::InvalidParameters(format!(
+                        "{}, line {}: expected age {}, got {}",
+                        path,
+                        lineno + 1,
+                        psurvival.len(),
+                        age
+                    )))
+                }
+                None => {
+                    return Err(SimulationError::InvalidParameters(format!(
+                        "{}, line {}: expected an integer age and a survival probability, separated by a tab",
+                        path,
+                        lineno + 1
+                    )))
+                }
+            }
+        }
+        Self::new(psurvival).map_err(SimulationError::InvalidParameters)
+    }
+
+    /// The oldest age with a survival probability.
+    pub fn max_age(&self) -> u32 {
+        self.psurvival.len() as u32 - 1
+    }
+
+    /// The probability that a diploid of `age` survives
+    /// the next step, which is 0.0 past [`SurvivalSchedule::max_age`].
+    pub fn psurvival(&self, age: u32) -> f64 {
+        self.psurvival.get(age as usize).copied().unwrap_or(0.0)
+    }
+}
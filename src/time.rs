@@ -0,0 +1,127 @@
+use tskit::TableAccess;
+
+use crate::diploid::SimParams;
+use crate::error::SimulationError;
+
+/// Units for node and mutation times in the output.
+///
+/// The simulations record times in birth steps before
+/// the end of the run.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnits {
+    /// Birth steps, as recorded.
+    Steps,
+    /// Generations.  A fraction `1 - psurvival` of the
+    /// population is replaced each step, so one step is
+    /// `1 - psurvival` generations.
+    Generations,
+    /// Generations divided by `ploidy * popsize`.
+    Coalescent,
+}
+
+impl std::str::FromStr for TimeUnits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "steps" => Ok(TimeUnits::Steps),
+            "generations" => Ok(TimeUnits::Generations),
+            "coalescent" => Ok(TimeUnits::Coalescent),
+            _ => Err(format!(
+                "unknown time units {}, expected steps, generations, or coalescent",
+                s
+            )),
+        }
+    }
+}
+
+impl TimeUnits {
+    /// The number of these units per birth step.
+    pub fn scale(&self, params: &SimParams) -> f64 {
+        match self {
+            TimeUnits::Steps => 1.0,
+            TimeUnits::Generations => 1.0 - params.psurvival,
+            TimeUnits::Coalescent => {
+                (1.0 - params.psurvival) / (params.ploidy as f64 * params.popsize as f64)
+            }
+        }
+    }
+}
+
+/// Multiply all node and mutation times by `scale`.
+///
+/// # Errors
+///
+/// [`SimulationError::InvalidParameters`] if, afterwards,
+/// a parent is not older than its child or a mutation is
+/// younger than its node, which can happen if `scale` is
+/// not positive or times underflow.
+pub fn rescale_times(
+    tables: &mut tskit::TableCollection,
+    scale: f64,
+) -> Result<(), SimulationError> {
+    use tskit::TskitTypeAccess;
+
+    if scale == 1.0 {
+        return Ok(());
+    }
+
+    for t in tables.nodes().time_array_mut() {
+        *t *= scale;
+    }
+    // tskit 0.3.0 has no safe mutable access to mutation times.
+    let mutations = unsafe { &mut (*tables.as_mut_ptr()).mutations };
+    let mutation_times =
+        unsafe { std::slice::from_raw_parts_mut(mutations.time, mutations.num_rows as usize) };
+    for t in mutation_times {
+        *t *= scale;
+    }
+
+    let nodes = tables.nodes();
+    let edges = tables.edges();
+    for e in 0..edges.num_rows() as tskit::tsk_id_t {
+        let parent_time = nodes.time(edges.parent(e)?)?;
+        let child_time = nodes.time(edges.child(e)?)?;
+        match parent_time.partial_cmp(&child_time) {
+            Some(std::cmp::Ordering::Greater) => (),
+            Some(_) | None => {
+                return Err(SimulationError::InvalidParameters(format!(
+                    "rescaling times by {} gives edge {} a parent time of {} and a child time of {}",
+                    scale, e, parent_time, child_time
+                )));
+            }
+        }
+    }
+    let mutations = tables.mutations();
+    for m in 0..mutations.num_rows() as tskit::tsk_id_t {
+        let time = mutations.time(m)?;
+        let node_time = nodes.time(mutations.node(m)?)?;
+        match time.partial_cmp(&node_time) {
+            Some(std::cmp::Ordering::Greater) | Some(std::cmp::Ordering::Equal) => (),
+            Some(_) | None => {
+                return Err(SimulationError::InvalidParameters(format!(
+                    "rescaling times by {} gives mutation {} a time of {}, below its node's time of {}",
+                    scale, m, time, node_time
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add `offset` to all node and mutation times.
+pub fn shift_times(tables: &mut tskit::TableCollection, offset: f64) {
+    use tskit::TskitTypeAccess;
+
+    for t in tables.nodes().time_array_mut() {
+        *t += offset;
+    }
+    // tskit 0.3.0 has no safe mutable access to mutation times.
+    let mutations = unsafe { &mut (*tables.as_mut_ptr()).mutations };
+    let mutation_times =
+        unsafe { std::slice::from_raw_parts_mut(mutations.time, mutations.num_rows as usize) };
+    for t in mutation_times {
+        *t += offset;
+    }
+}
@@ -0,0 +1,1431 @@
+//! End-to-end runs of the simulations, checked after
+//! writing the tables to a tree file and reading them back.
+
+use example_tskit_rust_simulations::demography::{Demography, SizeChange};
+use example_tskit_rust_simulations::diploid::{
+    alive_sample_nodes, check_integrity, check_popsize, death_and_parents, simplify,
+    DiploidSimulation, Individual, ParentSelector, SimParams, MAX_NSTEPS,
+};
+use example_tskit_rust_simulations::export::{dump_tables_csv, edge_differences};
+use example_tskit_rust_simulations::haploid::{founders, moran_step, simplify_haploid};
+use example_tskit_rust_simulations::metadata::{
+    set_simulation_metadata, simulation_metadata, NodeMetadata, PopulationMetadata,
+    SimulationMetadata,
+};
+use example_tskit_rust_simulations::mutmap::MutationMap;
+use example_tskit_rust_simulations::output::{gzip_file, write_tables};
+use example_tskit_rust_simulations::rng::SimRng;
+use example_tskit_rust_simulations::stats::{expected_tmrca, is_fully_coalesced};
+use example_tskit_rust_simulations::survival::SurvivalSchedule;
+use rand::SeedableRng;
+use streaming_iterator::StreamingIterator;
+use tskit::TableAccess;
+
+/// Write `tables` to a temporary tree file and load it.
+fn round_trip(tables: &tskit::TableCollection) -> tskit::TableCollection {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("output.trees");
+    let path = path.to_str().unwrap();
+    write_tables(tables, path, true).unwrap();
+    tskit::TableCollection::new_from_file(path).unwrap()
+}
+
+/// The number of roots of each tree.
+fn roots_per_tree(tables: &tskit::TableCollection) -> Vec<usize> {
+    let treeseq = tables
+        .deepcopy()
+        .unwrap()
+        .tree_sequence(tskit::TreeSequenceFlags::default())
+        .unwrap();
+    let mut roots = vec![];
+    let mut trees = treeseq.tree_iterator(tskit::TreeFlags::default()).unwrap();
+    while let Some(tree) = trees.next() {
+        roots.push(tree.roots_to_vec().len());
+    }
+    roots
+}
+
+/// Check that `samples` are exactly the sample nodes, that
+/// the tables pass [`check_integrity`], which includes edge
+/// order, and that every parent is older than its child.
+fn assert_valid_output(tables: &tskit::TableCollection, samples: &[tskit::tsk_id_t]) {
+    let mut flagged = tables.nodes().samples_as_vector();
+    flagged.sort_unstable();
+    let mut expected = samples.to_vec();
+    expected.sort_unstable();
+    assert_eq!(flagged, expected);
+
+    check_integrity(tables).unwrap();
+
+    let nodes = tables.nodes();
+    let edges = tables.edges();
+    for e in 0..edges.num_rows() as tskit::tsk_id_t {
+        let parent_time = nodes.time(edges.parent(e).unwrap()).unwrap();
+        let child_time = nodes.time(edges.child(e).unwrap()).unwrap();
+        assert!(parent_time > child_time, "edge {}", e);
+    }
+}
+
+fn diploid_params() -> SimParams {
+    SimParams {
+        popsize: 50,
+        nsteps: 2000,
+        xovers: 2.0,
+        psurvival: 0.5,
+        genome_length: 1e4,
+        simplification_interval: 50,
+        mutrate: 1e-4,
+        ..SimParams::default()
+    }
+}
+
+#[test]
+fn diploid_run_round_trip() {
+    let mut sim = DiploidSimulation::new(diploid_params(), 42).unwrap();
+    let tables = sim.run().unwrap();
+    let samples = alive_sample_nodes(sim.alive());
+    assert_eq!(samples.len(), 100);
+
+    let loaded = round_trip(&tables);
+    assert!(loaded.equals(&tables, tskit::TableEqualityOptions::default()));
+    assert_valid_output(&loaded, &samples);
+
+    // The run is several times the expected TMRCA,
+    // so each tree has coalesced.
+    assert!(diploid_params().nsteps as f64 > 5.0 * expected_tmrca(&diploid_params(), 100));
+    let roots = roots_per_tree(&loaded);
+    assert!(roots.len() > 1);
+    assert!(roots.iter().all(|&r| r == 1));
+    assert!(is_fully_coalesced(&loaded).unwrap());
+}
+
+#[test]
+fn diploid_run_is_deterministic() {
+    let run = |seed| {
+        DiploidSimulation::new(diploid_params(), seed)
+            .unwrap()
+            .run()
+            .unwrap()
+    };
+    let tables = run(7);
+    assert!(tables.equals(&run(7), tskit::TableEqualityOptions::default()));
+    assert!(!tables.equals(&run(8), tskit::TableEqualityOptions::default()));
+}
+
+#[test]
+fn moran_run_round_trip() {
+    let popsize = 20;
+    let nsteps = 4000;
+    let mut rng = SimRng::seed_from_u64(42);
+    let mut tables = tskit::TableCollection::new(1.0).unwrap();
+    let mut alive = founders(popsize, nsteps as f64, &mut tables).unwrap();
+    for step in (0..nsteps).rev() {
+        moran_step(&mut alive, step as f64, &mut tables, &mut rng).unwrap();
+        if step % 100 == 0 {
+            simplify_haploid(&mut alive, &mut tables, false).unwrap();
+        }
+    }
+    tables.build_index().unwrap();
+
+    let loaded = round_trip(&tables);
+    assert_valid_output(&loaded, &alive);
+
+    // Without recombination there is a single tree, and
+    // 4000 steps is ten times the expected TMRCA of N^2 steps.
+    assert_eq!(roots_per_tree(&loaded), vec![1]);
+}
+
+/// Run overlapping_generations with `args`, writing
+/// `name.trees` in `dir`, and return the seed it used,
+/// from the `.seed` file, and the tables.
+fn run_overlapping_generations(
+    dir: &std::path::Path,
+    name: &str,
+    args: &[&str],
+) -> (u64, tskit::TableCollection) {
+    let treefile = dir.join(format!("{}.trees", name));
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .args(["-N", "50", "-n", "200", "-x", "1", "-t"])
+        .arg(&treefile)
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let seed = std::fs::read_to_string(dir.join(format!("{}.seed", name)))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    let tables = tskit::TableCollection::new_from_file(treefile.to_str().unwrap()).unwrap();
+    (seed, tables)
+}
+
+#[test]
+fn unseeded_runs_differ_and_recorded_seed_reproduces() {
+    let dir = tempfile::tempdir().unwrap();
+    let (seed1, tables1) = run_overlapping_generations(dir.path(), "first", &[]);
+    let (seed2, tables2) = run_overlapping_generations(dir.path(), "second", &[]);
+    assert_ne!(seed1, seed2);
+    let ignore_provenance = tskit::TableEqualityOptions::IGNORE_PROVENANCE;
+    assert!(!tables1.equals(&tables2, ignore_provenance));
+
+    let seed = seed1.to_string();
+    let (seed3, tables3) = run_overlapping_generations(dir.path(), "third", &["--seed", &seed]);
+    assert_eq!(seed3, seed1);
+    assert!(tables1.equals(&tables3, ignore_provenance));
+}
+
+#[test]
+fn csv_rows_match_tables() {
+    let tables = DiploidSimulation::new(diploid_params(), 3)
+        .unwrap()
+        .run()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("tables");
+    let prefix = prefix.to_str().unwrap();
+    dump_tables_csv(&tables, prefix).unwrap();
+
+    let nodes = std::fs::read_to_string(format!("{}.nodes.csv", prefix)).unwrap();
+    let mut lines = nodes.lines();
+    assert_eq!(lines.next(), Some("id,flags,time,population,individual"));
+    assert_eq!(lines.count(), tables.nodes().num_rows() as usize);
+
+    let edges = std::fs::read_to_string(format!("{}.edges.csv", prefix)).unwrap();
+    let mut lines = edges.lines();
+    assert_eq!(lines.next(), Some("id,left,right,parent,child"));
+    assert_eq!(lines.count(), tables.edges().num_rows() as usize);
+}
+
+#[test]
+fn mismatched_alive_length_is_reported() {
+    let mut params = diploid_params();
+    params.nsteps = 10;
+    let mut sim = DiploidSimulation::new(params, 4).unwrap();
+    sim.run().unwrap();
+    let mut alive = sim.alive().to_vec();
+    check_popsize(&alive, &params).unwrap();
+
+    alive.pop();
+    let e = check_popsize(&alive, &params).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "invariant violated: there are 49 individuals alive, but popsize is 50"
+    );
+}
+
+#[test]
+fn table_size_log_has_a_row_per_step() {
+    let params = diploid_params();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sizes.csv");
+    let path = path.to_str().unwrap();
+    let mut sim = DiploidSimulation::new(params, 5).unwrap();
+    sim.set_table_size_log(Some(path)).unwrap();
+    let tables = sim.run().unwrap();
+
+    let log = std::fs::read_to_string(path).unwrap();
+    let mut lines = log.lines();
+    assert_eq!(lines.next(), Some("step,num_nodes,num_edges"));
+    let rows = lines.collect::<Vec<_>>();
+    assert_eq!(rows.len(), params.nsteps as usize);
+    assert!(rows[0].starts_with(&format!("{},", params.nsteps - 1)));
+    assert_eq!(
+        rows[rows.len() - 1],
+        format!(
+            "0,{},{}",
+            tables.nodes().num_rows(),
+            tables.edges().num_rows()
+        )
+    );
+}
+
+#[test]
+fn start_from_rejects_a_different_genome_length() {
+    let mut params = diploid_params();
+    params.nsteps = 100;
+    let tables = DiploidSimulation::new(params, 6).unwrap().run().unwrap();
+    let loaded = round_trip(&tables);
+
+    params.genome_length *= 2.0;
+    let e = DiploidSimulation::start_from(params, 7, loaded)
+        .err()
+        .unwrap();
+    assert_eq!(
+        e.to_string(),
+        "the tables have sequence length 10000, but genome_length is 20000"
+    );
+}
+
+#[test]
+fn deme_names_round_trip() {
+    let mut params = diploid_params();
+    params.nsteps = 100;
+    params.demes = 3;
+    let tables = DiploidSimulation::new(params, 8).unwrap().run().unwrap();
+    let loaded = round_trip(&tables);
+
+    let populations = loaded.populations();
+    assert_eq!(populations.num_rows(), 3);
+    for d in 0..3 {
+        let md = populations
+            .metadata::<PopulationMetadata>(d as tskit::tsk_id_t)
+            .unwrap();
+        assert_eq!(md, Some(PopulationMetadata::deme(d)));
+    }
+}
+
+#[test]
+fn node_birth_steps_round_trip() {
+    let mut params = diploid_params();
+    params.nsteps = 100;
+    params.node_metadata = true;
+    let tables = DiploidSimulation::new(params, 31).unwrap().run().unwrap();
+    let loaded = round_trip(&tables);
+
+    let nodes = loaded.nodes();
+    assert!(nodes.num_rows() > 0);
+    for u in 0..nodes.num_rows() as tskit::tsk_id_t {
+        let md = nodes.metadata::<NodeMetadata>(u).unwrap().unwrap();
+        assert_eq!(md.birth_step as f64, nodes.time(u).unwrap(), "node {}", u);
+    }
+}
+
+/// The final frequency of the followed selected mutation,
+/// over runs with seeds 1 to 20, checking each trajectory.
+/// The `(step, position, frequency)` rows of the
+/// trajectory of a run with selected mutations.
+fn trajectory_rows(selection_coefficient: f64, seed: u64) -> Vec<(u32, f64, f64)> {
+    let mut params = diploid_params();
+    params.nsteps = 300;
+    params.selected_mutrate = 1e-6;
+    params.selection_coefficient = selection_coefficient;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trajectory.csv");
+    let path = path.to_str().unwrap();
+
+    let mut sim = DiploidSimulation::new(params, seed).unwrap();
+    sim.set_trajectory(Some(path)).unwrap();
+    sim.run().unwrap();
+
+    let trajectory = std::fs::read_to_string(path).unwrap();
+    let mut lines = trajectory.lines();
+    assert_eq!(lines.next(), Some("step,position,frequency"));
+    let rows = lines
+        .map(|line| {
+            let fields = line.split(',').collect::<Vec<_>>();
+            (
+                fields[0].parse::<u32>().unwrap(),
+                fields[1].parse::<f64>().unwrap(),
+                fields[2].parse::<f64>().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert!(!rows.is_empty());
+    assert_eq!(rows[rows.len() - 1].0, 0);
+    for (i, row) in rows.iter().enumerate() {
+        assert_eq!(row.0 as usize, rows.len() - 1 - i);
+        assert_eq!(row.1, rows[0].1);
+        assert!((0.0..=1.0).contains(&row.2));
+    }
+    // The mutation arises on one node.
+    assert!(rows[0].2 > 0.0);
+    rows
+}
+
+fn final_trajectory_frequencies(selection_coefficient: f64) -> Vec<f64> {
+    (1..=20)
+        .map(|seed| {
+            let rows = trajectory_rows(selection_coefficient, seed);
+            rows[rows.len() - 1].2
+        })
+        .collect()
+}
+
+#[test]
+fn selected_trajectory_drifts_when_neutral_and_rises_when_favoured() {
+    // A neutral mutation's frequency is a martingale starting
+    // at 1 / 100, so on average it ends near there.
+    let neutral = final_trajectory_frequencies(0.0);
+    let mean = neutral.iter().sum::<f64>() / neutral.len() as f64;
+    assert!(mean < 0.1, "mean final neutral frequency {}", mean);
+
+    let favoured = final_trajectory_frequencies(2.0);
+    let fixed = favoured.iter().filter(|&&f| f == 1.0).count();
+    assert!(fixed >= favoured.len() / 2, "{:?}", favoured);
+}
+
+#[test]
+fn node_times_stay_ordered_at_max_nsteps() {
+    for continuous_time in [false, true] {
+        let mut params = diploid_params();
+        params.nsteps = MAX_NSTEPS;
+        params.continuous_time = continuous_time;
+        let mut sim = DiploidSimulation::new(params, 9).unwrap();
+        // Only the first few of the 2^52 steps.
+        for birth_time in (MAX_NSTEPS - 5..MAX_NSTEPS).rev() {
+            sim.step(birth_time).unwrap();
+        }
+
+        let nodes = sim.tables().nodes();
+        let edges = sim.tables().edges();
+        assert_eq!(nodes.time(0).unwrap(), MAX_NSTEPS as f64);
+        assert!(edges.num_rows() > 0);
+        for e in 0..edges.num_rows() as tskit::tsk_id_t {
+            let parent = nodes.time(edges.parent(e).unwrap()).unwrap();
+            let child = nodes.time(edges.child(e).unwrap()).unwrap();
+            assert!(
+                parent > child,
+                "parent time {}, child time {}",
+                parent,
+                child
+            );
+            assert!(child >= (MAX_NSTEPS - 5) as f64);
+        }
+    }
+
+    let mut params = diploid_params();
+    params.nsteps = MAX_NSTEPS + 1;
+    assert!(DiploidSimulation::new(params, 9).is_err());
+}
+
+/// The indexes of the diploids that die in one step of
+/// a population from `founders`, in the order visited.
+fn death_order(params: &SimParams, seed: u64) -> Vec<usize> {
+    use example_tskit_rust_simulations::diploid::{death_and_parents, UniformSelector};
+    let mut tables = tskit::TableCollection::new(params.genome_length).unwrap();
+    let alive = example_tskit_rust_simulations::diploid::founders(params, &mut tables).unwrap();
+    let mut selector = UniformSelector::new(params);
+    let mut parents = vec![];
+    let mut rng = SimRng::seed_from_u64(seed);
+    death_and_parents(&alive, params, &mut selector, &mut parents, &mut rng).unwrap();
+    parents.iter().map(|p| p.index).collect()
+}
+
+#[test]
+fn shuffled_death_order_follows_the_rng() {
+    let mut params = diploid_params();
+    params.psurvival = 0.0;
+    let in_order = (0..params.popsize as usize).collect::<Vec<_>>();
+    assert_eq!(death_order(&params, 10), in_order);
+
+    params.shuffle_deaths = true;
+    let shuffled = death_order(&params, 10);
+    assert_eq!(death_order(&params, 10), shuffled);
+    assert_ne!(shuffled, in_order);
+    assert_ne!(death_order(&params, 11), shuffled);
+    let mut sorted = shuffled.clone();
+    sorted.sort_unstable();
+    assert_eq!(sorted, in_order);
+}
+
+#[test]
+fn buffered_edges_do_not_change_the_output() {
+    let mut params = diploid_params();
+    params.record_individuals = true;
+    params.sample_every = 300;
+    let unbuffered = DiploidSimulation::new(params, 12).unwrap().run().unwrap();
+    params.buffer_edges = true;
+    let buffered = DiploidSimulation::new(params, 12).unwrap().run().unwrap();
+
+    // Each dump has its own file uuid, so compare the
+    // tables read back rather than the bytes.
+    assert!(round_trip(&unbuffered).equals(
+        &round_trip(&buffered),
+        tskit::TableEqualityOptions::default()
+    ));
+}
+
+#[test]
+fn short_run_is_not_coalesced() {
+    let mut params = diploid_params();
+    params.nsteps = 10;
+    let tables = DiploidSimulation::new(params, 13).unwrap().run().unwrap();
+    assert!(roots_per_tree(&tables).iter().any(|&r| r > 1));
+    assert!(!is_fully_coalesced(&tables).unwrap());
+
+    let dir = tempfile::tempdir().unwrap();
+    let treefile = dir.path().join("short.trees");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .args(["-N", "50", "-n", "10", "--require-coalescence", "-t"])
+        .arg(&treefile)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert!(treefile.exists());
+}
+
+#[test]
+fn mutation_map_leaves_zero_rate_region_empty() {
+    let params = diploid_params();
+    let map = MutationMap::new(vec![0.0, 4e3, 6e3], vec![1e-4, 0.0, 1e-4], 1e4).unwrap();
+    let mut sim = DiploidSimulation::new(params, 17).unwrap();
+    sim.set_mutation_map(map).unwrap();
+    let tables = sim.run().unwrap();
+
+    let sites = tables.sites();
+    let positions = (0..sites.num_rows() as tskit::tsk_id_t)
+        .map(|s| sites.position(s).unwrap())
+        .collect::<Vec<_>>();
+    assert!(positions.iter().any(|&p| p < 4e3));
+    assert!(positions.iter().any(|&p| p >= 6e3));
+    assert!(positions.iter().all(|&p| !(4e3..6e3).contains(&p)));
+}
+
+#[test]
+fn unindexed_output_loads_and_can_be_indexed() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, mut tables) =
+        run_overlapping_generations(dir.path(), "unindexed", &["-m", "1e-3", "--no-index"]);
+    assert!(!tables.is_indexed());
+    tables.build_index().unwrap();
+    assert!(tables.is_indexed());
+    assert!(!roots_per_tree(&tables).is_empty());
+}
+
+#[test]
+fn run_indexes_mutated_tables_only_when_asked() {
+    let indexed = DiploidSimulation::new(diploid_params(), 19)
+        .unwrap()
+        .run()
+        .unwrap();
+    assert!(indexed.is_indexed());
+    assert!(indexed.mutations().num_rows() > 0);
+
+    let mut sim = DiploidSimulation::new(diploid_params(), 19).unwrap();
+    sim.set_build_index(false);
+    let mut unindexed = sim.run().unwrap();
+    assert!(!unindexed.is_indexed());
+    unindexed.build_index().unwrap();
+    assert!(unindexed.equals(&indexed, tskit::TableEqualityOptions::default()));
+}
+
+#[test]
+fn diploids_past_the_max_age_always_die() {
+    let params = SimParams {
+        nsteps: 100,
+        ..diploid_params()
+    };
+    let schedule = SurvivalSchedule::new(vec![1.0; 3]).unwrap();
+    assert_eq!(schedule.max_age(), 2);
+    let mut sim = DiploidSimulation::new(params, 23).unwrap();
+    sim.set_survival_schedule(schedule);
+    for birth_time in (0..params.nsteps).rev() {
+        let before = sim.ages().to_vec();
+        sim.step(birth_time).unwrap();
+        for (&old, &new) in before.iter().zip(sim.ages()) {
+            if old > 2 {
+                assert_eq!(new, 0);
+            } else {
+                assert_eq!(new, old + 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn sample_every_must_be_less_than_nsteps() {
+    let mut params = SimParams {
+        nsteps: 100,
+        sample_every: 99,
+        ..diploid_params()
+    };
+    assert!(params.validate().is_ok());
+    params.sample_every = 100;
+    let e = DiploidSimulation::new(params, 29).err().unwrap();
+    assert_eq!(
+        e.to_string(),
+        "sample_every must be < nsteps for any ancient samples to be kept, got 100 and 100"
+    );
+}
+
+#[test]
+fn warns_when_only_the_last_step_simplifies() {
+    let warned = |nsteps: &str| {
+        let dir = tempfile::tempdir().unwrap();
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+            .args(["-N", "10", "-n", nsteps, "--simplify", "100", "-v"])
+            .arg("-t")
+            .arg(dir.path().join("warn.trees"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("only the last step simplifies")
+    };
+    assert!(!warned("101"));
+    assert!(warned("100"));
+}
+
+#[test]
+fn one_transition_between_each_pair_of_trees() {
+    let params = SimParams {
+        nsteps: 500,
+        ..diploid_params()
+    };
+    let tables = DiploidSimulation::new(params, 31).unwrap().run().unwrap();
+    let ntrees = roots_per_tree(&tables).len();
+    assert!(ntrees > 2);
+
+    let transitions = edge_differences(&tables).unwrap();
+    assert_eq!(transitions.len(), ntrees - 1);
+    assert_eq!(
+        transitions[transitions.len() - 1].right,
+        params.genome_length
+    );
+    for w in transitions.windows(2) {
+        assert_eq!(w[0].right, w[1].left);
+    }
+    for t in &transitions {
+        assert!(!t.edges_out.is_empty() || !t.edges_in.is_empty());
+    }
+}
+
+#[test]
+fn resumed_run_matches_an_uninterrupted_one() {
+    // The checkpoint falls between two simplifications, so it
+    // holds thousands of unsimplified, or buffered, edges whose
+    // f64 breakpoints must round trip exactly.
+    let nsteps = 310;
+    for (buffer_edges, substreams) in [(false, false), (true, true)] {
+        let params = SimParams {
+            popsize: 100,
+            nsteps,
+            xovers: 1.0,
+            psurvival: 0.0,
+            genome_length: 1e6,
+            simplification_interval: 100,
+            mutrate: 1e-6,
+            buffer_edges,
+            ..SimParams::default()
+        };
+        let new_sim = || {
+            let mut sim = DiploidSimulation::new(params, 5).unwrap();
+            sim.set_substreams(substreams);
+            sim
+        };
+        let straight = new_sim().run().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("checkpoint");
+        let prefix = prefix.to_str().unwrap();
+        let mut sim = new_sim();
+        sim.set_checkpointing(nsteps as u32 / 2, prefix);
+        let checkpointed = sim.run().unwrap();
+        assert!(checkpointed.equals(&straight, tskit::TableEqualityOptions::default()));
+
+        let mut sim = DiploidSimulation::from_checkpoint(params, prefix).unwrap();
+        assert_eq!(sim.remaining_steps(), nsteps / 2);
+        sim.set_substreams(substreams);
+        let resumed = sim.run().unwrap();
+        assert!(resumed.equals(&straight, tskit::TableEqualityOptions::default()));
+    }
+}
+
+#[test]
+fn quiet_runs_still_report_fatal_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.toml");
+    for binary in [
+        env!("CARGO_BIN_EXE_diploid_wright_fisher"),
+        env!("CARGO_BIN_EXE_overlapping_generations"),
+    ] {
+        for quiet in [&[][..], &["-q"][..]] {
+            let output = std::process::Command::new(binary)
+                .arg("--config")
+                .arg(&missing)
+                .args(quiet)
+                .output()
+                .unwrap();
+            assert!(!output.status.success());
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains("No such file"), "{}", stderr);
+        }
+    }
+}
+
+#[test]
+fn exponential_growth_reaches_the_final_popsize() {
+    let mut params = diploid_params();
+    params.nsteps = 200;
+    let rate = 0.002;
+    let expected = (params.popsize as f64 * (rate * params.nsteps as f64).exp()).round() as usize;
+    let mut sim = DiploidSimulation::new(params, 11).unwrap();
+    sim.set_demography(Demography::exponential(params.popsize, rate, params.nsteps).unwrap())
+        .unwrap();
+    let tables = round_trip(&sim.run().unwrap());
+    assert_eq!(sim.alive().len(), expected);
+    assert_eq!(tables.nodes().samples_as_vector().len(), 2 * expected);
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .args(["--growth-rate", "fast", "-t"])
+        .arg(tempfile::tempdir().unwrap().path().join("bad.trees"))
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn start_from_infers_popsize_and_reports_a_missing_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut params = diploid_params();
+    params.popsize = 30;
+    params.nsteps = 100;
+    let burnin = dir.path().join("burnin.trees");
+    write_tables(
+        &DiploidSimulation::new(params, 12).unwrap().run().unwrap(),
+        burnin.to_str().unwrap(),
+        false,
+    )
+    .unwrap();
+
+    let treefile = dir.path().join("continued.trees");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .args([
+            "-n",
+            "50",
+            "-x",
+            "1",
+            "-S",
+            "13",
+            "-L",
+            "10000",
+            "--start-from",
+        ])
+        .arg(&burnin)
+        .arg("-t")
+        .arg(&treefile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let tables = tskit::TableCollection::new_from_file(treefile.to_str().unwrap()).unwrap();
+    assert_eq!(tables.nodes().samples_as_vector().len(), 60);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .arg("--start-from")
+        .arg(dir.path().join("missing.trees"))
+        .arg("-t")
+        .arg(&treefile)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("[ERROR]"));
+}
+
+#[test]
+fn zero_time_limit_stops_at_the_first_simplification() {
+    let params = diploid_params();
+    let mut sim = DiploidSimulation::new(params, 14).unwrap();
+    sim.set_max_duration(Some(std::time::Duration::ZERO));
+    let tables = round_trip(&sim.run().unwrap());
+    let remaining = params.nsteps - params.simplification_interval as u64;
+    assert_eq!(sim.remaining_steps(), remaining);
+    let nodes = tables.nodes();
+    let youngest = (0..nodes.num_rows() as tskit::tsk_id_t)
+        .map(|n| nodes.time(n).unwrap())
+        .fold(f64::INFINITY, f64::min);
+    assert_eq!(youngest, remaining as f64);
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .args(["--max-seconds", "soon", "-t"])
+        .arg(tempfile::tempdir().unwrap().path().join("bad.trees"))
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+/// The largest edge table at the end of a step, after any
+/// simplification, from the table size log.
+fn max_edges_per_step(max_edges: Option<usize>) -> usize {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sizes.csv");
+    let path = path.to_str().unwrap();
+    let mut sim = DiploidSimulation::new(diploid_params(), 15).unwrap();
+    sim.set_simplify_when_edges(max_edges);
+    sim.set_table_size_log(Some(path)).unwrap();
+    sim.run().unwrap();
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|row| row.rsplit(',').next().unwrap().parse::<usize>().unwrap())
+        .max()
+        .unwrap()
+}
+
+#[test]
+fn edge_bound_limits_the_edge_table() {
+    let max_edges = 8000;
+    assert!(max_edges_per_step(None) > max_edges);
+    assert!(max_edges_per_step(Some(max_edges)) <= max_edges);
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .args(["--simplify-when-edges", "many", "-t"])
+        .arg(tempfile::tempdir().unwrap().path().join("bad.trees"))
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn sample_size_keeps_k_samples_reproducibly() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = ["--sample-size", "7", "-S", "16"];
+    let (_, first) = run_overlapping_generations(dir.path(), "first", &args);
+    let (_, second) = run_overlapping_generations(dir.path(), "second", &args);
+    assert_eq!(first.nodes().samples_as_vector().len(), 7);
+    assert!(first.equals(&second, tskit::TableEqualityOptions::IGNORE_PROVENANCE));
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .args(["--sample-size", "some", "-t"])
+        .arg(dir.path().join("bad.trees"))
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn wright_fisher_binary_matches_the_library() {
+    let dir = tempfile::tempdir().unwrap();
+    let treefile = dir.path().join("wf.trees");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_diploid_wright_fisher"))
+        .args([
+            "-N", "50", "-n", "200", "-x", "1", "-L", "10000", "-m", "1e-4",
+        ])
+        .args(["-S", "17", "-t"])
+        .arg(&treefile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let tables = tskit::TableCollection::new_from_file(treefile.to_str().unwrap()).unwrap();
+
+    let params = SimParams {
+        popsize: 50,
+        nsteps: 200,
+        xovers: 1.0,
+        genome_length: 1e4,
+        mutrate: 1e-4,
+        psurvival: 0.0,
+        ..SimParams::default()
+    };
+    let expected = DiploidSimulation::new(params, 17).unwrap().run().unwrap();
+    let options = tskit::TableEqualityOptions::IGNORE_PROVENANCE
+        | tskit::TableEqualityOptions::IGNORE_TS_METADATA;
+    assert!(tables.equals(&expected, options));
+}
+
+#[test]
+fn vcf_has_a_phased_column_per_individual_and_distinct_positions() {
+    let dir = tempfile::tempdir().unwrap();
+    let treefile = dir.path().join("vcf.trees");
+    let vcf = dir.path().join("out").join("genotypes.vcf");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_overlapping_generations"))
+        .args(["-N", "20", "-n", "200", "-x", "1", "-L", "50", "-m", "0.05"])
+        .args(["-S", "18", "--vcf"])
+        .arg(&vcf)
+        .arg("-t")
+        .arg(&treefile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let tables = tskit::TableCollection::new_from_file(treefile.to_str().unwrap()).unwrap();
+
+    let contents = std::fs::read_to_string(&vcf).unwrap();
+    let header = contents
+        .lines()
+        .find(|line| line.starts_with("#CHROM"))
+        .unwrap();
+    assert_eq!(header.split('\t').count(), 9 + 20);
+    assert!(header.ends_with("\ttsk_19"));
+    let rows = contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>();
+    assert_eq!(rows.len(), tables.sites().num_rows() as usize);
+    assert!(rows.len() > 50);
+
+    let mut previous = 0;
+    for row in rows {
+        let fields = row.split('\t').collect::<Vec<_>>();
+        let pos = fields[1].parse::<u64>().unwrap();
+        assert!(pos > previous);
+        previous = pos;
+        for gt in &fields[9..] {
+            assert_eq!(gt.len(), 3);
+            let alleles = gt.split('|').collect::<Vec<_>>();
+            assert_eq!(alleles.len(), 2);
+            assert!(alleles.iter().all(|&a| a == "0" || a == "1"));
+        }
+    }
+}
+
+#[test]
+fn strongly_beneficial_mutation_rises_to_fixation() {
+    let rows = trajectory_rows(2.0, 2);
+    assert_eq!(rows[0].2, 0.01);
+    assert_eq!(rows[rows.len() - 1].2, 1.0);
+    // A neutral allele at 1% would take hundreds of steps to
+    // reach half the population, if it ever did.
+    let half = rows.iter().position(|row| row.2 >= 0.5).unwrap();
+    assert!(half < 50, "{:?}", rows);
+}
+
+/// The number of edges whose parent and child are in
+/// different populations.
+fn cross_deme_edges(migration: f64) -> usize {
+    let mut params = diploid_params();
+    params.nsteps = 500;
+    params.demes = 2;
+    params.migration = migration;
+    let tables = round_trip(&DiploidSimulation::new(params, 19).unwrap().run().unwrap());
+    let nodes = tables.nodes();
+    let edges = tables.edges();
+    (0..edges.num_rows() as tskit::tsk_id_t)
+        .filter(|&e| {
+            nodes.population(edges.parent(e).unwrap()).unwrap()
+                != nodes.population(edges.child(e).unwrap()).unwrap()
+        })
+        .count()
+}
+
+#[test]
+fn isolated_demes_share_no_edges() {
+    assert_eq!(cross_deme_edges(0.0), 0);
+    assert!(cross_deme_edges(0.5) > 0);
+}
+
+#[test]
+fn bulk_whole_genome_edges_match_edges_recorded_one_by_one() {
+    for ploidy in [2, 4] {
+        let mut params = diploid_params();
+        params.xovers = 0.0;
+        params.ploidy = ploidy;
+        // Without recombination, unbuffered runs append
+        // the edges of each step in bulk, while buffered
+        // runs record each one through the crossover code.
+        let bulk = DiploidSimulation::new(params, 20).unwrap().run().unwrap();
+        params.buffer_edges = true;
+        let one_by_one = DiploidSimulation::new(params, 20).unwrap().run().unwrap();
+        assert!(bulk.edges().num_rows() > 0);
+        assert!(round_trip(&bulk).equals(
+            &round_trip(&one_by_one),
+            tskit::TableEqualityOptions::default()
+        ));
+    }
+}
+
+#[test]
+fn canonically_sorted_runs_dump_identical_csv() {
+    let dir = tempfile::tempdir().unwrap();
+    let dump = |name: &str, buffer_edges: bool| {
+        let mut params = diploid_params();
+        params.mutrate = 0.0;
+        params.buffer_edges = buffer_edges;
+        let mut sim = DiploidSimulation::new(params, 21).unwrap();
+        sim.set_canonical_sort(true);
+        let prefix = dir.path().join(name);
+        let prefix = prefix.to_str().unwrap();
+        dump_tables_csv(&sim.run().unwrap(), prefix).unwrap();
+        ["nodes", "edges"].map(|table| std::fs::read(format!("{}.{}.csv", prefix, table)).unwrap())
+    };
+    let first = dump("first", false);
+    assert_eq!(first, dump("second", false));
+    assert_eq!(first, dump("buffered", true));
+}
+
+#[test]
+fn gzipped_tree_file_decompresses_to_the_same_tables() {
+    let tables = DiploidSimulation::new(diploid_params(), 22)
+        .unwrap()
+        .run()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("compressed.trees");
+    let path = path.to_str().unwrap();
+    write_tables(&tables, path, true).unwrap();
+    let original = std::fs::read(path).unwrap();
+
+    let gz_path = gzip_file(path).unwrap();
+    assert_eq!(gz_path, format!("{}.gz", path));
+    assert!(!std::path::Path::new(path).exists());
+
+    let mut decoder = flate2::read::GzDecoder::new(std::fs::File::open(&gz_path).unwrap());
+    let mut decompressed = vec![];
+    std::io::Read::read_to_end(&mut decoder, &mut decompressed).unwrap();
+    assert_eq!(decompressed, original);
+
+    let restored = dir.path().join("restored.trees");
+    std::fs::write(&restored, decompressed).unwrap();
+    let loaded = tskit::TableCollection::new_from_file(restored.to_str().unwrap()).unwrap();
+    assert!(loaded.equals(&round_trip(&tables), tskit::TableEqualityOptions::default()));
+}
+
+#[test]
+fn command_line_options_override_the_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("run.toml");
+    std::fs::write(
+        &config,
+        "seed = 23\n[params]\npopsize = 30\nnsteps = 50\nxovers = 1.0\ngenome_length = 1e4\n",
+    )
+    .unwrap();
+    let treefile = dir.path().join("config.trees");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_diploid_wright_fisher"))
+        .arg("--config")
+        .arg(&config)
+        .args(["-N", "20", "-t"])
+        .arg(&treefile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let tables = tskit::TableCollection::new_from_file(treefile.to_str().unwrap()).unwrap();
+    assert_eq!(tables.nodes().samples_as_vector().len(), 40);
+    assert_eq!(tables.sequence_length(), 1e4);
+    let seed = std::fs::read_to_string(dir.path().join("config.seed")).unwrap();
+    assert_eq!(seed.trim(), "23");
+}
+
+/// The parents of each row of the individual table.
+fn individual_parents(tables: &tskit::TableCollection) -> Vec<Vec<tskit::tsk_id_t>> {
+    let individuals = tables.individuals();
+    (0..individuals.num_rows() as tskit::tsk_id_t)
+        .map(|i| individuals.parents(i).unwrap().unwrap_or_default())
+        .collect()
+}
+
+#[test]
+fn recorded_individuals_read_back_with_two_parents() {
+    let mut params = diploid_params();
+    params.nsteps = 100;
+    params.record_individuals = true;
+    let mut sim = DiploidSimulation::new(params, 24).unwrap();
+    sim.set_filter_individuals(false);
+    let tables = round_trip(&sim.run().unwrap());
+
+    // Sorting may interleave the founders with their offspring, but only
+    // the founders lack parents and parents always precede their children.
+    let parents = individual_parents(&tables);
+    let founders = parents.iter().filter(|p| p.is_empty()).count();
+    assert_eq!(founders, params.popsize as usize);
+    assert!(parents.len() > founders);
+    for (i, p) in parents.iter().enumerate().filter(|(_, p)| !p.is_empty()) {
+        assert_eq!(p.len(), 2, "individual {}", i);
+        assert!(p.iter().all(|&q| q >= 0 && (q as usize) < i));
+    }
+
+    // Each sample node belongs to a recorded individual.
+    let nodes = tables.nodes();
+    for u in nodes.samples_as_vector() {
+        let individual = nodes.individual(u).unwrap();
+        assert_eq!(parents[individual as usize].len(), 2);
+    }
+}
+
+#[test]
+fn simplify_idmap_translates_a_known_node() {
+    // Node 0 is the parent of both nodes of the one alive
+    // diploid, so it is their MRCA.  Node 1 has no
+    // descendants and is removed.
+    let mut tables = tskit::TableCollection::new(100.0).unwrap();
+    tables.add_population().unwrap();
+    for _ in 0..2 {
+        tables.add_node(0, 1.0, 0, tskit::TSK_NULL).unwrap();
+    }
+    for _ in 0..2 {
+        tables.add_node(0, 0.0, 0, tskit::TSK_NULL).unwrap();
+    }
+    tables.add_edge(0.0, 100.0, 0, 2).unwrap();
+    tables.add_edge(0.0, 100.0, 0, 3).unwrap();
+    let mut alive = vec![Individual {
+        nodes: vec![2, 3],
+        individual: tskit::TSK_NULL,
+    }];
+
+    let idmap = simplify(
+        &mut alive,
+        &mut tables,
+        None,
+        tskit::SimplificationOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(idmap, vec![2, tskit::TSK_NULL, 0, 1]);
+    assert_eq!(alive[0].nodes, vec![0, 1]);
+    assert_eq!(tables.nodes().time(idmap[0]).unwrap(), 1.0);
+    assert_eq!(tables.edges().parent(0).unwrap(), idmap[0]);
+}
+
+#[test]
+fn ancient_samples_keep_their_birth_times() {
+    let mut params = diploid_params();
+    params.nsteps = 100;
+    params.sample_every = 25;
+    let mut sim = DiploidSimulation::new(params, 17).unwrap();
+
+    // The times of the nodes born at each sampled step,
+    // before any simplification.
+    let sampled = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let recorded = sampled.clone();
+    sim.set_on_step(Some(Box::new(move |birth_time, tables, alive| {
+        if birth_time > 0 && birth_time % 25 == 0 {
+            let nodes = tables.nodes();
+            for u in alive_sample_nodes(alive) {
+                let time = nodes.time(u).unwrap();
+                if time == birth_time as f64 {
+                    recorded.borrow_mut().push(time);
+                }
+            }
+        }
+    })));
+    let tables = round_trip(&sim.run().unwrap());
+
+    let mut expected = sampled.borrow().clone();
+    assert!(!expected.is_empty());
+    expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let nodes = tables.nodes();
+    let mut times: Vec<f64> = nodes
+        .samples_as_vector()
+        .into_iter()
+        .map(|u| nodes.time(u).unwrap())
+        .filter(|&t| t > 0.0 && t % 25.0 == 0.0)
+        .collect();
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(times, expected);
+    assert_eq!(sim.ancient_samples().len(), expected.len());
+}
+
+/// Always chooses the diploid at index 0 as both parents.
+struct FirstDiploid;
+
+impl ParentSelector for FirstDiploid {
+    fn choose(
+        &mut self,
+        _alive: &[Individual],
+        _offspring_index: usize,
+        _rng: &mut SimRng,
+    ) -> (usize, usize) {
+        (0, 0)
+    }
+}
+
+#[test]
+fn custom_selector_chooses_every_parent() {
+    let mut params = diploid_params();
+    params.popsize = 10;
+    params.psurvival = 0.0;
+    let alive: Vec<Individual> = (0..10)
+        .map(|i| Individual {
+            nodes: vec![2 * i, 2 * i + 1],
+            individual: tskit::TSK_NULL,
+        })
+        .collect();
+    let mut parents = vec![];
+    let mut rng = SimRng::seed_from_u64(5);
+    death_and_parents(&alive, &params, &mut FirstDiploid, &mut parents, &mut rng).unwrap();
+
+    assert_eq!(parents.len(), 10);
+    for (i, p) in parents.iter().enumerate() {
+        assert_eq!(p.index, i);
+        assert_eq!(p.parent_indexes, (0, 0));
+        assert_eq!(p.parent0.nodes, vec![0, 1]);
+        assert_eq!(p.parent1.nodes, vec![0, 1]);
+    }
+}
+
+#[test]
+fn complete_selfing_records_identical_parents() {
+    let mut params = diploid_params();
+    params.nsteps = 50;
+    params.selfing_rate = 1.0;
+    params.record_individuals = true;
+    let mut sim = DiploidSimulation::new(params, 6).unwrap();
+    sim.set_filter_individuals(false);
+    let tables = round_trip(&sim.run().unwrap());
+
+    let parents = individual_parents(&tables);
+    let offspring: Vec<_> = parents.iter().filter(|p| !p.is_empty()).collect();
+    assert!(!offspring.is_empty());
+    for p in offspring {
+        assert_eq!(p.len(), 2);
+        assert_eq!(p[0], p[1]);
+    }
+}
+
+#[test]
+fn skipping_final_simplification_keeps_the_last_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = ["--seed", "12", "--simplify", "150"];
+    let (_, simplified) = run_overlapping_generations(dir.path(), "simplified", &args);
+    let mut unsimplified_args = args.to_vec();
+    unsimplified_args.push("--no-final-simplify");
+    let (_, unsimplified) =
+        run_overlapping_generations(dir.path(), "unsimplified", &unsimplified_args);
+
+    // Each of the last 50 steps adds 100 nodes, and most
+    // are not ancestral to the samples.
+    let simplified_nodes = simplified.nodes().num_rows();
+    let unsimplified_nodes = unsimplified.nodes().num_rows();
+    assert!(
+        unsimplified_nodes > simplified_nodes + 2500,
+        "{} {}",
+        unsimplified_nodes,
+        simplified_nodes
+    );
+    assert!(unsimplified.edges().num_rows() > simplified.edges().num_rows());
+}
+
+#[test]
+fn continuous_birth_times_are_real_valued_and_ordered() {
+    let mut params = diploid_params();
+    params.nsteps = 200;
+    params.continuous_time = true;
+    let tables = round_trip(&DiploidSimulation::new(params, 9).unwrap().run().unwrap());
+
+    let nodes = tables.nodes();
+    let times: Vec<f64> = (0..nodes.num_rows() as tskit::tsk_id_t)
+        .map(|u| nodes.time(u).unwrap())
+        .collect();
+    let integer = times.iter().filter(|t| t.fract() == 0.0).count();
+    assert!(
+        integer * 100 < times.len(),
+        "{} of {}",
+        integer,
+        times.len()
+    );
+
+    let edges = tables.edges();
+    assert!(edges.num_rows() > 0);
+    for e in 0..edges.num_rows() as tskit::tsk_id_t {
+        let parent = times[edges.parent(e).unwrap() as usize];
+        let child = times[edges.child(e).unwrap() as usize];
+        assert!(parent > child, "edge {}: {} {}", e, parent, child);
+    }
+}
+
+#[test]
+fn keeping_unary_nodes_keeps_more_nodes() {
+    let mut params = diploid_params();
+    params.nsteps = 200;
+    let run = |keep_unary| {
+        let mut sim = DiploidSimulation::new(params, 13).unwrap();
+        sim.set_keep_unary(keep_unary);
+        round_trip(&sim.run().unwrap())
+    };
+    let pruned = run(false);
+    let unary = run(true);
+
+    // Most ancestors of the samples only pass on one
+    // lineage, so are removed unless unary nodes are kept.
+    let samples = pruned.nodes().samples_as_vector().len();
+    assert_eq!(unary.nodes().samples_as_vector().len(), samples);
+    assert!(
+        unary.nodes().num_rows() > 2 * pruned.nodes().num_rows(),
+        "{} {}",
+        unary.nodes().num_rows(),
+        pruned.nodes().num_rows()
+    );
+    check_integrity(&unary).unwrap();
+}
+
+/// Tables for `DiploidSimulation::start_from` with three
+/// populations, of which only the first two hold founders.
+fn founders_with_an_empty_population(params: &SimParams) -> tskit::TableCollection {
+    let mut tables = tskit::TableCollection::new(params.genome_length).unwrap();
+    for _ in 0..3 {
+        tables.add_population().unwrap();
+    }
+    for i in 0..params.popsize as tskit::tsk_id_t {
+        for _ in 0..2 {
+            tables
+                .add_node(tskit::TSK_NODE_IS_SAMPLE, 0.0, i % 2, tskit::TSK_NULL)
+                .unwrap();
+        }
+    }
+    tables
+}
+
+#[test]
+fn empty_populations_are_dropped_unless_asked_to_keep_them() {
+    let mut params = diploid_params();
+    params.nsteps = 100;
+    params.demes = 2;
+    let run = |filter| {
+        let tables = founders_with_an_empty_population(&params);
+        let mut sim = DiploidSimulation::start_from(params, 3, tables).unwrap();
+        sim.set_filter_populations(filter);
+        round_trip(&sim.run().unwrap())
+    };
+
+    let filtered = run(true);
+    assert_eq!(filtered.populations().num_rows(), 2);
+    let kept = run(false);
+    assert_eq!(kept.populations().num_rows(), 3);
+
+    for tables in [&filtered, &kept] {
+        let nodes = tables.nodes();
+        let mut per_population = [0; 3];
+        for u in 0..nodes.num_rows() as tskit::tsk_id_t {
+            per_population[nodes.population(u).unwrap() as usize] += 1;
+        }
+        assert!(per_population[0] > 0 && per_population[1] > 0);
+        assert_eq!(per_population[2], 0);
+    }
+}
+
+#[test]
+fn bottleneck_shrinks_the_population_at_its_step() {
+    let mut params = diploid_params();
+    params.nsteps = 100;
+    let mut sim = DiploidSimulation::new(params, 19).unwrap();
+    let bottleneck = SizeChange {
+        step: 60,
+        popsize: 10,
+    };
+    sim.set_demography(Demography::new(vec![bottleneck]).unwrap())
+        .unwrap();
+    for birth_time in (0..params.nsteps).rev() {
+        sim.step(birth_time).unwrap();
+        let expected = if birth_time > 60 { 50 } else { 10 };
+        assert_eq!(sim.alive().len(), expected, "step {}", birth_time);
+        assert_eq!(sim.params().popsize, expected as u32);
+    }
+    check_popsize(sim.alive(), sim.params()).unwrap();
+}
+
+#[test]
+fn discrete_genome_has_integer_coordinates() {
+    let mut params = diploid_params();
+    params.nsteps = 200;
+    params.genome_length = 1000.0;
+    params.discrete_genome = true;
+    params.mutrate = 1e-3;
+    let tables = round_trip(&DiploidSimulation::new(params, 29).unwrap().run().unwrap());
+
+    let edges = tables.edges();
+    assert!(edges.num_rows() > 0);
+    for e in 0..edges.num_rows() as tskit::tsk_id_t {
+        let left = edges.left(e).unwrap();
+        let right = edges.right(e).unwrap();
+        assert_eq!(left.fract(), 0.0, "edge {}", e);
+        assert_eq!(right.fract(), 0.0, "edge {}", e);
+    }
+
+    let sites = tables.sites();
+    assert!(sites.num_rows() > 0);
+    for s in 0..sites.num_rows() as tskit::tsk_id_t {
+        assert_eq!(sites.position(s).unwrap().fract(), 0.0, "site {}", s);
+    }
+
+    // At most one mutation per site on each branch.
+    let mutations = tables.mutations();
+    let mut branches = std::collections::HashSet::new();
+    for m in 0..mutations.num_rows() as tskit::tsk_id_t {
+        let key = (mutations.site(m).unwrap(), mutations.node(m).unwrap());
+        assert!(branches.insert(key), "mutation {}", m);
+    }
+}
+
+#[test]
+fn tetraploid_individuals_have_four_sample_nodes() {
+    let mut params = diploid_params();
+    params.nsteps = 100;
+    params.ploidy = 4;
+    params.record_individuals = true;
+    let mut sim = DiploidSimulation::new(params, 37).unwrap();
+    let tables = round_trip(&sim.run().unwrap());
+
+    assert_eq!(sim.alive().len(), 50);
+    assert!(sim.alive().iter().all(|a| a.nodes.len() == 4));
+
+    let nodes = tables.nodes();
+    let samples = nodes.samples_as_vector();
+    assert_eq!(samples.len(), 200);
+    let mut per_individual = vec![0; tables.individuals().num_rows() as usize];
+    for u in samples {
+        per_individual[nodes.individual(u).unwrap() as usize] += 1;
+    }
+    assert_eq!(per_individual.iter().filter(|&&n| n == 4).count(), 50);
+    assert!(per_individual.iter().all(|&n| n == 0 || n == 4));
+}
+
+#[test]
+fn simulation_metadata_round_trips() {
+    let mut tables = tskit::TableCollection::new(1.0).unwrap();
+    assert_eq!(simulation_metadata(&tables).unwrap(), None);
+    let metadata = SimulationMetadata::new("diploid_wright_fisher", 100, 1000, 42);
+    set_simulation_metadata(&mut tables, &metadata).unwrap();
+    tables.build_index().unwrap();
+    let loaded = round_trip(&tables);
+    assert_eq!(simulation_metadata(&loaded).unwrap(), Some(metadata));
+
+    let dir = tempfile::tempdir().unwrap();
+    let (seed, tables) = run_overlapping_generations(dir.path(), "metadata", &["--seed", "8"]);
+    assert_eq!(
+        simulation_metadata(&tables).unwrap(),
+        Some(SimulationMetadata::new(
+            "overlapping_generations",
+            50,
+            200,
+            seed
+        ))
+    );
+}
+
+#[test]
+fn on_step_is_called_once_per_step_with_decreasing_times() {
+    let mut params = diploid_params();
+    params.nsteps = 120;
+    let mut sim = DiploidSimulation::new(params, 41).unwrap();
+    let steps = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let recorded = steps.clone();
+    sim.set_on_step(Some(Box::new(move |birth_time, _tables, alive| {
+        assert_eq!(alive.len(), 50);
+        recorded.borrow_mut().push(birth_time);
+    })));
+    sim.run().unwrap();
+
+    let steps = steps.borrow();
+    assert_eq!(steps.len() as u64, params.nsteps);
+    assert_eq!(*steps, (0..params.nsteps).rev().collect::<Vec<_>>());
+}
+
+#[test]
+fn wright_fisher_binary_writes_the_site_frequency_spectrum() {
+    let dir = tempfile::tempdir().unwrap();
+    let treefile = dir.path().join("sfs.trees");
+    let sfs = dir.path().join("out").join("wf.sfs");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_diploid_wright_fisher"))
+        .args([
+            "-N", "20", "-n", "100", "-x", "1", "-L", "10000", "-m", "1e-3",
+        ])
+        .args(["-S", "3", "-t"])
+        .arg(&treefile)
+        .arg("--sfs")
+        .arg(&sfs)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let tables = tskit::TableCollection::new_from_file(treefile.to_str().unwrap()).unwrap();
+
+    let counts: Vec<u64> = std::fs::read_to_string(&sfs)
+        .unwrap()
+        .lines()
+        .enumerate()
+        .map(|(k, line)| {
+            let (bin, n) = line.split_once('\t').unwrap();
+            assert_eq!(bin.parse::<usize>().unwrap(), k);
+            n.parse().unwrap()
+        })
+        .collect();
+    // Bins 0 to 40 for the 40 sample nodes.
+    assert_eq!(counts.len(), 41);
+    assert_eq!(counts.iter().sum::<u64>(), tables.sites().num_rows() as u64);
+    assert!(counts[1] > 0);
+}